@@ -8,7 +8,7 @@ use cosmwasm_std::{
 
 use crate::msg::{
     space_pad, ContractStatusLevel, HandleAnswer, HandleMsg, HookMsg, InitMsg, QueryAnswer,
-    QueryMsg, ResponseStatus::Success,
+    QueryMsg, QueryWithPermit, ResponseStatus::Success,
 };
 use crate::rand::sha_256;
 use crate::receiver::Snip20ReceiveMsg;
@@ -20,6 +20,7 @@ use crate::state::{
 use crate::transaction_history::{get_transfers, get_txs, store_burn, store_mint, store_transfer};
 use crate::viewing_key::{ViewingKey, VIEWING_KEY_SIZE};
 use scrt_finance::master_msg::MasterHandleMsg;
+use scrt_finance::permit::{self, Permission, Permit};
 use secret_toolkit::storage::{TypedStore, TypedStoreMut};
 
 /// We make sure that responses from `handle` are padded to a multiple of this size.
@@ -73,6 +74,7 @@ pub fn init<S: Storage, A: Api, Q: Querier>(
         decimals: 6,
         admin: admin.clone(),
         prng_seed: prng_seed_hashed.to_vec(),
+        contract_address: env.contract.address.clone(),
         total_supply_is_public: true,
     })?;
     config.set_total_supply(total_supply);
@@ -131,6 +133,7 @@ pub fn handle<S: Storage, A: Api, Q: Querier>(
         HandleMsg::RegisterReceive { code_hash, .. } => try_register_receive(deps, env, code_hash),
         HandleMsg::CreateViewingKey { entropy, .. } => try_create_key(deps, env, entropy),
         HandleMsg::SetViewingKey { key, .. } => try_set_key(deps, env, key),
+        HandleMsg::RevokePermit { permit_name, .. } => revoke_permit(deps, env, permit_name),
         HandleMsg::IncreaseAllowance {
             spender,
             amount,
@@ -197,10 +200,54 @@ pub fn query<S: Storage, A: Api, Q: Querier>(deps: &Extern<S, A, Q>, msg: QueryM
         QueryMsg::TokenInfo {} => query_token_info(&deps.storage),
         QueryMsg::Minters {} => query_minters(deps),
         QueryMsg::RewardBalance {} => query_reward_balance(deps),
+        QueryMsg::WithPermit { permit, query } => permit_queries(deps, permit, query),
         _ => authenticated_queries(deps, msg),
     }
 }
 
+fn permit_queries<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    permit: Permit,
+    query: QueryWithPermit,
+) -> QueryResult {
+    let contract_address = ReadonlyConfig::from_storage(&deps.storage)
+        .constants()?
+        .contract_address;
+    let account = permit::validate(&deps.storage, &deps.api, &permit, &contract_address)?;
+
+    let required = match query {
+        QueryWithPermit::Allowance { .. } => Permission::Allowance,
+        QueryWithPermit::Balance {} => Permission::Balance,
+        QueryWithPermit::TransferHistory { .. } | QueryWithPermit::TransactionHistory { .. } => {
+            Permission::History
+        }
+    };
+    if !permit.check_permission(&required) {
+        return Err(StdError::generic_err(format!(
+            "This query requires the {:?} permission",
+            required
+        )));
+    }
+
+    match query {
+        QueryWithPermit::Allowance { owner, spender } => {
+            if account != owner && account != spender {
+                return Err(StdError::generic_err(
+                    "Cannot query an allowance that the permit's signer is not a party of",
+                ));
+            }
+            try_check_allowance(deps, owner, spender)
+        }
+        QueryWithPermit::Balance {} => query_balance(&deps, &account),
+        QueryWithPermit::TransferHistory { page, page_size } => {
+            query_transfers(&deps, &account, page.unwrap_or(0), page_size)
+        }
+        QueryWithPermit::TransactionHistory { page, page_size } => {
+            query_transactions(&deps, &account, page.unwrap_or(0), page_size)
+        }
+    }
+}
+
 pub fn authenticated_queries<S: Storage, A: Api, Q: Querier>(
     deps: &Extern<S, A, Q>,
     msg: QueryMsg,
@@ -430,6 +477,20 @@ pub fn try_set_key<S: Storage, A: Api, Q: Querier>(
     })
 }
 
+fn revoke_permit<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    permit_name: String,
+) -> StdResult<HandleResponse> {
+    permit::revoke_permit(&mut deps.storage, &env.message.sender, &permit_name);
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![],
+        data: Some(to_binary(&HandleAnswer::RevokePermit { status: Success })?),
+    })
+}
+
 pub fn try_create_key<S: Storage, A: Api, Q: Querier>(
     deps: &mut Extern<S, A, Q>,
     env: Env,
@@ -1161,9 +1222,174 @@ fn transfer_reward<S: Storage, A: Api, Q: Querier>(
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::msg::ResponseStatus;
-    use crate::msg::{InitConfig, InitialBalance};
+    use crate::msg::InitialBalance;
     use cosmwasm_std::testing::*;
-    use cosmwasm_std::{from_binary, BlockInfo, ContractInfo, MessageInfo, QueryResponse, WasmMsg};
-    use std::any::Any;
+    use scrt_finance::permit::{PermitParams, PermitSignature, PubKey};
+
+    // A secp256k1 key whose account address happens to be valid UTF-8, which `MockApi` needs to
+    // make it human readable. The permits below were signed with it for `MOCK_CONTRACT_ADDR`
+    const PERMIT_PUB_KEY: &str = "AxD6uuGM4ZbalLHzM768XR03mWZTx9mF2LpdRXYpxAgv";
+    const BALANCE_PERMIT_SIGNATURE: &str =
+        "GkvUM1Mi1IltNzRhMROFm3LODg7Q+toX9ujBn8w3ZlJDtzEc0RPHanmtIG6nsNBJjeWiHALES/f6ZHUsEStwrQ==";
+    const HISTORY_PERMIT_SIGNATURE: &str =
+        "aP3/amOjbgTRHO5htFfLO1/Er4LajfyF6RHwtNzdxZ158vbwf1Q6p7YcmRVT87jZ7tVvjXk4t14C+f4mnOOkVQ==";
+
+    // Helper functions
+
+    fn init_helper(
+        initial_balances: Vec<InitialBalance>,
+    ) -> (
+        StdResult<InitResponse>,
+        Extern<MockStorage, MockApi, MockQuerier>,
+    ) {
+        let mut deps = mock_dependencies(20, &[]);
+        let env = mock_env("instantiator", &[]);
+
+        let init_msg = InitMsg {
+            initial_balances: Some(initial_balances),
+            prng_seed: Binary::from("lolz fun yay".as_bytes()),
+            master: SecretContract {
+                address: HumanAddr("master".to_string()),
+                hash: "1".to_string(),
+            },
+            sefi: SecretContract {
+                address: HumanAddr("sefi".to_string()),
+                hash: "2".to_string(),
+            },
+        };
+
+        (init(&mut deps, env, init_msg), deps)
+    }
+
+    /// The account that signed the test permits
+    fn permit_account() -> HumanAddr {
+        let pub_key = Binary::from_base64(PERMIT_PUB_KEY).unwrap();
+        MockApi::new(20)
+            .human_address(&permit::pubkey_to_account(pub_key.as_slice()))
+            .unwrap()
+    }
+
+    fn permit_helper(name: &str, permission: Permission, signature: &str) -> Permit {
+        Permit {
+            params: PermitParams {
+                allowed_tokens: vec![HumanAddr(MOCK_CONTRACT_ADDR.to_string())],
+                permit_name: name.to_string(),
+                chain_id: "secret-2".to_string(),
+                permissions: vec![permission],
+            },
+            signature: PermitSignature {
+                pub_key: PubKey {
+                    r#type: "tendermint/PubKeySecp256k1".to_string(),
+                    value: Binary::from_base64(PERMIT_PUB_KEY).unwrap(),
+                },
+                signature: Binary::from_base64(signature).unwrap(),
+            },
+        }
+    }
+
+    fn extract_error_msg<T>(error: StdResult<T>) -> String {
+        match error {
+            Ok(_) => panic!("An error was expected"),
+            Err(StdError::GenericErr { msg, .. }) => msg,
+            Err(err) => panic!("Unexpected error: {:?}", err),
+        }
+    }
+
+    // Query tests
+
+    #[test]
+    fn test_query_balance_with_permit() {
+        let (init_result, deps) = init_helper(vec![InitialBalance {
+            address: permit_account(),
+            amount: Uint128(5000),
+        }]);
+        assert!(
+            init_result.is_ok(),
+            "Init failed: {}",
+            init_result.err().unwrap()
+        );
+
+        let query_msg = QueryMsg::WithPermit {
+            permit: permit_helper("wallet", Permission::Balance, BALANCE_PERMIT_SIGNATURE),
+            query: QueryWithPermit::Balance {},
+        };
+        let query_result = query(&deps, query_msg).unwrap();
+        match from_binary(&query_result).unwrap() {
+            QueryAnswer::Balance { amount } => assert_eq!(amount, Uint128(5000)),
+            _ => panic!("Unexpected query answer"),
+        }
+
+        // Granting more than what was signed invalidates the signature
+        let query_msg = QueryMsg::WithPermit {
+            permit: permit_helper("wallet", Permission::Owner, BALANCE_PERMIT_SIGNATURE),
+            query: QueryWithPermit::Balance {},
+        };
+        assert_eq!(
+            query(&deps, query_msg).unwrap_err(),
+            StdError::unauthorized()
+        );
+    }
+
+    #[test]
+    fn test_query_with_permit_without_permission() {
+        let (init_result, deps) = init_helper(vec![InitialBalance {
+            address: permit_account(),
+            amount: Uint128(5000),
+        }]);
+        assert!(
+            init_result.is_ok(),
+            "Init failed: {}",
+            init_result.err().unwrap()
+        );
+
+        let query_msg = QueryMsg::WithPermit {
+            permit: permit_helper("history", Permission::History, HISTORY_PERMIT_SIGNATURE),
+            query: QueryWithPermit::Balance {},
+        };
+        assert_eq!(
+            extract_error_msg(query(&deps, query_msg)),
+            "This query requires the Balance permission"
+        );
+    }
+
+    #[test]
+    fn test_query_with_revoked_permit() {
+        let (init_result, mut deps) = init_helper(vec![InitialBalance {
+            address: permit_account(),
+            amount: Uint128(5000),
+        }]);
+        assert!(
+            init_result.is_ok(),
+            "Init failed: {}",
+            init_result.err().unwrap()
+        );
+
+        let handle_msg = HandleMsg::RevokePermit {
+            permit_name: "wallet".to_string(),
+            padding: None,
+        };
+        handle(&mut deps, mock_env(permit_account(), &[]), handle_msg).unwrap();
+
+        let query_msg = QueryMsg::WithPermit {
+            permit: permit_helper("wallet", Permission::Balance, BALANCE_PERMIT_SIGNATURE),
+            query: QueryWithPermit::Balance {},
+        };
+        assert_eq!(
+            extract_error_msg(query(&deps, query_msg)),
+            format!(
+                "permit \"wallet\" was revoked by account {}",
+                permit_account()
+            )
+        );
+
+        // Only the revoked permit stops working
+        let query_msg = QueryMsg::WithPermit {
+            permit: permit_helper("history", Permission::History, HISTORY_PERMIT_SIGNATURE),
+            query: QueryWithPermit::TransferHistory {
+                page: None,
+                page_size: 10,
+            },
+        };
+        assert!(query(&deps, query_msg).is_ok());
+    }
 }
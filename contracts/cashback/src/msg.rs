@@ -6,6 +6,7 @@ use cosmwasm_std::{Binary, HumanAddr, StdError, StdResult, Uint128};
 use crate::state::SecretContract;
 use crate::transaction_history::{RichTx, Tx};
 use crate::viewing_key::ViewingKey;
+use scrt_finance::permit::Permit;
 
 #[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema)]
 pub struct InitialBalance {
@@ -72,6 +73,10 @@ pub enum HandleMsg {
         key: String,
         padding: Option<String>,
     },
+    RevokePermit {
+        permit_name: String,
+        padding: Option<String>,
+    },
 
     // Allowance
     IncreaseAllowance {
@@ -185,6 +190,9 @@ pub enum HandleAnswer {
     SetViewingKey {
         status: ResponseStatus,
     },
+    RevokePermit {
+        status: ResponseStatus,
+    },
 
     // Allowance
     IncreaseAllowance {
@@ -263,6 +271,29 @@ pub enum QueryMsg {
     },
     Minters {},
     RewardBalance {},
+    WithPermit {
+        permit: Permit,
+        query: QueryWithPermit,
+    },
+}
+
+/// The authenticated queries, on behalf of whoever signed the permit
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum QueryWithPermit {
+    Allowance {
+        owner: HumanAddr,
+        spender: HumanAddr,
+    },
+    Balance {},
+    TransferHistory {
+        page: Option<u32>,
+        page_size: u32,
+    },
+    TransactionHistory {
+        page: Option<u32>,
+        page_size: u32,
+    },
 }
 
 impl QueryMsg {
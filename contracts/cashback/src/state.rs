@@ -43,6 +43,8 @@ pub struct Constants {
     pub prng_seed: Vec<u8>,
     // privacy configuration
     pub total_supply_is_public: bool,
+    // the address of this contract, used to validate query permits
+    pub contract_address: HumanAddr,
 }
 
 #[derive(Serialize, Debug, Deserialize, Clone, PartialEq, JsonSchema, Default)]
@@ -8,7 +8,7 @@ use cosmwasm_std::{
 
 use crate::msg::{
     space_pad, ContractStatusLevel, HandleAnswer, HandleMsg, InitMsg, QueryAnswer, QueryMsg,
-    ResponseStatus::Success,
+    QueryWithPermit, ResponseStatus::Success,
 };
 use crate::rand::sha_256;
 use crate::receiver::Snip20ReceiveMsg;
@@ -18,6 +18,7 @@ use crate::state::{
     ReadonlyBalances, ReadonlyConfig,
 };
 use crate::viewing_key::{ViewingKey, VIEWING_KEY_SIZE};
+use scrt_finance::permit::{self, Permission, Permit};
 
 /// We make sure that responses from `handle` are padded to a multiple of this size.
 pub const RESPONSE_BLOCK_SIZE: usize = 256;
@@ -72,6 +73,7 @@ pub fn init<S: Storage, A: Api, Q: Querier>(
         decimals: msg.decimals,
         admin: admin.clone(),
         prng_seed: prng_seed_hashed.to_vec(),
+        contract_address: env.contract.address.clone(),
         total_supply_is_public: init_config.public_total_supply(),
     })?;
     config.set_total_supply(total_supply);
@@ -126,6 +128,7 @@ pub fn handle<S: Storage, A: Api, Q: Querier>(
         HandleMsg::RegisterReceive { code_hash, .. } => try_register_receive(deps, env, code_hash),
         HandleMsg::CreateViewingKey { entropy, .. } => try_create_key(deps, env, entropy),
         HandleMsg::SetViewingKey { key, .. } => try_set_key(deps, env, key),
+        HandleMsg::RevokePermit { permit_name, .. } => revoke_permit(deps, env, permit_name),
 
         // Allowance
         HandleMsg::IncreaseAllowance {
@@ -176,10 +179,49 @@ pub fn query<S: Storage, A: Api, Q: Querier>(deps: &Extern<S, A, Q>, msg: QueryM
         QueryMsg::TokenInfo {} => query_token_info(&deps.storage),
         QueryMsg::ExchangeRate {} => query_exchange_rate(),
         QueryMsg::Minters { .. } => query_minters(deps),
+        QueryMsg::WithPermit { permit, query } => permit_queries(deps, permit, query),
         _ => authenticated_queries(deps, msg),
     }
 }
 
+fn permit_queries<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    permit: Permit,
+    query: QueryWithPermit,
+) -> QueryResult {
+    let contract_address = ReadonlyConfig::from_storage(&deps.storage)
+        .constants()?
+        .contract_address;
+    let account = permit::validate(&deps.storage, &deps.api, &permit, &contract_address)?;
+
+    let required = match query {
+        QueryWithPermit::Allowance { .. } => Permission::Allowance,
+        QueryWithPermit::Balance {} => Permission::Balance,
+        QueryWithPermit::TransferHistory { .. } => Permission::History,
+    };
+    if !permit.check_permission(&required) {
+        return Err(StdError::generic_err(format!(
+            "This query requires the {:?} permission",
+            required
+        )));
+    }
+
+    match query {
+        QueryWithPermit::Allowance { owner, spender } => {
+            if account != owner && account != spender {
+                return Err(StdError::generic_err(
+                    "Cannot query an allowance that the permit's signer is not a party of",
+                ));
+            }
+            try_check_allowance(deps, owner, spender)
+        }
+        QueryWithPermit::Balance {} => query_balance(&deps, &account),
+        QueryWithPermit::TransferHistory { page, page_size } => {
+            query_transactions(&deps, &account, page.unwrap_or(0), page_size)
+        }
+    }
+}
+
 pub fn authenticated_queries<S: Storage, A: Api, Q: Querier>(
     deps: &Extern<S, A, Q>,
     msg: QueryMsg,
@@ -369,6 +411,20 @@ pub fn try_set_key<S: Storage, A: Api, Q: Querier>(
     })
 }
 
+fn revoke_permit<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    permit_name: String,
+) -> StdResult<HandleResponse> {
+    permit::revoke_permit(&mut deps.storage, &env.message.sender, &permit_name);
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![],
+        data: Some(to_binary(&HandleAnswer::RevokePermit { status: Success })?),
+    })
+}
+
 pub fn try_create_key<S: Storage, A: Api, Q: Querier>(
     deps: &mut Extern<S, A, Q>,
     env: Env,
@@ -1087,6 +1143,10 @@ mod tests {
             sha_256("lolz fun yay".to_owned().as_bytes())
         );
         assert_eq!(constants.total_supply_is_public, false);
+        assert_eq!(
+            constants.contract_address,
+            HumanAddr(MOCK_CONTRACT_ADDR.to_string())
+        );
     }
 
     #[test]
@@ -1317,6 +1377,35 @@ mod tests {
         assert!(actual_vk.check_viewing_key(&saved_vk));
     }
 
+    #[test]
+    fn test_handle_revoke_permit() {
+        let (init_result, mut deps) = init_helper(vec![InitialBalance {
+            address: HumanAddr("bob".to_string()),
+            amount: Uint128(5000),
+        }]);
+        assert!(
+            init_result.is_ok(),
+            "Init failed: {}",
+            init_result.err().unwrap()
+        );
+
+        let handle_msg = HandleMsg::RevokePermit {
+            permit_name: "wallet".to_string(),
+            padding: None,
+        };
+        let handle_result = handle(&mut deps, mock_env("bob", &[]), handle_msg);
+        let unwrapped_result: HandleAnswer =
+            from_binary(&handle_result.unwrap().data.unwrap()).unwrap();
+        assert_eq!(
+            to_binary(&unwrapped_result).unwrap(),
+            to_binary(&HandleAnswer::RevokePermit { status: Success }).unwrap(),
+        );
+
+        let bob = HumanAddr("bob".to_string());
+        assert!(permit::is_permit_revoked(&deps.storage, &bob, "wallet"));
+        assert!(!permit::is_permit_revoked(&deps.storage, &bob, "other"));
+    }
+
     #[test]
     fn test_handle_transfer_from() {
         let (init_result, mut deps) = init_helper(vec![InitialBalance {
@@ -2332,6 +2421,42 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_query_with_permit_for_another_contract() {
+        let (init_result, deps) = init_helper(vec![InitialBalance {
+            address: HumanAddr("bob".to_string()),
+            amount: Uint128(5000),
+        }]);
+        assert!(
+            init_result.is_ok(),
+            "Init failed: {}",
+            init_result.err().unwrap()
+        );
+
+        let permit = Permit {
+            params: permit::PermitParams {
+                allowed_tokens: vec![HumanAddr("some-other-token".to_string())],
+                permit_name: "wallet".to_string(),
+                chain_id: "secret-2".to_string(),
+                permissions: vec![Permission::Balance],
+            },
+            signature: permit::PermitSignature {
+                pub_key: permit::PubKey {
+                    r#type: "tendermint/PubKeySecp256k1".to_string(),
+                    value: Binary(vec![2u8; 33]),
+                },
+                signature: Binary(vec![0u8; 64]),
+            },
+        };
+        let query_msg = QueryMsg::WithPermit {
+            permit,
+            query: QueryWithPermit::Balance {},
+        };
+        let query_result = query(&deps, query_msg);
+        let error = extract_error_msg(query_result);
+        assert!(error.contains("permit doesn't apply to contract"));
+    }
+
     #[test]
     fn test_query_token_info() {
         let init_name = "sec-sec".to_string();
@@ -5,6 +5,7 @@ use cosmwasm_std::{Binary, HumanAddr, StdError, StdResult, Uint128};
 
 use crate::state::Tx;
 use crate::viewing_key::ViewingKey;
+use scrt_finance::permit::Permit;
 
 #[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema)]
 pub struct InitialBalance {
@@ -77,6 +78,10 @@ pub enum HandleMsg {
         key: String,
         padding: Option<String>,
     },
+    RevokePermit {
+        permit_name: String,
+        padding: Option<String>,
+    },
 
     // Allowance
     IncreaseAllowance {
@@ -170,6 +175,9 @@ pub enum HandleAnswer {
     SetViewingKey {
         status: ResponseStatus,
     },
+    RevokePermit {
+        status: ResponseStatus,
+    },
 
     // Allowance
     IncreaseAllowance {
@@ -236,6 +244,25 @@ pub enum QueryMsg {
         page_size: u32,
     },
     Minters {},
+    WithPermit {
+        permit: Permit,
+        query: QueryWithPermit,
+    },
+}
+
+/// The authenticated queries, on behalf of whoever signed the permit
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum QueryWithPermit {
+    Allowance {
+        owner: HumanAddr,
+        spender: HumanAddr,
+    },
+    Balance {},
+    TransferHistory {
+        page: Option<u32>,
+        page_size: u32,
+    },
 }
 
 impl QueryMsg {
@@ -2,9 +2,43 @@ pub const CONFIG_KEY: &[u8] = b"config";
 pub const REWARD_POOL_KEY: &[u8] = b"rewardpool";
 pub const TOKEN_INFO_KEY: &[u8] = b"tokeninfo";
 pub const VIEWING_KEY_KEY: &[u8] = b"viewingkey";
+pub const EXTRA_REWARDS_KEY: &[u8] = b"extrarewards";
+pub const EXTRA_DEBTS_KEY: &[u8] = b"extradebts";
+pub const SUBSCRIBERS_KEY: &[u8] = b"subscribers";
+pub const POLL_FACTORY_KEY: &[u8] = b"pollfactory";
+pub const DELEGATIONS_KEY: &[u8] = b"delegations";
+pub const DELEGATED_POWER_KEY: &[u8] = b"delegatedpower";
+pub const BALANCE_CHECKPOINTS_KEY: &[u8] = b"balancecheckpoints";
+pub const TOTAL_LOCKED_CHECKPOINTS_KEY: &[u8] = b"totallockedcheckpoints";
+pub const DELEGATE_CHECKPOINTS_KEY: &[u8] = b"delegatecheckpoints";
+pub const DELEGATED_POWER_CHECKPOINTS_KEY: &[u8] = b"delegatedpowercheckpoints";
+pub const LOCKS_KEY: &[u8] = b"locks";
+pub const LOCK_TIERS_KEY: &[u8] = b"locktiers";
+pub const STAKERS_COUNT_KEY: &[u8] = b"stakerscount";
+pub const TRUSTED_CONTRACTS_KEY: &[u8] = b"trustedcontracts";
+pub const DEADLINE_KEY: &[u8] = b"deadline";
+pub const LAST_ALLOCATION_BLOCK_KEY: &[u8] = b"lastallocationblock";
+pub const MIGRATION_TARGET_KEY: &[u8] = b"migrationtarget";
+pub const REDEEM_FEE_KEY: &[u8] = b"redeemfee";
+pub const FEE_EXEMPT_KEY: &[u8] = b"feeexempt";
+pub const LAST_DEPOSITS_KEY: &[u8] = b"lastdeposits";
+pub const REFERRAL_RATE_KEY: &[u8] = b"referralrate";
+pub const REFERRERS_KEY: &[u8] = b"referrers";
+pub const REFERRAL_REWARDS_KEY: &[u8] = b"referralrewards";
+pub const VESTING_KEY: &[u8] = b"vesting";
+pub const VESTINGS_KEY: &[u8] = b"vestings";
 
 pub const RESPONSE_BLOCK_SIZE: usize = 256;
+pub const NO_BOOST: u64 = 100; // Lock boosts are in percent
+pub const BLOCKS_PER_YEAR: u64 = 5_256_000; // Assuming 6 second blocks
+pub const SUNSET_GRACE_PERIOD: u64 = 100_800; // About a week, for stakers to claim after the deadline
+pub const MAX_REDEEM_FEE_BPS: u64 = 1_000; // 10%
+pub const MAX_REFERRAL_RATE_BPS: u64 = 2_000; // 20%
+pub const MAX_VESTING_PENALTY_BPS: u64 = 5_000; // 50%
+                                                // Every subscriber is notified on each balance change, so there can't be too many of them
+pub const MAX_SUBSCRIBERS: usize = 32;
 
-// TODO: get those as an input for specific coins, as some coins might require different scales than others
-// pub const INC_TOKEN_SCALE: u128 = 1; // LP Tokens and Gov Token is at the same scale
-pub const REWARD_SCALE: u128 = 1_000_000_000_000; // 10 ^ 12
+// Used when the init message doesn't set a scale, unless the incentivized token's decimals call for
+// a bigger one
+pub const DEFAULT_REWARD_SCALE: u128 = 1_000_000_000_000; // 10 ^ 12
+pub const MAX_REWARD_SCALE: u128 = 1_000_000_000_000_000_000_000_000; // 10 ^ 24
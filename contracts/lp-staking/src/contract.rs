@@ -10,15 +10,19 @@ use secret_toolkit::storage::{TypedStore, TypedStoreMut};
 use secret_toolkit::utils::{pad_handle_result, pad_query_result};
 
 use crate::constants::*;
-use crate::querier::query_pending;
-use crate::state::Config;
+use crate::querier::{query_pending, query_yearly_rewards};
+use crate::state::{checkpoint_at, push_checkpoint, Config, ExtraReward, Lock, Vesting};
 use scrt_finance::lp_staking_msg::LPStakingResponseStatus::Success;
 use scrt_finance::lp_staking_msg::{
     LPStakingHandleAnswer, LPStakingHandleMsg, LPStakingHookMsg, LPStakingInitMsg,
-    LPStakingQueryAnswer, LPStakingQueryMsg, LPStakingReceiveAnswer, LPStakingReceiveMsg,
+    LPStakingQueryAnswer, LPStakingQueryMsg, LPStakingQueryWithPermit, LPStakingReceiveAnswer,
+    LPStakingReceiveMsg, LockTier, RedeemFee, RewardAmount, VestingConfig,
 };
 use scrt_finance::master_msg::MasterHandleMsg;
-use scrt_finance::types::{RewardPool, TokenInfo, UserInfo};
+use scrt_finance::math::{self, Rounding};
+use scrt_finance::permit::{self, Permission, Permit};
+use scrt_finance::secret_poll_msg::PollHandleMsg;
+use scrt_finance::types::{RewardPool, SecretContract, TokenInfo, UserInfo};
 use scrt_finance::viewing_key::{ViewingKey, VIEWING_KEY_SIZE};
 
 pub fn init<S: Storage, A: Api, Q: Querier>(
@@ -27,6 +31,19 @@ pub fn init<S: Storage, A: Api, Q: Querier>(
     msg: LPStakingInitMsg,
 ) -> StdResult<InitResponse> {
     // Initialize state
+    let decimals = snip20::token_info_query(
+        &deps.querier,
+        RESPONSE_BLOCK_SIZE,
+        msg.inc_token.contract_hash.clone(),
+        msg.inc_token.address.clone(),
+    )?
+    .decimals;
+    let reward_scale = match msg.reward_scale {
+        Some(scale) => scale.u128(),
+        None => DEFAULT_REWARD_SCALE.max(min_reward_scale(decimals)?),
+    };
+    validate_reward_scale(reward_scale, decimals)?;
+
     let prng_seed_hashed = sha_256(&msg.prng_seed.0);
     let mut config_store = TypedStoreMut::attach(&mut deps.storage);
     config_store.store(
@@ -40,6 +57,7 @@ pub fn init<S: Storage, A: Api, Q: Querier>(
             prng_seed: prng_seed_hashed.to_vec(),
             is_stopped: false,
             own_addr: env.contract.address,
+            reward_scale,
         },
     )?;
 
@@ -48,6 +66,7 @@ pub fn init<S: Storage, A: Api, Q: Querier>(
         &RewardPool {
             residue: 0,
             inc_token_supply: 0,
+            boosted_supply: 0,
             acc_reward_per_share: 0,
         },
     )?;
@@ -55,6 +74,27 @@ pub fn init<S: Storage, A: Api, Q: Querier>(
     TypedStoreMut::<TokenInfo, S>::attach(&mut deps.storage)
         .store(TOKEN_INFO_KEY, &msg.token_info)?;
 
+    TypedStoreMut::<Vec<SecretContract>, S>::attach(&mut deps.storage)
+        .store(SUBSCRIBERS_KEY, &vec![])?;
+    TypedStoreMut::<Option<HumanAddr>, S>::attach(&mut deps.storage)
+        .store(POLL_FACTORY_KEY, &None)?;
+    TypedStoreMut::<Vec<ExtraReward>, S>::attach(&mut deps.storage)
+        .store(EXTRA_REWARDS_KEY, &vec![])?;
+    TypedStoreMut::<u32, S>::attach(&mut deps.storage).store(STAKERS_COUNT_KEY, &0)?;
+    TypedStoreMut::<Vec<LockTier>, S>::attach(&mut deps.storage).store(LOCK_TIERS_KEY, &vec![])?;
+    TypedStoreMut::<Vec<HumanAddr>, S>::attach(&mut deps.storage)
+        .store(TRUSTED_CONTRACTS_KEY, &vec![])?;
+    TypedStoreMut::<Option<u64>, S>::attach(&mut deps.storage).store(DEADLINE_KEY, &None)?;
+    TypedStoreMut::<Option<SecretContract>, S>::attach(&mut deps.storage)
+        .store(MIGRATION_TARGET_KEY, &None)?;
+    TypedStoreMut::<Option<RedeemFee>, S>::attach(&mut deps.storage)
+        .store(REDEEM_FEE_KEY, &None)?;
+    TypedStoreMut::<Vec<HumanAddr>, S>::attach(&mut deps.storage).store(FEE_EXEMPT_KEY, &vec![])?;
+    TypedStoreMut::<u64, S>::attach(&mut deps.storage).store(REFERRAL_RATE_KEY, &0)?;
+    TypedStoreMut::<Option<VestingConfig>, S>::attach(&mut deps.storage)
+        .store(VESTING_KEY, &None)?;
+    TypedStoreMut::attach(&mut deps.storage).store(LAST_ALLOCATION_BLOCK_KEY, &env.block.height)?;
+
     // Register sSCRT and incentivized token, set vks
     let messages = vec![
         snip20::register_receive_msg(
@@ -110,7 +150,12 @@ pub fn handle<S: Storage, A: Api, Q: Querier>(
     }
 
     let response = match msg {
-        LPStakingHandleMsg::Redeem { amount } => redeem(deps, env, amount),
+        LPStakingHandleMsg::Redeem { amount, recipient } => redeem(deps, env, amount, recipient),
+        LPStakingHandleMsg::Claim { recipient } => claim(deps, env, recipient),
+        LPStakingHandleMsg::TransferStake { recipient, amount }
+        | LPStakingHandleMsg::Transfer {
+            recipient, amount, ..
+        } => transfer_stake(deps, env, recipient, amount.u128()),
         LPStakingHandleMsg::Receive {
             from, amount, msg, ..
         } => receive(deps, env, from, amount.u128(), msg),
@@ -118,8 +163,48 @@ pub fn handle<S: Storage, A: Api, Q: Querier>(
             create_viewing_key(deps, env, entropy)
         }
         LPStakingHandleMsg::SetViewingKey { key, .. } => set_viewing_key(deps, env, key),
+        LPStakingHandleMsg::RevokePermit { permit_name, .. } => {
+            revoke_permit(deps, env, permit_name)
+        }
+        LPStakingHandleMsg::Delegate { delegate } => delegate_votes(deps, env, delegate),
+        LPStakingHandleMsg::ExpireLock { address } => expire_lock(deps, env, address),
+        LPStakingHandleMsg::MigrateTo { new_pool } => migrate_to(deps, env, new_pool),
+        LPStakingHandleMsg::ClaimReferralRewards { recipient } => {
+            claim_referral_rewards(deps, env, recipient)
+        }
+        LPStakingHandleMsg::ClaimVested {
+            recipient,
+            include_locked,
+        } => claim_vested(deps, env, recipient, include_locked.unwrap_or(false)),
         LPStakingHandleMsg::StopContract {} => stop_contract(deps, env),
         LPStakingHandleMsg::ChangeAdmin { address } => change_admin(deps, env, address),
+        LPStakingHandleMsg::SetPollFactory { address } => set_poll_factory(deps, env, address),
+        LPStakingHandleMsg::AddRewardToken { token } => add_reward_token(deps, env, token),
+        LPStakingHandleMsg::SetDeadline { block } => set_deadline(deps, env, block),
+        LPStakingHandleMsg::ClaimRewardPool { recipient } => {
+            claim_reward_pool(deps, env, recipient)
+        }
+        LPStakingHandleMsg::SetMigrationTarget { new_pool } => {
+            set_migration_target(deps, env, new_pool)
+        }
+        LPStakingHandleMsg::SetRedeemFee { fee } => set_redeem_fee(deps, env, fee),
+        LPStakingHandleMsg::AddFeeExempt { contracts } => add_fee_exempt(deps, env, contracts),
+        LPStakingHandleMsg::RemoveFeeExempt { contracts } => {
+            remove_fee_exempt(deps, env, contracts)
+        }
+        LPStakingHandleMsg::SetReferralRate { rate_bps } => set_referral_rate(deps, env, rate_bps),
+        LPStakingHandleMsg::SetVesting { vesting } => set_vesting(deps, env, vesting),
+        LPStakingHandleMsg::SetLockTiers { tiers } => set_lock_tiers(deps, env, tiers),
+        LPStakingHandleMsg::AddTrustedContracts { contracts } => {
+            add_trusted_contracts(deps, env, contracts)
+        }
+        LPStakingHandleMsg::RemoveTrustedContracts { contracts } => {
+            remove_trusted_contracts(deps, env, contracts)
+        }
+        LPStakingHandleMsg::AddSubscribers { contracts } => add_subscribers(deps, env, contracts),
+        LPStakingHandleMsg::RemoveSubscribers { contracts } => {
+            remove_subscribers(deps, env, contracts)
+        }
         LPStakingHandleMsg::NotifyAllocation { amount, hook } => notify_allocation(
             deps,
             env,
@@ -139,8 +224,22 @@ pub fn query<S: Storage, A: Api, Q: Querier>(
     let response = match msg {
         LPStakingQueryMsg::ContractStatus {} => query_contract_status(deps),
         LPStakingQueryMsg::RewardToken {} => query_reward_token(deps),
+        LPStakingQueryMsg::RewardTokens {} => query_reward_tokens(deps),
         LPStakingQueryMsg::IncentivizedToken {} => query_incentivized_token(deps),
         LPStakingQueryMsg::TokenInfo {} => query_token_info(deps),
+        LPStakingQueryMsg::TotalLocked {} => query_total_locked(deps),
+        LPStakingQueryMsg::TotalLockedAt { height } => query_total_locked_at(deps, height),
+        LPStakingQueryMsg::Subscribers {} => query_subscribers(deps),
+        LPStakingQueryMsg::LockTiers {} => query_lock_tiers(deps),
+        LPStakingQueryMsg::PoolStats { height } => query_pool_stats(deps, height),
+        LPStakingQueryMsg::TrustedContracts {} => query_trusted_contracts(deps),
+        LPStakingQueryMsg::Deadline {} => query_deadline(deps),
+        LPStakingQueryMsg::MigrationTarget {} => query_migration_target(deps),
+        LPStakingQueryMsg::RedeemFee {} => query_redeem_fee(deps),
+        LPStakingQueryMsg::FeeExempt {} => query_fee_exempt(deps),
+        LPStakingQueryMsg::ReferralRate {} => query_referral_rate(deps),
+        LPStakingQueryMsg::VestingConfig {} => query_vesting_config(deps),
+        LPStakingQueryMsg::WithPermit { permit, query } => permit_queries(deps, permit, query),
         _ => authenticated_queries(deps, msg),
     };
 
@@ -166,6 +265,31 @@ pub fn authenticated_queries<S: Storage, A: Api, Q: Querier>(
                 address, height, ..
             } => query_pending_rewards(deps, &address, height),
             LPStakingQueryMsg::Balance { address, .. } => query_deposit(deps, &address),
+            LPStakingQueryMsg::BalanceAt {
+                address, height, ..
+            } => query_deposit_at(deps, &address, height),
+            LPStakingQueryMsg::Delegation {
+                address, height, ..
+            } => query_delegation(deps, &address, height),
+            LPStakingQueryMsg::Lock { address, .. } => query_lock(deps, &address),
+            LPStakingQueryMsg::ReferralRewards { address, .. } => {
+                query_referral_rewards(deps, &address)
+            }
+            LPStakingQueryMsg::Vesting {
+                address, height, ..
+            } => query_vesting(deps, &address, height),
+            LPStakingQueryMsg::StakerBalance {
+                staker,
+                height,
+                contract,
+                ..
+            } => query_staker_balance(deps, &contract, &staker, height),
+            LPStakingQueryMsg::StakerDelegation {
+                staker,
+                height,
+                contract,
+                ..
+            } => query_staker_delegation(deps, &contract, &staker, height),
             _ => panic!("This should never happen"),
         };
     }
@@ -175,6 +299,38 @@ pub fn authenticated_queries<S: Storage, A: Api, Q: Querier>(
     })?)
 }
 
+pub fn permit_queries<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    permit: Permit,
+    query: LPStakingQueryWithPermit,
+) -> StdResult<Binary> {
+    let config: Config = TypedStore::attach(&deps.storage).load(CONFIG_KEY)?;
+    let address = permit::validate(&deps.storage, &deps.api, &permit, &config.own_addr)?;
+
+    let required = match query {
+        LPStakingQueryWithPermit::Delegation { .. } => Permission::Owner,
+        _ => Permission::Balance,
+    };
+    if !permit.check_permission(&required) {
+        return Err(StdError::generic_err(format!(
+            "this query requires the {:?} permission",
+            required
+        )));
+    }
+
+    match query {
+        LPStakingQueryWithPermit::Rewards { height } => {
+            query_pending_rewards(deps, &address, height)
+        }
+        LPStakingQueryWithPermit::Balance {} => query_deposit(deps, &address),
+        LPStakingQueryWithPermit::BalanceAt { height } => query_deposit_at(deps, &address, height),
+        LPStakingQueryWithPermit::Delegation { height } => query_delegation(deps, &address, height),
+        LPStakingQueryWithPermit::Lock {} => query_lock(deps, &address),
+        LPStakingQueryWithPermit::ReferralRewards {} => query_referral_rewards(deps, &address),
+        LPStakingQueryWithPermit::Vesting { height } => query_vesting(deps, &address, height),
+    }
+}
+
 // Handle functions
 
 fn receive<S: Storage, A: Api, Q: Querier>(
@@ -187,7 +343,14 @@ fn receive<S: Storage, A: Api, Q: Querier>(
     let msg: LPStakingReceiveMsg = from_binary(&msg)?;
 
     match msg {
-        LPStakingReceiveMsg::Deposit {} => deposit(deps, env, from, amount),
+        LPStakingReceiveMsg::Deposit {
+            lock_period,
+            beneficiary,
+            referrer,
+        } => deposit(deps, env, from, amount, lock_period, beneficiary, referrer),
+        LPStakingReceiveMsg::DepositRewards { end_block } => {
+            deposit_rewards(deps, env, from, amount, end_block)
+        }
     }
 }
 
@@ -204,7 +367,7 @@ fn notify_allocation<S: Storage, A: Api, Q: Querier>(
         ));
     }
 
-    let reward_pool = update_rewards(deps, /*&env, &config,*/ amount)?;
+    let reward_pool = update_rewards(deps, env.block.height, amount)?;
 
     let mut response = Ok(HandleResponse {
         messages: vec![],
@@ -214,12 +377,60 @@ fn notify_allocation<S: Storage, A: Api, Q: Querier>(
 
     if let Some(hook_msg) = hook {
         response = match hook_msg {
-            LPStakingHookMsg::Deposit { from, amount } => {
-                deposit_hook(deps, env, config, reward_pool, from, amount.u128())
+            LPStakingHookMsg::Deposit {
+                from,
+                beneficiary,
+                amount,
+                lock_period,
+            } => deposit_hook(
+                deps,
+                env,
+                config,
+                reward_pool,
+                from,
+                beneficiary,
+                amount.u128(),
+                lock_period,
+            ),
+            LPStakingHookMsg::Redeem {
+                to,
+                amount,
+                recipient,
+            } => redeem_hook(deps, env, config, reward_pool, to, amount, recipient, None),
+            LPStakingHookMsg::Claim { staker, recipient } => {
+                claim_hook(deps, env, config, reward_pool, staker, recipient)
+            }
+            LPStakingHookMsg::TransferStake {
+                from,
+                recipient,
+                amount,
+            } => transfer_stake_hook(
+                deps,
+                env,
+                config,
+                reward_pool,
+                from,
+                recipient,
+                amount.u128(),
+            ),
+            // Same as claiming on behalf of the staker
+            LPStakingHookMsg::ExpireLock { address } => {
+                claim_hook(deps, env, config, reward_pool, address.clone(), address)
             }
-            LPStakingHookMsg::Redeem { to, amount } => {
-                redeem_hook(deps, env, config, reward_pool, to, amount)
+            LPStakingHookMsg::ClaimRewardPool { recipient } => {
+                claim_reward_pool_hook(deps, config, reward_pool, recipient)
             }
+            // Same as redeeming everything, except that the tokens are deposited to the new pool
+            LPStakingHookMsg::MigrateTo { staker, new_pool } => redeem_hook(
+                deps,
+                env,
+                config,
+                reward_pool,
+                staker.clone(),
+                None,
+                staker,
+                Some(new_pool),
+            ),
         }
     }
 
@@ -231,6 +442,9 @@ fn deposit<S: Storage, A: Api, Q: Querier>(
     env: Env,
     from: HumanAddr,
     amount: u128,
+    lock_period: Option<u64>,
+    beneficiary: Option<HumanAddr>,
+    referrer: Option<HumanAddr>,
 ) -> StdResult<HandleResponse> {
     // Ensure that the sent tokens are from an expected contract address
     let config = TypedStore::<Config, S>::attach(&deps.storage).load(CONFIG_KEY)?;
@@ -240,51 +454,140 @@ fn deposit<S: Storage, A: Api, Q: Querier>(
             config.inc_token.address, env.message.sender
         )));
     }
+    if let Some(period) = lock_period {
+        load_lock_tier(&deps.storage, period)?;
+    }
+    if has_ended(&deps.storage, env.block.height)? {
+        return Err(StdError::generic_err(
+            "this pool has ended and doesn't accept deposits",
+        ));
+    }
+
+    let beneficiary = beneficiary.unwrap_or_else(|| from.clone());
+    if beneficiary != from && lock_period.is_some() {
+        return Err(StdError::generic_err(
+            "cannot lock a deposit on behalf of someone else",
+        ));
+    }
+
+    // Only the staker themselves can name their referrer, and only once
+    if let Some(referrer) = referrer {
+        if beneficiary != from {
+            return Err(StdError::generic_err(
+                "cannot set a referrer on behalf of someone else",
+            ));
+        }
+        if referrer != from && load_referrer(&deps.storage, &from).is_none() {
+            let mut referrers_store = PrefixedStorage::new(REFERRERS_KEY, &mut deps.storage);
+            TypedStoreMut::attach(&mut referrers_store).store(from.0.as_bytes(), &referrer)?;
+        }
+    }
 
     update_allocation(
         env,
         config,
         Some(to_binary(&LPStakingHookMsg::Deposit {
             from,
+            beneficiary,
             amount: Uint128(amount),
+            lock_period,
         })?),
     )
 }
 
+#[allow(clippy::too_many_arguments)]
 fn deposit_hook<S: Storage, A: Api, Q: Querier>(
     deps: &mut Extern<S, A, Q>,
-    _env: Env,
+    env: Env,
     config: Config,
     mut reward_pool: RewardPool,
     from: HumanAddr,
+    beneficiary: HumanAddr,
     amount: u128,
+    lock_period: Option<u64>,
 ) -> StdResult<HandleResponse> {
+    // Depositing on behalf of someone else neither locks their balance nor extends their lock
+    let old_lock = load_lock(&deps.storage, &beneficiary);
+    let new_lock = if from == beneficiary {
+        extend_lock(&deps.storage, &old_lock, env.block.height, lock_period)?
+    } else {
+        old_lock.at(env.block.height)
+    };
+
     let mut messages: Vec<CosmosMsg> = vec![];
-    let mut users_store = TypedStoreMut::<UserInfo, S>::attach(&mut deps.storage);
-    let mut user = users_store
-        .load(from.0.as_bytes())
+    let mut user = TypedStore::<UserInfo, S>::attach(&deps.storage)
+        .load(beneficiary.0.as_bytes())
         .unwrap_or(UserInfo { locked: 0, debt: 0 }); // NotFound is the only possible error
 
+    let old_stake = old_lock.boosted(user.locked)?;
     if user.locked > 0 {
-        let pending = user.locked * reward_pool.acc_reward_per_share / REWARD_SCALE - user.debt;
-        if pending > 0 {
-            messages.push(secret_toolkit::snip20::transfer_msg(
-                from.clone(),
-                Uint128(pending),
-                None,
-                RESPONSE_BLOCK_SIZE,
-                config.reward_token.contract_hash,
-                config.reward_token.address,
-            )?);
-        }
+        let pending = pending_rewards(
+            old_stake,
+            reward_pool.acc_reward_per_share,
+            config.reward_scale,
+            user.debt,
+        )?;
+        let pending = pay_referrer(&mut deps.storage, &beneficiary, pending)?;
+        messages.extend(harvest(
+            &mut deps.storage,
+            &config,
+            &beneficiary,
+            &beneficiary,
+            pending,
+            env.block.height,
+        )?);
+    }
+
+    let old_locked = user.locked;
+    user.locked = math::add(user.locked, amount)?;
+    let new_stake = new_lock.boosted(user.locked)?;
+    user.debt = accrued_rewards(
+        new_stake,
+        reward_pool.acc_reward_per_share,
+        config.reward_scale,
+    )?;
+    TypedStoreMut::<UserInfo, S>::attach(&mut deps.storage)
+        .store(beneficiary.0.as_bytes(), &user)?;
+    store_lock(&mut deps.storage, &beneficiary, &new_lock)?;
+    // Same as locks, depositing on behalf of someone else doesn't restart their redeem fee. It
+    // only moves it forward in proportion to the deposit, so that a small deposit can't be used
+    // to grief them, and a large one can't be used to get around the fee
+    if from == beneficiary {
+        store_last_deposit(&mut deps.storage, &beneficiary, env.block.height)?;
+    } else {
+        weigh_last_deposit(
+            &mut deps.storage,
+            &beneficiary,
+            old_locked,
+            amount,
+            env.block.height,
+        )?;
     }
 
-    user.locked += amount;
-    user.debt = user.locked * reward_pool.acc_reward_per_share / REWARD_SCALE;
-    users_store.store(from.0.as_bytes(), &user)?;
+    messages.extend(claim_extra_rewards(
+        &mut deps.storage,
+        env.block.height,
+        reward_pool.boosted_supply,
+        &beneficiary,
+        &beneficiary,
+        old_stake,
+        new_stake,
+    )?);
 
-    reward_pool.inc_token_supply += amount;
+    reward_pool.inc_token_supply = math::add(reward_pool.inc_token_supply, amount)?;
+    reward_pool.boosted_supply =
+        math::sub(math::add(reward_pool.boosted_supply, new_stake)?, old_stake)?;
     TypedStoreMut::attach(&mut deps.storage).store(REWARD_POOL_KEY, &reward_pool)?;
+    checkpoint_total_locked(&mut deps.storage, env.block.height, &reward_pool)?;
+
+    update_stakers_count(&mut deps.storage, old_locked, user.locked)?;
+    messages.extend(update_voting_power(
+        &mut deps.storage,
+        env.block.height,
+        beneficiary,
+        old_locked,
+        user.locked,
+    )?);
 
     Ok(HandleResponse {
         messages,
@@ -299,18 +602,21 @@ fn redeem<S: Storage, A: Api, Q: Querier>(
     deps: &mut Extern<S, A, Q>,
     env: Env,
     amount: Option<Uint128>,
+    recipient: Option<HumanAddr>,
 ) -> StdResult<HandleResponse> {
     let config = TypedStore::<Config, S>::attach(&deps.storage).load(CONFIG_KEY)?;
     update_allocation(
         env.clone(),
         config,
         Some(to_binary(&LPStakingHookMsg::Redeem {
-            to: env.message.sender,
+            to: env.message.sender.clone(),
             amount,
+            recipient: recipient.unwrap_or(env.message.sender),
         })?),
     )
 }
 
+#[allow(clippy::too_many_arguments)]
 fn redeem_hook<S: Storage, A: Api, Q: Querier>(
     deps: &mut Extern<S, A, Q>,
     env: Env,
@@ -318,6 +624,8 @@ fn redeem_hook<S: Storage, A: Api, Q: Querier>(
     mut reward_pool: RewardPool,
     to: HumanAddr,
     amount: Option<Uint128>,
+    recipient: HumanAddr,
+    new_pool: Option<SecretContract>,
 ) -> StdResult<HandleResponse> {
     let mut user = TypedStore::<UserInfo, S>::attach(&deps.storage)
         .load(to.0.as_bytes())
@@ -331,8 +639,34 @@ fn redeem_hook<S: Storage, A: Api, Q: Querier>(
         )));
     }
 
+    // Locks are lifted once the pool has ended, since there's nothing left to earn
+    let old_lock = load_lock(&deps.storage, &to);
+    if amount > 0
+        && env.block.height < old_lock.unlock_block
+        && !has_ended(&deps.storage, env.block.height)?
+    {
+        return Err(StdError::generic_err(format!(
+            "this balance is locked until block {}",
+            old_lock.unlock_block
+        )));
+    }
+
+    // Migrating is free, and so is leaving a pool that has ended
+    let fee = if new_pool.is_none() && !has_ended(&deps.storage, env.block.height)? {
+        redeem_fee(&deps.storage, &to, amount, env.block.height)?
+    } else {
+        None
+    };
+
     let mut messages: Vec<CosmosMsg> = vec![];
-    let pending = user.locked * reward_pool.acc_reward_per_share / REWARD_SCALE - user.debt;
+    let old_stake = old_lock.boosted(user.locked)?;
+    let pending = pending_rewards(
+        old_stake,
+        reward_pool.acc_reward_per_share,
+        config.reward_scale,
+        user.debt,
+    )?;
+    let pending = pay_referrer(&mut deps.storage, &to, pending)?;
     debug_print(format!("DEBUG DEBUG DEBUG"));
     debug_print(format!(
         "reward pool: | residue: {} | total supply: {} | acc: {} |",
@@ -344,39 +678,331 @@ fn redeem_hook<S: Storage, A: Api, Q: Querier>(
     ));
     debug_print(format!("pending: {}", pending));
     debug_print(format!("DEBUG DEBUG DEBUG"));
-    if pending > 0 {
-        // Transfer rewards
+    messages.extend(harvest(
+        &mut deps.storage,
+        &config,
+        &to,
+        &recipient,
+        pending,
+        env.block.height,
+    )?);
+
+    // Transfer redeemed tokens
+    let old_locked = user.locked;
+    user.locked = math::sub(user.locked, amount)?;
+    let new_lock = old_lock.at(env.block.height);
+    let new_stake = new_lock.boosted(user.locked)?;
+    user.debt = accrued_rewards(
+        new_stake,
+        reward_pool.acc_reward_per_share,
+        config.reward_scale,
+    )?;
+    TypedStoreMut::<UserInfo, S>::attach(&mut deps.storage).store(to.0.as_bytes(), &user)?;
+    store_lock(&mut deps.storage, &to, &new_lock)?;
+
+    messages.extend(claim_extra_rewards(
+        &mut deps.storage,
+        env.block.height,
+        reward_pool.boosted_supply,
+        &to,
+        &recipient,
+        old_stake,
+        new_stake,
+    )?);
+
+    reward_pool.inc_token_supply = math::sub(reward_pool.inc_token_supply, amount)?;
+    reward_pool.boosted_supply =
+        math::sub(math::add(reward_pool.boosted_supply, new_stake)?, old_stake)?;
+    TypedStoreMut::attach(&mut deps.storage).store(REWARD_POOL_KEY, &reward_pool)?;
+    checkpoint_total_locked(&mut deps.storage, env.block.height, &reward_pool)?;
+
+    update_stakers_count(&mut deps.storage, old_locked, user.locked)?;
+    messages.extend(update_voting_power(
+        &mut deps.storage,
+        env.block.height,
+        to,
+        old_locked,
+        user.locked,
+    )?);
+
+    if let Some(new_pool) = new_pool {
+        if amount > 0 {
+            messages.push(snip20::send_msg(
+                new_pool.address,
+                Uint128(amount),
+                Some(to_binary(&LPStakingReceiveMsg::Deposit {
+                    lock_period: None,
+                    beneficiary: Some(recipient),
+                    referrer: None,
+                })?),
+                None,
+                RESPONSE_BLOCK_SIZE,
+                config.inc_token.contract_hash,
+                config.inc_token.address,
+            )?);
+        }
+
+        return Ok(HandleResponse {
+            messages,
+            log: vec![],
+            data: Some(to_binary(&LPStakingHandleAnswer::MigrateTo {
+                status: Success,
+            })?),
+        });
+    }
+
+    let mut amount = amount;
+    if let Some((fee, treasury)) = fee {
+        amount = math::sub(amount, fee)?;
+        messages.push(snip20::send_msg(
+            treasury.address,
+            Uint128(fee),
+            None,
+            None,
+            RESPONSE_BLOCK_SIZE,
+            config.inc_token.contract_hash.clone(),
+            config.inc_token.address.clone(),
+        )?);
+    }
+
+    if amount > 0 {
         messages.push(secret_toolkit::snip20::transfer_msg(
-            to.clone(),
-            Uint128(pending),
+            recipient,
+            Uint128(amount),
             None,
             RESPONSE_BLOCK_SIZE,
-            config.reward_token.contract_hash,
-            config.reward_token.address,
+            config.inc_token.contract_hash,
+            config.inc_token.address,
         )?);
     }
 
-    // Transfer redeemed tokens
-    user.locked -= amount;
-    user.debt = user.locked * reward_pool.acc_reward_per_share / REWARD_SCALE;
-    TypedStoreMut::<UserInfo, S>::attach(&mut deps.storage).store(to.0.as_bytes(), &user)?;
+    Ok(HandleResponse {
+        messages,
+        log: vec![],
+        data: Some(to_binary(&LPStakingHandleAnswer::Redeem {
+            status: Success,
+        })?),
+    })
+}
+
+fn migrate_to<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    new_pool: HumanAddr,
+) -> StdResult<HandleResponse> {
+    let config = TypedStore::<Config, S>::attach(&deps.storage).load(CONFIG_KEY)?;
+    let target: Option<SecretContract> =
+        TypedStore::attach(&deps.storage).load(MIGRATION_TARGET_KEY)?;
+    let target = match target {
+        Some(target) if target.address == new_pool => target,
+        Some(target) => {
+            return Err(StdError::generic_err(format!(
+                "this pool can only be migrated to {}",
+                target.address
+            )))
+        }
+        None => {
+            return Err(StdError::generic_err(
+                "migrating is not enabled for this pool",
+            ))
+        }
+    };
+
+    update_allocation(
+        env.clone(),
+        config,
+        Some(to_binary(&LPStakingHookMsg::MigrateTo {
+            staker: env.message.sender,
+            new_pool: target,
+        })?),
+    )
+}
+
+fn claim<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    recipient: Option<HumanAddr>,
+) -> StdResult<HandleResponse> {
+    let config = TypedStore::<Config, S>::attach(&deps.storage).load(CONFIG_KEY)?;
+    update_allocation(
+        env.clone(),
+        config,
+        Some(to_binary(&LPStakingHookMsg::Claim {
+            staker: env.message.sender.clone(),
+            recipient: recipient.unwrap_or(env.message.sender),
+        })?),
+    )
+}
+
+/// Pays a staker's pending rewards to `recipient`, without changing their balance
+fn claim_hook<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    config: Config,
+    mut reward_pool: RewardPool,
+    staker: HumanAddr,
+    recipient: HumanAddr,
+) -> StdResult<HandleResponse> {
+    let mut user = TypedStore::<UserInfo, S>::attach(&deps.storage)
+        .load(staker.0.as_bytes())
+        .unwrap_or(UserInfo { locked: 0, debt: 0 }); // NotFound is the only possible error
+    let old_lock = load_lock(&deps.storage, &staker);
+
+    let mut messages: Vec<CosmosMsg> = vec![];
+    let old_stake = old_lock.boosted(user.locked)?;
+    let pending = pending_rewards(
+        old_stake,
+        reward_pool.acc_reward_per_share,
+        config.reward_scale,
+        user.debt,
+    )?;
+    let pending = pay_referrer(&mut deps.storage, &staker, pending)?;
+    messages.extend(harvest(
+        &mut deps.storage,
+        &config,
+        &staker,
+        &recipient,
+        pending,
+        env.block.height,
+    )?);
 
-    reward_pool.inc_token_supply -= amount;
+    // An expired lock stops boosting the stake from here on
+    let new_lock = old_lock.at(env.block.height);
+    let new_stake = new_lock.boosted(user.locked)?;
+    user.debt = accrued_rewards(
+        new_stake,
+        reward_pool.acc_reward_per_share,
+        config.reward_scale,
+    )?;
+    TypedStoreMut::<UserInfo, S>::attach(&mut deps.storage).store(staker.0.as_bytes(), &user)?;
+    store_lock(&mut deps.storage, &staker, &new_lock)?;
+
+    messages.extend(claim_extra_rewards(
+        &mut deps.storage,
+        env.block.height,
+        reward_pool.boosted_supply,
+        &staker,
+        &recipient,
+        old_stake,
+        new_stake,
+    )?);
+
+    reward_pool.boosted_supply =
+        math::sub(math::add(reward_pool.boosted_supply, new_stake)?, old_stake)?;
     TypedStoreMut::attach(&mut deps.storage).store(REWARD_POOL_KEY, &reward_pool)?;
 
-    messages.push(secret_toolkit::snip20::transfer_msg(
-        to,
-        Uint128(amount),
-        None,
-        RESPONSE_BLOCK_SIZE,
-        config.inc_token.contract_hash,
-        config.inc_token.address,
+    Ok(HandleResponse {
+        messages,
+        log: vec![],
+        data: Some(to_binary(&LPStakingHandleAnswer::Claim {
+            status: Success,
+        })?),
+    })
+}
+
+fn transfer_stake<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    recipient: HumanAddr,
+    amount: u128,
+) -> StdResult<HandleResponse> {
+    if recipient == env.message.sender {
+        return Err(StdError::generic_err("cannot transfer stake to yourself"));
+    }
+
+    let config = TypedStore::<Config, S>::attach(&deps.storage).load(CONFIG_KEY)?;
+    update_allocation(
+        env.clone(),
+        config,
+        Some(to_binary(&LPStakingHookMsg::TransferStake {
+            from: env.message.sender,
+            recipient,
+            amount: Uint128(amount),
+        })?),
+    )
+}
+
+/// Moves stake between two stakers without unstaking it. Both of them get their pending rewards.
+/// Locked stake can't be transferred, and neither can stake be transferred into a lock, where it
+/// would take the lock's boost without being locked itself. Same as redeems, the sender's lock is
+/// lifted once the pool has ended
+fn transfer_stake_hook<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    config: Config,
+    mut reward_pool: RewardPool,
+    from: HumanAddr,
+    recipient: HumanAddr,
+    amount: u128,
+) -> StdResult<HandleResponse> {
+    let mut sender = TypedStore::<UserInfo, S>::attach(&deps.storage)
+        .load(from.0.as_bytes())
+        .unwrap_or(UserInfo { locked: 0, debt: 0 }); // NotFound is the only possible error
+    if amount > sender.locked {
+        return Err(StdError::generic_err(format!(
+            "insufficient funds to transfer: balance={}, required={}",
+            sender.locked, amount,
+        )));
+    }
+
+    let unlock_block = load_lock(&deps.storage, &from).unlock_block;
+    if env.block.height < unlock_block && !has_ended(&deps.storage, env.block.height)? {
+        return Err(StdError::generic_err(format!(
+            "this balance is locked until block {}",
+            unlock_block
+        )));
+    }
+
+    let recipient_unlock_block = load_lock(&deps.storage, &recipient).unlock_block;
+    if env.block.height < recipient_unlock_block {
+        return Err(StdError::generic_err(format!(
+            "{} has a balance that is locked until block {}, and cannot receive stake until then",
+            recipient, recipient_unlock_block
+        )));
+    }
+
+    let mut receiver = TypedStore::<UserInfo, S>::attach(&deps.storage)
+        .load(recipient.0.as_bytes())
+        .unwrap_or(UserInfo { locked: 0, debt: 0 });
+    let old_receiver_locked = receiver.locked;
+
+    let new_sender_locked = math::sub(sender.locked, amount)?;
+    let mut messages = restake(
+        &mut deps.storage,
+        &config,
+        &mut reward_pool,
+        env.block.height,
+        &from,
+        &mut sender,
+        new_sender_locked,
+    )?;
+    let new_receiver_locked = math::add(receiver.locked, amount)?;
+    messages.extend(restake(
+        &mut deps.storage,
+        &config,
+        &mut reward_pool,
+        env.block.height,
+        &recipient,
+        &mut receiver,
+        new_receiver_locked,
     )?);
 
+    // The moved stake brings its redeem fee clock along, weighted by amount like on-behalf deposits
+    let from_last_deposit = load_last_deposit(&deps.storage, &from);
+    weigh_last_deposit(
+        &mut deps.storage,
+        &recipient,
+        old_receiver_locked,
+        amount,
+        from_last_deposit,
+    )?;
+
+    TypedStoreMut::attach(&mut deps.storage).store(REWARD_POOL_KEY, &reward_pool)?;
+
     Ok(HandleResponse {
         messages,
         log: vec![],
-        data: Some(to_binary(&LPStakingHandleAnswer::Redeem {
+        data: Some(to_binary(&LPStakingHandleAnswer::TransferStake {
             status: Success,
         })?),
     })
@@ -421,6 +1047,22 @@ pub fn set_viewing_key<S: Storage, A: Api, Q: Querier>(
     })
 }
 
+fn revoke_permit<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    permit_name: String,
+) -> StdResult<HandleResponse> {
+    permit::revoke_permit(&mut deps.storage, &env.message.sender, &permit_name);
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![],
+        data: Some(to_binary(&LPStakingHandleAnswer::RevokePermit {
+            status: Success,
+        })?),
+    })
+}
+
 fn stop_contract<S: Storage, A: Api, Q: Querier>(
     deps: &mut Extern<S, A, Q>,
     env: Env,
@@ -485,110 +1127,1124 @@ fn change_admin<S: Storage, A: Api, Q: Querier>(
     })
 }
 
-/// YOU SHOULD NEVER USE THIS! This will erase any eligibility for rewards you earned so far
-fn emergency_redeem<S: Storage, A: Api, Q: Querier>(
+fn set_poll_factory<S: Storage, A: Api, Q: Querier>(
     deps: &mut Extern<S, A, Q>,
     env: Env,
+    address: Option<HumanAddr>,
 ) -> StdResult<HandleResponse> {
     let config: Config = TypedStore::attach(&deps.storage).load(CONFIG_KEY)?;
-    let mut user: UserInfo = TypedStoreMut::attach(&mut deps.storage)
-        .load(env.message.sender.0.as_bytes())
-        .unwrap_or(UserInfo { locked: 0, debt: 0 });
-
-    let mut reward_pool: RewardPool =
-        TypedStoreMut::attach(&mut deps.storage).load(REWARD_POOL_KEY)?;
-    reward_pool.inc_token_supply -= user.locked;
-    TypedStoreMut::attach(&mut deps.storage).store(REWARD_POOL_KEY, &reward_pool)?;
-
-    let mut messages = vec![];
-    if user.locked > 0 {
-        messages.push(secret_toolkit::snip20::transfer_msg(
-            env.message.sender.clone(),
-            Uint128(user.locked),
-            None,
-            RESPONSE_BLOCK_SIZE,
-            config.inc_token.contract_hash,
-            config.inc_token.address,
-        )?);
-    }
+    enforce_admin(config, env)?;
 
-    user = UserInfo { locked: 0, debt: 0 };
-    TypedStoreMut::attach(&mut deps.storage).store(env.message.sender.0.as_bytes(), &user)?;
+    TypedStoreMut::attach(&mut deps.storage).store(POLL_FACTORY_KEY, &address)?;
 
     Ok(HandleResponse {
-        messages,
+        messages: vec![],
         log: vec![],
-        data: Some(to_binary(&LPStakingHandleAnswer::EmergencyRedeem {
+        data: Some(to_binary(&LPStakingHandleAnswer::SetPollFactory {
             status: Success,
         })?),
     })
 }
 
-// Query functions
+fn add_reward_token<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    token: SecretContract,
+) -> StdResult<HandleResponse> {
+    let config: Config = TypedStore::attach(&deps.storage).load(CONFIG_KEY)?;
+    enforce_admin(config.clone(), env.clone())?;
+
+    let mut extra_rewards_store = TypedStoreMut::attach(&mut deps.storage);
+    let mut extra_rewards: Vec<ExtraReward> = extra_rewards_store.load(EXTRA_REWARDS_KEY)?;
+    if token.address == config.reward_token.address
+        || token.address == config.inc_token.address
+        || extra_rewards
+            .iter()
+            .any(|r| r.token.address == token.address)
+    {
+        return Err(StdError::generic_err(format!(
+            "{} is already used by this contract",
+            token.address
+        )));
+    }
+
+    extra_rewards.push(ExtraReward {
+        token: token.clone(),
+        reward_per_block: 0,
+        end_block: env.block.height,
+        last_update_block: env.block.height,
+        residue: 0,
+        remainder: 0,
+        acc_reward_per_share: 0,
+    });
+    extra_rewards_store.store(EXTRA_REWARDS_KEY, &extra_rewards)?;
+
+    let messages = vec![
+        snip20::register_receive_msg(
+            env.contract_code_hash,
+            None,
+            1,
+            token.contract_hash.clone(),
+            token.address.clone(),
+        )?,
+        snip20::set_viewing_key_msg(
+            config.viewing_key,
+            None,
+            RESPONSE_BLOCK_SIZE,
+            token.contract_hash,
+            token.address,
+        )?,
+    ];
+
+    Ok(HandleResponse {
+        messages,
+        log: vec![],
+        data: Some(to_binary(&LPStakingHandleAnswer::AddRewardToken {
+            status: Success,
+        })?),
+    })
+}
+
+fn set_deadline<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    block: u64,
+) -> StdResult<HandleResponse> {
+    let config: Config = TypedStore::attach(&deps.storage).load(CONFIG_KEY)?;
+    enforce_admin(config, env.clone())?;
+
+    // Allocations are split at the deadline when they arrive, so it can't move once it has passed
+    if has_ended(&deps.storage, env.block.height)? {
+        return Err(StdError::generic_err("this pool has already ended"));
+    }
+    if block < env.block.height {
+        return Err(StdError::generic_err(
+            "the deadline cannot be set to a past block",
+        ));
+    }
+    // Otherwise whatever stakers leave behind could never be claimed
+    claimable_from(block)?;
+
+    TypedStoreMut::attach(&mut deps.storage).store(DEADLINE_KEY, &Some(block))?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![],
+        data: Some(to_binary(&LPStakingHandleAnswer::SetDeadline {
+            status: Success,
+        })?),
+    })
+}
+
+fn set_migration_target<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    new_pool: Option<SecretContract>,
+) -> StdResult<HandleResponse> {
+    let config: Config = TypedStore::attach(&deps.storage).load(CONFIG_KEY)?;
+    enforce_admin(config, env.clone())?;
+
+    if new_pool.as_ref().map(|p| &p.address) == Some(&env.contract.address) {
+        return Err(StdError::generic_err("a pool cannot be migrated to itself"));
+    }
+    TypedStoreMut::attach(&mut deps.storage).store(MIGRATION_TARGET_KEY, &new_pool)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![],
+        data: Some(to_binary(&LPStakingHandleAnswer::SetMigrationTarget {
+            status: Success,
+        })?),
+    })
+}
+
+fn claim_referral_rewards<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    recipient: Option<HumanAddr>,
+) -> StdResult<HandleResponse> {
+    let config: Config = TypedStore::attach(&deps.storage).load(CONFIG_KEY)?;
+
+    let referrer = env.message.sender;
+    let mut referral_store = PrefixedStorage::new(REFERRAL_REWARDS_KEY, &mut deps.storage);
+    let mut rewards_store = TypedStoreMut::<u128, _>::attach(&mut referral_store);
+    let amount = rewards_store.load(referrer.0.as_bytes()).unwrap_or(0); // NotFound is the only possible error
+    rewards_store.store(referrer.0.as_bytes(), &0)?;
+
+    // Referral rewards vest like any other harvest, or they'd be a way around vesting
+    let recipient = recipient.unwrap_or_else(|| referrer.clone());
+    let messages = harvest(
+        &mut deps.storage,
+        &config,
+        &referrer,
+        &recipient,
+        amount,
+        env.block.height,
+    )?;
+
+    Ok(HandleResponse {
+        messages,
+        log: vec![],
+        data: Some(to_binary(&LPStakingHandleAnswer::ClaimReferralRewards {
+            status: Success,
+        })?),
+    })
+}
+
+fn claim_vested<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    recipient: Option<HumanAddr>,
+    include_locked: bool,
+) -> StdResult<HandleResponse> {
+    let config: Config = TypedStore::attach(&deps.storage).load(CONFIG_KEY)?;
+
+    let owner = env.message.sender;
+    let mut vesting = load_vesting(&deps.storage, &owner);
+    vesting.settle(env.block.height)?;
+    let mut amount = vesting.claimable;
+    vesting.claimable = 0;
+
+    if include_locked && vesting.locked > 0 {
+        let vesting_config: Option<VestingConfig> =
+            TypedStore::attach(&deps.storage).load(VESTING_KEY)?;
+        let penalty_bps = vesting_config.map_or(0, |v| v.penalty_bps);
+        let penalty = math::mul_div(vesting.locked, penalty_bps as u128, 10_000, Rounding::Up)?;
+        amount = math::add(amount, math::sub(vesting.locked, penalty)?)?;
+        vesting.locked = 0;
+        if penalty > 0 {
+            redistribute(&mut deps.storage, config.reward_scale, penalty)?;
+        }
+    }
+    store_vesting(&mut deps.storage, &owner, &vesting)?;
+
+    let mut messages = vec![];
+    if amount > 0 {
+        messages.push(snip20::transfer_msg(
+            recipient.unwrap_or(owner),
+            Uint128(amount),
+            None,
+            RESPONSE_BLOCK_SIZE,
+            config.reward_token.contract_hash,
+            config.reward_token.address,
+        )?);
+    }
+
+    Ok(HandleResponse {
+        messages,
+        log: vec![],
+        data: Some(to_binary(&LPStakingHandleAnswer::ClaimVested {
+            status: Success,
+        })?),
+    })
+}
+
+fn set_vesting<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    vesting: Option<VestingConfig>,
+) -> StdResult<HandleResponse> {
+    let config: Config = TypedStore::attach(&deps.storage).load(CONFIG_KEY)?;
+    enforce_admin(config, env)?;
+
+    if let Some(vesting) = &vesting {
+        if vesting.period == 0 {
+            return Err(StdError::generic_err(
+                "the vesting period must be at least one block",
+            ));
+        }
+        if vesting.penalty_bps > MAX_VESTING_PENALTY_BPS {
+            return Err(StdError::generic_err(format!(
+                "the early claim penalty cannot be more than {} basis points",
+                MAX_VESTING_PENALTY_BPS
+            )));
+        }
+    }
+    TypedStoreMut::attach(&mut deps.storage).store(VESTING_KEY, &vesting)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![],
+        data: Some(to_binary(&LPStakingHandleAnswer::SetVesting {
+            status: Success,
+        })?),
+    })
+}
+
+fn set_referral_rate<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    rate_bps: u64,
+) -> StdResult<HandleResponse> {
+    let config: Config = TypedStore::attach(&deps.storage).load(CONFIG_KEY)?;
+    enforce_admin(config, env)?;
+
+    if rate_bps > MAX_REFERRAL_RATE_BPS {
+        return Err(StdError::generic_err(format!(
+            "the referral rate cannot be more than {} basis points",
+            MAX_REFERRAL_RATE_BPS
+        )));
+    }
+    TypedStoreMut::attach(&mut deps.storage).store(REFERRAL_RATE_KEY, &rate_bps)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![],
+        data: Some(to_binary(&LPStakingHandleAnswer::SetReferralRate {
+            status: Success,
+        })?),
+    })
+}
+
+fn set_redeem_fee<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    fee: Option<RedeemFee>,
+) -> StdResult<HandleResponse> {
+    let config: Config = TypedStore::attach(&deps.storage).load(CONFIG_KEY)?;
+    enforce_admin(config, env)?;
+
+    if let Some(fee) = &fee {
+        if fee.rate_bps > MAX_REDEEM_FEE_BPS {
+            return Err(StdError::generic_err(format!(
+                "the redeem fee cannot be more than {} basis points",
+                MAX_REDEEM_FEE_BPS
+            )));
+        }
+        if fee.decay_period == 0 {
+            return Err(StdError::generic_err(
+                "the redeem fee's decay period must be at least one block",
+            ));
+        }
+    }
+    TypedStoreMut::attach(&mut deps.storage).store(REDEEM_FEE_KEY, &fee)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![],
+        data: Some(to_binary(&LPStakingHandleAnswer::SetRedeemFee {
+            status: Success,
+        })?),
+    })
+}
+
+/// Unlike trusted contracts, which the poll factory also manages, only the admin can exempt a
+/// contract from the redeem fee
+fn add_fee_exempt<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    contracts: Vec<HumanAddr>,
+) -> StdResult<HandleResponse> {
+    let config: Config = TypedStore::attach(&deps.storage).load(CONFIG_KEY)?;
+    enforce_admin(config, env)?;
+
+    let mut exempt_store = TypedStoreMut::attach(&mut deps.storage);
+    let mut exempt: Vec<HumanAddr> = exempt_store.load(FEE_EXEMPT_KEY)?;
+    for contract in contracts {
+        if !exempt.contains(&contract) {
+            exempt.push(contract);
+        }
+    }
+    exempt_store.store(FEE_EXEMPT_KEY, &exempt)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![],
+        data: Some(to_binary(&LPStakingHandleAnswer::AddFeeExempt {
+            status: Success,
+        })?),
+    })
+}
+
+fn remove_fee_exempt<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    contracts: Vec<HumanAddr>,
+) -> StdResult<HandleResponse> {
+    let config: Config = TypedStore::attach(&deps.storage).load(CONFIG_KEY)?;
+    enforce_admin(config, env)?;
+
+    let mut exempt_store = TypedStoreMut::attach(&mut deps.storage);
+    let mut exempt: Vec<HumanAddr> = exempt_store.load(FEE_EXEMPT_KEY)?;
+    exempt.retain(|c| !contracts.contains(c));
+    exempt_store.store(FEE_EXEMPT_KEY, &exempt)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![],
+        data: Some(to_binary(&LPStakingHandleAnswer::RemoveFeeExempt {
+            status: Success,
+        })?),
+    })
+}
+
+fn claim_reward_pool<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    recipient: Option<HumanAddr>,
+) -> StdResult<HandleResponse> {
+    let config: Config = TypedStore::attach(&deps.storage).load(CONFIG_KEY)?;
+    enforce_admin(config.clone(), env.clone())?;
+
+    match load_deadline(&deps.storage)?
+        .map(claimable_from)
+        .transpose()?
+    {
+        Some(claimable_from) if env.block.height >= claimable_from => {}
+        Some(claimable_from) => {
+            return Err(StdError::generic_err(format!(
+                "the reward pool can be claimed from block {}",
+                claimable_from
+            )))
+        }
+        None => return Err(StdError::generic_err("this pool has no deadline")),
+    }
+
+    let recipient = recipient.unwrap_or_else(|| env.message.sender.clone());
+    update_allocation(
+        env,
+        config,
+        Some(to_binary(&LPStakingHookMsg::ClaimRewardPool { recipient })?),
+    )
+}
+
+fn claim_reward_pool_hook<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    config: Config,
+    mut reward_pool: RewardPool,
+    recipient: HumanAddr,
+) -> StdResult<HandleResponse> {
+    let amount = reward_pool.residue;
+    reward_pool.residue = 0;
+    TypedStoreMut::attach(&mut deps.storage).store(REWARD_POOL_KEY, &reward_pool)?;
+
+    let mut messages = vec![];
+    if amount > 0 {
+        messages.push(snip20::transfer_msg(
+            recipient,
+            Uint128(amount),
+            None,
+            RESPONSE_BLOCK_SIZE,
+            config.reward_token.contract_hash,
+            config.reward_token.address,
+        )?);
+    }
+
+    Ok(HandleResponse {
+        messages,
+        log: vec![],
+        data: Some(to_binary(&LPStakingHandleAnswer::ClaimRewardPool {
+            status: Success,
+        })?),
+    })
+}
+
+fn deposit_rewards<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    from: HumanAddr,
+    amount: u128,
+    end_block: u64,
+) -> StdResult<HandleResponse> {
+    // Funding reschedules whatever is left of the campaign, so it can't be left to anyone
+    let config: Config = TypedStore::attach(&deps.storage).load(CONFIG_KEY)?;
+    if from != config.admin {
+        return Err(StdError::generic_err(
+            "only the admin can fund extra rewards",
+        ));
+    }
+    if end_block <= env.block.height {
+        return Err(StdError::generic_err(
+            "the rewards must be paid out until a future block",
+        ));
+    }
+
+    let reward_pool: RewardPool = TypedStore::attach(&deps.storage).load(REWARD_POOL_KEY)?;
+    let scale = load_reward_scale(&deps.storage)?;
+    let mut extra_rewards = update_extra_rewards(
+        &mut deps.storage,
+        env.block.height,
+        reward_pool.boosted_supply,
+        scale,
+    )?;
+
+    let reward = extra_rewards
+        .iter_mut()
+        .find(|r| r.token.address == env.message.sender)
+        .ok_or_else(|| {
+            StdError::generic_err(format!(
+                "{} is not a reward token of this contract",
+                env.message.sender
+            ))
+        })?;
+
+    // Whatever wasn't paid out yet is spread along with the new funds until the new end block
+    let mut total = math::add(amount, reward.remainder)?;
+    if reward.end_block > env.block.height {
+        let remaining_blocks = (reward.end_block - env.block.height) as u128;
+        total = math::add(total, math::mul(remaining_blocks, reward.reward_per_block)?)?;
+    }
+    let blocks = (end_block - env.block.height) as u128;
+    reward.reward_per_block = math::div(total, blocks, Rounding::Down)?;
+    reward.remainder = total % blocks;
+    reward.end_block = end_block;
+
+    TypedStoreMut::attach(&mut deps.storage).store(EXTRA_REWARDS_KEY, &extra_rewards)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![],
+        data: Some(to_binary(&LPStakingReceiveAnswer::DepositRewards {
+            status: Success,
+        })?),
+    })
+}
+
+fn set_lock_tiers<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    tiers: Vec<LockTier>,
+) -> StdResult<HandleResponse> {
+    let config: Config = TypedStore::attach(&deps.storage).load(CONFIG_KEY)?;
+    enforce_admin(config, env)?;
+
+    for (i, tier) in tiers.iter().enumerate() {
+        if tier.period == 0 || tier.boost < NO_BOOST {
+            return Err(StdError::generic_err(format!(
+                "invalid lock tier: a lock must last at least one block, and boost by at least {}%",
+                NO_BOOST
+            )));
+        }
+        if tiers[..i].iter().any(|t| t.period == tier.period) {
+            return Err(StdError::generic_err(format!(
+                "there is more than one lock tier of {} blocks",
+                tier.period
+            )));
+        }
+    }
+
+    // Existing locks keep the boost they were made with
+    TypedStoreMut::attach(&mut deps.storage).store(LOCK_TIERS_KEY, &tiers)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![],
+        data: Some(to_binary(&LPStakingHandleAnswer::SetLockTiers {
+            status: Success,
+        })?),
+    })
+}
+
+/// Drops the boost of a staker's expired lock. Until the staker's balance changes, an expired
+/// lock keeps its boost, so anyone can do this on their behalf. Pending rewards go to the staker
+fn expire_lock<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    address: HumanAddr,
+) -> StdResult<HandleResponse> {
+    let lock = load_lock(&deps.storage, &address);
+    if lock.boost == NO_BOOST || env.block.height < lock.unlock_block {
+        return Err(StdError::generic_err(format!(
+            "{} has no expired lock",
+            address
+        )));
+    }
+
+    let config: Config = TypedStore::attach(&deps.storage).load(CONFIG_KEY)?;
+    update_allocation(
+        env,
+        config,
+        Some(to_binary(&LPStakingHookMsg::ExpireLock { address })?),
+    )
+}
+
+fn add_subscribers<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    contracts: Vec<SecretContract>,
+) -> StdResult<HandleResponse> {
+    enforce_admin_or_poll_factory(deps, &env)?;
+
+    let mut subscribers_store = TypedStoreMut::attach(&mut deps.storage);
+    let mut subscribers: Vec<SecretContract> = subscribers_store.load(SUBSCRIBERS_KEY)?;
+    for contract in contracts {
+        if !subscribers.iter().any(|s| s.address == contract.address) {
+            subscribers.push(contract);
+        }
+    }
+    if subscribers.len() > MAX_SUBSCRIBERS {
+        return Err(StdError::generic_err(format!(
+            "a pool cannot have more than {} subscribers",
+            MAX_SUBSCRIBERS
+        )));
+    }
+    subscribers_store.store(SUBSCRIBERS_KEY, &subscribers)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![],
+        data: Some(to_binary(&LPStakingHandleAnswer::AddSubscribers {
+            status: Success,
+        })?),
+    })
+}
+
+fn remove_subscribers<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    contracts: Vec<HumanAddr>,
+) -> StdResult<HandleResponse> {
+    enforce_admin_or_poll_factory(deps, &env)?;
+
+    let mut subscribers_store = TypedStoreMut::attach(&mut deps.storage);
+    let mut subscribers: Vec<SecretContract> = subscribers_store.load(SUBSCRIBERS_KEY)?;
+    subscribers.retain(|s| !contracts.contains(&s.address));
+    subscribers_store.store(SUBSCRIBERS_KEY, &subscribers)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![],
+        data: Some(to_binary(&LPStakingHandleAnswer::RemoveSubscribers {
+            status: Success,
+        })?),
+    })
+}
+
+fn add_trusted_contracts<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    contracts: Vec<HumanAddr>,
+) -> StdResult<HandleResponse> {
+    // The poll factory trusts the polls it creates to read their voters' balances
+    enforce_admin_or_poll_factory(deps, &env)?;
+
+    let mut trusted_store = TypedStoreMut::attach(&mut deps.storage);
+    let mut trusted: Vec<HumanAddr> = trusted_store.load(TRUSTED_CONTRACTS_KEY)?;
+    for contract in contracts {
+        if !trusted.contains(&contract) {
+            trusted.push(contract);
+        }
+    }
+    trusted_store.store(TRUSTED_CONTRACTS_KEY, &trusted)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![],
+        data: Some(to_binary(&LPStakingHandleAnswer::AddTrustedContracts {
+            status: Success,
+        })?),
+    })
+}
+
+fn remove_trusted_contracts<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    contracts: Vec<HumanAddr>,
+) -> StdResult<HandleResponse> {
+    enforce_admin_or_poll_factory(deps, &env)?;
+
+    let mut trusted_store = TypedStoreMut::attach(&mut deps.storage);
+    let mut trusted: Vec<HumanAddr> = trusted_store.load(TRUSTED_CONTRACTS_KEY)?;
+    trusted.retain(|c| !contracts.contains(c));
+    trusted_store.store(TRUSTED_CONTRACTS_KEY, &trusted)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![],
+        data: Some(to_binary(&LPStakingHandleAnswer::RemoveTrustedContracts {
+            status: Success,
+        })?),
+    })
+}
+
+/// Delegates the sender's voting power to another address in every poll that is subscribed to
+/// this pool. A poll still counts the sender's own vote instead, if they choose to vote
+fn delegate_votes<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    delegate: Option<HumanAddr>,
+) -> StdResult<HandleResponse> {
+    let delegator = env.message.sender;
+    if delegate == Some(delegator.clone()) {
+        return Err(StdError::generic_err("cannot delegate to yourself"));
+    }
+
+    let user = TypedStore::<UserInfo, S>::attach(&deps.storage)
+        .load(delegator.0.as_bytes())
+        .unwrap_or(UserInfo { locked: 0, debt: 0 });
+
+    let height = env.block.height;
+    let old_delegate = load_delegate(&deps.storage, &delegator);
+    if let Some(old_delegate) = &old_delegate {
+        update_delegated_power(&mut deps.storage, height, old_delegate, user.locked, 0)?;
+    }
+    if let Some(new_delegate) = &delegate {
+        update_delegated_power(&mut deps.storage, height, new_delegate, 0, user.locked)?;
+    }
+
+    let mut delegations_store = PrefixedStorage::new(DELEGATIONS_KEY, &mut deps.storage);
+    TypedStoreMut::attach(&mut delegations_store).store(delegator.0.as_bytes(), &delegate)?;
+    push_checkpoint(
+        &mut PrefixedStorage::multilevel(
+            &[DELEGATE_CHECKPOINTS_KEY, delegator.0.as_bytes()],
+            &mut deps.storage,
+        ),
+        height,
+        delegate.clone(),
+    )?;
+
+    let messages = notify_subscribers(
+        &deps.storage,
+        PollHandleMsg::UpdateDelegation {
+            delegator,
+            voting_power: Uint128(user.locked),
+            old_delegate,
+            new_delegate: delegate,
+        },
+    )?;
+
+    Ok(HandleResponse {
+        messages,
+        log: vec![],
+        data: Some(to_binary(&LPStakingHandleAnswer::Delegate {
+            status: Success,
+        })?),
+    })
+}
+
+/// YOU SHOULD NEVER USE THIS! This will erase any eligibility for rewards you earned so far
+fn emergency_redeem<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+) -> StdResult<HandleResponse> {
+    let config: Config = TypedStore::attach(&deps.storage).load(CONFIG_KEY)?;
+    let mut user: UserInfo = TypedStoreMut::attach(&mut deps.storage)
+        .load(env.message.sender.0.as_bytes())
+        .unwrap_or(UserInfo { locked: 0, debt: 0 });
+
+    let mut reward_pool: RewardPool =
+        TypedStoreMut::attach(&mut deps.storage).load(REWARD_POOL_KEY)?;
+
+    // Extra rewards are forfeited as well
+    let extra_rewards = update_extra_rewards(
+        &mut deps.storage,
+        env.block.height,
+        reward_pool.boosted_supply,
+        config.reward_scale,
+    )?;
+    store_extra_debts(
+        &mut deps.storage,
+        &env.message.sender,
+        0,
+        &extra_rewards,
+        config.reward_scale,
+    )?;
+
+    // Locks don't apply once the contract is stopped
+    let lock = load_lock(&deps.storage, &env.message.sender);
+    store_lock(&mut deps.storage, &env.message.sender, &Lock::default())?;
+
+    reward_pool.inc_token_supply = math::sub(reward_pool.inc_token_supply, user.locked)?;
+    reward_pool.boosted_supply = math::sub(reward_pool.boosted_supply, lock.boosted(user.locked)?)?;
+    TypedStoreMut::attach(&mut deps.storage).store(REWARD_POOL_KEY, &reward_pool)?;
+    checkpoint_total_locked(&mut deps.storage, env.block.height, &reward_pool)?;
+
+    let mut messages = vec![];
+    if user.locked > 0 {
+        messages.push(secret_toolkit::snip20::transfer_msg(
+            env.message.sender.clone(),
+            Uint128(user.locked),
+            None,
+            RESPONSE_BLOCK_SIZE,
+            config.inc_token.contract_hash,
+            config.inc_token.address,
+        )?);
+    }
+
+    let old_locked = user.locked;
+    user = UserInfo { locked: 0, debt: 0 };
+    TypedStoreMut::attach(&mut deps.storage).store(env.message.sender.0.as_bytes(), &user)?;
+
+    update_stakers_count(&mut deps.storage, old_locked, user.locked)?;
+    // Polls aren't notified, so that no subscriber can ever keep a staker from getting their
+    // tokens out. A poll re-reads all of its voters' balances before it is finalized, and one
+    // that counts a snapshot reads the checkpoints recorded here instead
+    record_voting_power(
+        &mut deps.storage,
+        env.block.height,
+        &env.message.sender,
+        old_locked,
+        user.locked,
+    )?;
+
+    Ok(HandleResponse {
+        messages,
+        log: vec![],
+        data: Some(to_binary(&LPStakingHandleAnswer::EmergencyRedeem {
+            status: Success,
+        })?),
+    })
+}
+
+// Query functions
 
 fn query_pending_rewards<S: Storage, A: Api, Q: Querier>(
     deps: &Extern<S, A, Q>,
     address: &HumanAddr,
     block: u64,
 ) -> StdResult<Binary> {
-    let new_rewards = query_pending(deps, block)?;
+    let deadline = load_deadline(&deps.storage)?;
+    let last_allocation_block: u64 =
+        TypedStore::attach(&deps.storage).load(LAST_ALLOCATION_BLOCK_KEY)?;
+    let (new_rewards, _) = split_at_deadline(
+        deadline,
+        last_allocation_block,
+        block,
+        query_pending(deps, block)?,
+    )?;
     let reward_pool = TypedStore::<RewardPool, S>::attach(&deps.storage).load(REWARD_POOL_KEY)?;
     let user = TypedStore::<UserInfo, S>::attach(&deps.storage)
         .load(address.0.as_bytes())
         .unwrap_or(UserInfo { locked: 0, debt: 0 });
+    let stake = load_lock(&deps.storage, address).boosted(user.locked)?;
+    let config: Config = TypedStore::attach(&deps.storage).load(CONFIG_KEY)?;
     let mut acc_reward_per_share = reward_pool.acc_reward_per_share;
 
-    if reward_pool.inc_token_supply != 0 {
-        acc_reward_per_share +=
-            (new_rewards + reward_pool.residue) * REWARD_SCALE / reward_pool.inc_token_supply;
-    }
+    if reward_pool.boosted_supply != 0 {
+        let mut residue = reward_pool.residue;
+        if deadline.map_or(false, |d| block > d) {
+            residue = 0;
+        }
+        acc_reward_per_share = math::add(
+            acc_reward_per_share,
+            reward_per_share(
+                math::add(new_rewards, residue)?,
+                config.reward_scale,
+                reward_pool.boosted_supply,
+            )?,
+        )?;
+    }
+
+    let pending = pending_rewards(stake, acc_reward_per_share, config.reward_scale, user.debt)?;
+    let cut = referral_cut(&deps.storage, address, pending)?.map_or(0, |(_, cut)| cut);
+    // This is not necessarily accurate, since we don't validate new_rewards. It is up to the UI
+    // to display accurate numbers
+    let rewards = math::sub(pending, cut)?;
+
+    let mut extra_rewards: Vec<ExtraReward> =
+        TypedStore::attach(&deps.storage).load(EXTRA_REWARDS_KEY)?;
+    let debts = load_extra_debts(&deps.storage, address, extra_rewards.len());
+    let mut extra_amounts = vec![];
+    for (reward, debt) in extra_rewards.iter_mut().zip(debts) {
+        accrue_extra_reward(
+            reward,
+            block,
+            reward_pool.boosted_supply,
+            config.reward_scale,
+        )?;
+        extra_amounts.push(RewardAmount {
+            token: reward.token.clone(),
+            amount: Uint128(pending_rewards(
+                stake,
+                reward.acc_reward_per_share,
+                config.reward_scale,
+                debt,
+            )?),
+        });
+    }
+
+    to_binary(&LPStakingQueryAnswer::Rewards {
+        rewards: Uint128(rewards),
+        extra_rewards: extra_amounts,
+    })
+}
+
+fn query_deposit<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    address: &HumanAddr,
+) -> StdResult<Binary> {
+    let user = TypedStore::attach(&deps.storage)
+        .load(address.0.as_bytes())
+        .unwrap_or(UserInfo { locked: 0, debt: 0 });
+
+    to_binary(&LPStakingQueryAnswer::Balance {
+        amount: Uint128(user.locked),
+    })
+}
+
+fn query_deposit_at<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    address: &HumanAddr,
+    height: u64,
+) -> StdResult<Binary> {
+    let checkpoints = ReadonlyPrefixedStorage::multilevel(
+        &[BALANCE_CHECKPOINTS_KEY, address.0.as_bytes()],
+        &deps.storage,
+    );
+    let amount: u128 = checkpoint_at(&checkpoints, height)?.unwrap_or(0);
+
+    to_binary(&LPStakingQueryAnswer::Balance {
+        amount: Uint128(amount),
+    })
+}
+
+fn query_delegation<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    address: &HumanAddr,
+    height: Option<u64>,
+) -> StdResult<Binary> {
+    let (delegate, delegated_power) = if let Some(height) = height {
+        let delegate_checkpoints = ReadonlyPrefixedStorage::multilevel(
+            &[DELEGATE_CHECKPOINTS_KEY, address.0.as_bytes()],
+            &deps.storage,
+        );
+        let power_checkpoints = ReadonlyPrefixedStorage::multilevel(
+            &[DELEGATED_POWER_CHECKPOINTS_KEY, address.0.as_bytes()],
+            &deps.storage,
+        );
+
+        (
+            checkpoint_at(&delegate_checkpoints, height)?.unwrap_or(None),
+            checkpoint_at(&power_checkpoints, height)?.unwrap_or(0),
+        )
+    } else {
+        let delegated_store = ReadonlyPrefixedStorage::new(DELEGATED_POWER_KEY, &deps.storage);
+        (
+            load_delegate(&deps.storage, address),
+            TypedStore::attach(&delegated_store)
+                .load(address.0.as_bytes())
+                .unwrap_or(0),
+        )
+    };
+
+    to_binary(&LPStakingQueryAnswer::Delegation {
+        delegate,
+        delegated_power: Uint128(delegated_power),
+    })
+}
+
+fn query_lock<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    address: &HumanAddr,
+) -> StdResult<Binary> {
+    let lock = load_lock(&deps.storage, address);
+
+    to_binary(&LPStakingQueryAnswer::Lock {
+        unlock_block: lock.unlock_block,
+        boost: lock.boost,
+    })
+}
+
+fn query_contract_status<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+) -> StdResult<Binary> {
+    let config: Config = TypedStore::attach(&deps.storage).load(CONFIG_KEY)?;
+
+    to_binary(&LPStakingQueryAnswer::ContractStatus {
+        is_stopped: config.is_stopped,
+    })
+}
+
+fn query_reward_token<S: Storage, A: Api, Q: Querier>(deps: &Extern<S, A, Q>) -> StdResult<Binary> {
+    let config: Config = TypedStore::attach(&deps.storage).load(CONFIG_KEY)?;
+
+    to_binary(&LPStakingQueryAnswer::RewardToken {
+        token: config.reward_token,
+    })
+}
+
+fn query_reward_tokens<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+) -> StdResult<Binary> {
+    let config: Config = TypedStore::attach(&deps.storage).load(CONFIG_KEY)?;
+    let extra_rewards: Vec<ExtraReward> =
+        TypedStore::attach(&deps.storage).load(EXTRA_REWARDS_KEY)?;
+
+    let mut tokens = vec![config.reward_token];
+    tokens.extend(extra_rewards.into_iter().map(|r| r.token));
+
+    to_binary(&LPStakingQueryAnswer::RewardTokens { tokens })
+}
+
+fn query_incentivized_token<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+) -> StdResult<Binary> {
+    let config: Config = TypedStore::attach(&deps.storage).load(CONFIG_KEY)?;
+
+    to_binary(&LPStakingQueryAnswer::IncentivizedToken {
+        token: config.inc_token,
+    })
+}
+
+fn query_total_locked<S: Storage, A: Api, Q: Querier>(deps: &Extern<S, A, Q>) -> StdResult<Binary> {
+    let reward_pool: RewardPool = TypedStore::attach(&deps.storage).load(REWARD_POOL_KEY)?;
+
+    to_binary(&LPStakingQueryAnswer::TotalLocked {
+        amount: Uint128(reward_pool.inc_token_supply),
+    })
+}
+
+fn query_total_locked_at<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    height: u64,
+) -> StdResult<Binary> {
+    let checkpoints = ReadonlyPrefixedStorage::new(TOTAL_LOCKED_CHECKPOINTS_KEY, &deps.storage);
+    let amount: u128 = checkpoint_at(&checkpoints, height)?.unwrap_or(0);
+
+    to_binary(&LPStakingQueryAnswer::TotalLocked {
+        amount: Uint128(amount),
+    })
+}
+
+fn query_pool_stats<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    height: u64,
+) -> StdResult<Binary> {
+    let reward_pool: RewardPool = TypedStore::attach(&deps.storage).load(REWARD_POOL_KEY)?;
+    let stakers: u32 = TypedStore::attach(&deps.storage).load(STAKERS_COUNT_KEY)?;
+
+    let yearly_rewards = query_yearly_rewards(deps, height)?;
+    let mut apr_bps = 0;
+    if reward_pool.inc_token_supply > 0 {
+        apr_bps = math::mul_div(
+            yearly_rewards,
+            10_000,
+            reward_pool.inc_token_supply,
+            Rounding::Down,
+        )?;
+    }
+
+    to_binary(&LPStakingQueryAnswer::PoolStats {
+        total_locked: Uint128(reward_pool.inc_token_supply),
+        boosted_supply: Uint128(reward_pool.boosted_supply),
+        acc_reward_per_share: Uint128(reward_pool.acc_reward_per_share),
+        residue: Uint128(reward_pool.residue),
+        stakers,
+        yearly_rewards: Uint128(yearly_rewards),
+        apr_bps: Uint128(apr_bps),
+    })
+}
+
+fn query_subscribers<S: Storage, A: Api, Q: Querier>(deps: &Extern<S, A, Q>) -> StdResult<Binary> {
+    let contracts: Vec<SecretContract> = TypedStore::attach(&deps.storage).load(SUBSCRIBERS_KEY)?;
+
+    to_binary(&LPStakingQueryAnswer::Subscribers { contracts })
+}
+
+fn query_deadline<S: Storage, A: Api, Q: Querier>(deps: &Extern<S, A, Q>) -> StdResult<Binary> {
+    let deadline = load_deadline(&deps.storage)?;
+
+    to_binary(&LPStakingQueryAnswer::Deadline {
+        deadline,
+        claimable_from: deadline.map(claimable_from).transpose()?,
+    })
+}
+
+fn query_migration_target<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+) -> StdResult<Binary> {
+    let new_pool: Option<SecretContract> =
+        TypedStore::attach(&deps.storage).load(MIGRATION_TARGET_KEY)?;
+
+    to_binary(&LPStakingQueryAnswer::MigrationTarget { new_pool })
+}
+
+fn query_vesting_config<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+) -> StdResult<Binary> {
+    let vesting: Option<VestingConfig> = TypedStore::attach(&deps.storage).load(VESTING_KEY)?;
+
+    to_binary(&LPStakingQueryAnswer::VestingConfig { vesting })
+}
 
-    to_binary(&LPStakingQueryAnswer::Rewards {
-        // This is not necessarily accurate, since we don't validate new_rewards. It is up to
-        // the UI to display accurate numbers
-        rewards: Uint128(user.locked * acc_reward_per_share / REWARD_SCALE - user.debt),
+fn query_vesting<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    address: &HumanAddr,
+    height: u64,
+) -> StdResult<Binary> {
+    let vesting = load_vesting(&deps.storage, address);
+    let unlocked = vesting.unlocked_at(height)?;
+
+    to_binary(&LPStakingQueryAnswer::Vesting {
+        vested: Uint128(math::add(vesting.claimable, unlocked)?),
+        locked: Uint128(math::sub(vesting.locked, unlocked)?),
     })
 }
 
-fn query_deposit<S: Storage, A: Api, Q: Querier>(
+fn query_referral_rate<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+) -> StdResult<Binary> {
+    let rate_bps: u64 = TypedStore::attach(&deps.storage).load(REFERRAL_RATE_KEY)?;
+
+    to_binary(&LPStakingQueryAnswer::ReferralRate { rate_bps })
+}
+
+fn query_referral_rewards<S: Storage, A: Api, Q: Querier>(
     deps: &Extern<S, A, Q>,
     address: &HumanAddr,
 ) -> StdResult<Binary> {
-    let user = TypedStore::attach(&deps.storage)
+    let rewards_store = ReadonlyPrefixedStorage::new(REFERRAL_REWARDS_KEY, &deps.storage);
+    let amount: u128 = TypedStore::attach(&rewards_store)
         .load(address.0.as_bytes())
-        .unwrap_or(UserInfo { locked: 0, debt: 0 });
+        .unwrap_or(0); // NotFound is the only possible error
 
-    to_binary(&LPStakingQueryAnswer::Balance {
-        amount: Uint128(user.locked),
+    to_binary(&LPStakingQueryAnswer::ReferralRewards {
+        amount: Uint128(amount),
     })
 }
 
-fn query_contract_status<S: Storage, A: Api, Q: Querier>(
+fn query_redeem_fee<S: Storage, A: Api, Q: Querier>(deps: &Extern<S, A, Q>) -> StdResult<Binary> {
+    let fee: Option<RedeemFee> = TypedStore::attach(&deps.storage).load(REDEEM_FEE_KEY)?;
+
+    to_binary(&LPStakingQueryAnswer::RedeemFee { fee })
+}
+
+fn query_fee_exempt<S: Storage, A: Api, Q: Querier>(deps: &Extern<S, A, Q>) -> StdResult<Binary> {
+    let contracts: Vec<HumanAddr> = TypedStore::attach(&deps.storage).load(FEE_EXEMPT_KEY)?;
+
+    to_binary(&LPStakingQueryAnswer::FeeExempt { contracts })
+}
+
+fn query_trusted_contracts<S: Storage, A: Api, Q: Querier>(
     deps: &Extern<S, A, Q>,
 ) -> StdResult<Binary> {
-    let config: Config = TypedStore::attach(&deps.storage).load(CONFIG_KEY)?;
+    let contracts: Vec<HumanAddr> =
+        TypedStore::attach(&deps.storage).load(TRUSTED_CONTRACTS_KEY)?;
 
-    to_binary(&LPStakingQueryAnswer::ContractStatus {
-        is_stopped: config.is_stopped,
-    })
+    to_binary(&LPStakingQueryAnswer::TrustedContracts { contracts })
 }
 
-fn query_reward_token<S: Storage, A: Api, Q: Querier>(deps: &Extern<S, A, Q>) -> StdResult<Binary> {
-    let config: Config = TypedStore::attach(&deps.storage).load(CONFIG_KEY)?;
+/// Queries don't have a sender, so a trusted contract proves who it is with its own viewing key,
+/// which it sets like any other address would
+fn query_staker_balance<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    contract: &HumanAddr,
+    staker: &HumanAddr,
+    height: Option<u64>,
+) -> StdResult<Binary> {
+    enforce_trusted(deps, contract)?;
 
-    to_binary(&LPStakingQueryAnswer::RewardToken {
-        token: config.reward_token,
-    })
+    match height {
+        Some(height) => query_deposit_at(deps, staker, height),
+        None => query_deposit(deps, staker),
+    }
 }
 
-fn query_incentivized_token<S: Storage, A: Api, Q: Querier>(
+fn query_staker_delegation<S: Storage, A: Api, Q: Querier>(
     deps: &Extern<S, A, Q>,
+    contract: &HumanAddr,
+    staker: &HumanAddr,
+    height: Option<u64>,
 ) -> StdResult<Binary> {
-    let config: Config = TypedStore::attach(&deps.storage).load(CONFIG_KEY)?;
+    enforce_trusted(deps, contract)?;
 
-    to_binary(&LPStakingQueryAnswer::IncentivizedToken {
-        token: config.inc_token,
-    })
+    query_delegation(deps, staker, height)
+}
+
+fn query_lock_tiers<S: Storage, A: Api, Q: Querier>(deps: &Extern<S, A, Q>) -> StdResult<Binary> {
+    let tiers: Vec<LockTier> = TypedStore::attach(&deps.storage).load(LOCK_TIERS_KEY)?;
+
+    to_binary(&LPStakingQueryAnswer::LockTiers { tiers })
 }
 
 // This is only for Keplr support (Viewing Keys)
@@ -613,34 +2269,735 @@ fn enforce_admin(config: Config, env: Env) -> StdResult<()> {
         )));
     }
 
-    Ok(())
+    Ok(())
+}
+
+fn enforce_trusted<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    contract: &HumanAddr,
+) -> StdResult<()> {
+    if !is_trusted(&deps.storage, contract)? {
+        return Err(StdError::unauthorized());
+    }
+
+    Ok(())
+}
+
+fn is_trusted<S: ReadonlyStorage>(storage: &S, contract: &HumanAddr) -> StdResult<bool> {
+    let trusted: Vec<HumanAddr> = TypedStore::attach(storage).load(TRUSTED_CONTRACTS_KEY)?;
+    Ok(trusted.contains(contract))
+}
+
+fn enforce_admin_or_poll_factory<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    env: &Env,
+) -> StdResult<()> {
+    let config: Config = TypedStore::attach(&deps.storage).load(CONFIG_KEY)?;
+    let poll_factory: Option<HumanAddr> =
+        TypedStore::attach(&deps.storage).load(POLL_FACTORY_KEY)?;
+
+    if config.admin != env.message.sender && poll_factory != Some(env.message.sender.clone()) {
+        return Err(StdError::generic_err(format!(
+            "not an admin or a poll factory: {}",
+            env.message.sender
+        )));
+    }
+
+    Ok(())
+}
+
+/// Records a staker's new balance, and lets every subscribed poll know about it so that an
+/// existing vote's weight (and the weight of the staker's delegate) follows the staker's stake
+fn update_voting_power<S: Storage>(
+    storage: &mut S,
+    height: u64,
+    staker: HumanAddr,
+    old_power: u128,
+    new_power: u128,
+) -> StdResult<Vec<CosmosMsg>> {
+    let delegate = record_voting_power(storage, height, &staker, old_power, new_power)?;
+
+    notify_subscribers(
+        storage,
+        PollHandleMsg::UpdateVotingPower {
+            voter: staker,
+            old_power: Uint128(old_power),
+            new_power: Uint128(new_power),
+            delegate,
+        },
+    )
+}
+
+/// Records a staker's new balance and their delegate's new delegated power, and returns the
+/// delegate
+fn record_voting_power<S: Storage>(
+    storage: &mut S,
+    height: u64,
+    staker: &HumanAddr,
+    old_power: u128,
+    new_power: u128,
+) -> StdResult<Option<HumanAddr>> {
+    push_checkpoint(
+        &mut PrefixedStorage::multilevel(&[BALANCE_CHECKPOINTS_KEY, staker.0.as_bytes()], storage),
+        height,
+        new_power,
+    )?;
+
+    let delegate = load_delegate(storage, staker);
+    if let Some(delegate) = &delegate {
+        update_delegated_power(storage, height, delegate, old_power, new_power)?;
+    }
+
+    Ok(delegate)
+}
+
+fn notify_subscribers<S: ReadonlyStorage>(
+    storage: &S,
+    msg: PollHandleMsg,
+) -> StdResult<Vec<CosmosMsg>> {
+    let subscribers: Vec<SecretContract> = TypedStore::attach(storage).load(SUBSCRIBERS_KEY)?;
+
+    let mut messages = vec![];
+    for subscriber in subscribers {
+        messages.push(
+            WasmMsg::Execute {
+                contract_addr: subscriber.address,
+                callback_code_hash: subscriber.contract_hash,
+                msg: to_binary(&msg)?,
+                send: vec![],
+            }
+            .into(),
+        );
+    }
+
+    Ok(messages)
+}
+
+fn load_delegate<S: ReadonlyStorage>(storage: &S, delegator: &HumanAddr) -> Option<HumanAddr> {
+    let delegations_store = ReadonlyPrefixedStorage::new(DELEGATIONS_KEY, storage);
+    TypedStore::attach(&delegations_store)
+        .load(delegator.0.as_bytes())
+        .unwrap_or(None) // NotFound is the only possible error
+}
+
+/// Replaces `old_power` with `new_power` in the total power that was delegated to `delegate`
+fn update_delegated_power<S: Storage>(
+    storage: &mut S,
+    height: u64,
+    delegate: &HumanAddr,
+    old_power: u128,
+    new_power: u128,
+) -> StdResult<()> {
+    let mut delegated_store = PrefixedStorage::new(DELEGATED_POWER_KEY, storage);
+    let mut delegated_store = TypedStoreMut::<u128, _>::attach(&mut delegated_store);
+    let delegated_power = math::sub(
+        math::add(
+            delegated_store.load(delegate.0.as_bytes()).unwrap_or(0),
+            new_power,
+        )?,
+        old_power,
+    )?;
+    delegated_store.store(delegate.0.as_bytes(), &delegated_power)?;
+
+    push_checkpoint(
+        &mut PrefixedStorage::multilevel(
+            &[DELEGATED_POWER_CHECKPOINTS_KEY, delegate.0.as_bytes()],
+            storage,
+        ),
+        height,
+        delegated_power,
+    )
+}
+
+fn checkpoint_total_locked<S: Storage>(
+    storage: &mut S,
+    height: u64,
+    reward_pool: &RewardPool,
+) -> StdResult<()> {
+    push_checkpoint(
+        &mut PrefixedStorage::new(TOTAL_LOCKED_CHECKPOINTS_KEY, storage),
+        height,
+        reward_pool.inc_token_supply,
+    )
+}
+
+/// Keeps count of the addresses that have anything staked
+fn update_stakers_count<S: Storage>(
+    storage: &mut S,
+    old_locked: u128,
+    new_locked: u128,
+) -> StdResult<()> {
+    let mut count_store = TypedStoreMut::<u32, S>::attach(storage);
+    let count = count_store.load(STAKERS_COUNT_KEY)?;
+
+    if old_locked == 0 && new_locked > 0 {
+        let count = count
+            .checked_add(1)
+            .ok_or_else(|| StdError::generic_err("overflow: stakers count"))?;
+        count_store.store(STAKERS_COUNT_KEY, &count)
+    } else if old_locked > 0 && new_locked == 0 {
+        let count = count
+            .checked_sub(1)
+            .ok_or_else(|| StdError::generic_err("underflow: stakers count"))?;
+        count_store.store(STAKERS_COUNT_KEY, &count)
+    } else {
+        Ok(())
+    }
+}
+
+fn update_rewards<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    height: u64,
+    newly_allocated: u128,
+) -> StdResult<RewardPool> {
+    let deadline = load_deadline(&deps.storage)?;
+    let scale = load_reward_scale(&deps.storage)?;
+    let mut last_allocation_store = TypedStoreMut::<u64, S>::attach(&mut deps.storage);
+    let last_allocation_block = last_allocation_store.load(LAST_ALLOCATION_BLOCK_KEY)?;
+    last_allocation_store.store(LAST_ALLOCATION_BLOCK_KEY, &height)?;
+
+    let mut rewards_store = TypedStoreMut::attach(&mut deps.storage);
+    let mut reward_pool: RewardPool = rewards_store.load(REWARD_POOL_KEY)?;
+
+    // If there's no new allocation - there is nothing to update because the state of the pool stays the same
+    if newly_allocated <= 0 {
+        return Ok(reward_pool);
+    }
+
+    if reward_pool.boosted_supply == 0 {
+        reward_pool.residue = math::add(reward_pool.residue, newly_allocated)?;
+        rewards_store.store(REWARD_POOL_KEY, &reward_pool)?;
+        return Ok(reward_pool);
+    }
+
+    // Whatever was allocated for the blocks after the deadline is held back in the residue, which
+    // isn't handed out anymore once the pool has ended
+    if deadline.map_or(false, |d| height > d) {
+        let (newly_allocated, held_back) =
+            split_at_deadline(deadline, last_allocation_block, height, newly_allocated)?;
+        reward_pool.acc_reward_per_share = math::add(
+            reward_pool.acc_reward_per_share,
+            reward_per_share(newly_allocated, scale, reward_pool.boosted_supply)?,
+        )?;
+        reward_pool.residue = math::add(reward_pool.residue, held_back)?;
+        rewards_store.store(REWARD_POOL_KEY, &reward_pool)?;
+        return Ok(reward_pool);
+    }
+
+    // Effectively distributes the residue to the first one that stakes to an empty pool
+    reward_pool.acc_reward_per_share = math::add(
+        reward_pool.acc_reward_per_share,
+        reward_per_share(
+            math::add(newly_allocated, reward_pool.residue)?,
+            scale,
+            reward_pool.boosted_supply,
+        )?,
+    )?;
+    reward_pool.residue = 0;
+    rewards_store.store(REWARD_POOL_KEY, &reward_pool)?;
+
+    Ok(reward_pool)
+}
+
+/// Brings every extra reward's accumulator up to `height`
+fn update_extra_rewards<S: Storage>(
+    storage: &mut S,
+    height: u64,
+    boosted_supply: u128,
+    scale: u128,
+) -> StdResult<Vec<ExtraReward>> {
+    let mut extra_rewards_store = TypedStoreMut::attach(storage);
+    let mut extra_rewards: Vec<ExtraReward> = extra_rewards_store.load(EXTRA_REWARDS_KEY)?;
+    for reward in extra_rewards.iter_mut() {
+        accrue_extra_reward(reward, height, boosted_supply, scale)?;
+    }
+    extra_rewards_store.store(EXTRA_REWARDS_KEY, &extra_rewards)?;
+
+    Ok(extra_rewards)
+}
+
+fn accrue_extra_reward(
+    reward: &mut ExtraReward,
+    height: u64,
+    boosted_supply: u128,
+    scale: u128,
+) -> StdResult<()> {
+    let until = height.min(reward.end_block);
+    let mut newly_allocated = 0;
+    if until > reward.last_update_block {
+        newly_allocated = math::mul(
+            (until - reward.last_update_block) as u128,
+            reward.reward_per_block,
+        )?;
+        // What didn't divide evenly among the blocks is paid out with the last one
+        if until == reward.end_block {
+            newly_allocated = math::add(newly_allocated, reward.remainder)?;
+            reward.remainder = 0;
+        }
+    }
+    reward.last_update_block = reward.last_update_block.max(height);
+
+    // Same as the master's rewards, the residue goes to the first one that stakes to an empty pool
+    if boosted_supply == 0 {
+        reward.residue = math::add(reward.residue, newly_allocated)?;
+    } else {
+        reward.acc_reward_per_share = math::add(
+            reward.acc_reward_per_share,
+            reward_per_share(
+                math::add(newly_allocated, reward.residue)?,
+                scale,
+                boosted_supply,
+            )?,
+        )?;
+        reward.residue = 0;
+    }
+
+    Ok(())
+}
+
+/// Pays a staker's pending extra rewards to `recipient` and resets their debts to their new
+/// (boosted) stake. Must be called before `boosted_supply` changes
+fn claim_extra_rewards<S: Storage>(
+    storage: &mut S,
+    height: u64,
+    boosted_supply: u128,
+    staker: &HumanAddr,
+    recipient: &HumanAddr,
+    old_stake: u128,
+    new_stake: u128,
+) -> StdResult<Vec<CosmosMsg>> {
+    let scale = load_reward_scale(storage)?;
+    let extra_rewards = update_extra_rewards(storage, height, boosted_supply, scale)?;
+    let debts = load_extra_debts(storage, staker, extra_rewards.len());
+
+    let mut messages = vec![];
+    for (reward, debt) in extra_rewards.iter().zip(debts) {
+        let pending = pending_rewards(old_stake, reward.acc_reward_per_share, scale, debt)?;
+        if pending > 0 {
+            messages.push(secret_toolkit::snip20::transfer_msg(
+                recipient.clone(),
+                Uint128(pending),
+                None,
+                RESPONSE_BLOCK_SIZE,
+                reward.token.contract_hash.clone(),
+                reward.token.address.clone(),
+            )?);
+        }
+    }
+
+    store_extra_debts(storage, staker, new_stake, &extra_rewards, scale)?;
+
+    Ok(messages)
+}
+
+/// Tokens that were added after the staker's last update have no debt yet
+fn load_extra_debts<S: ReadonlyStorage>(storage: &S, staker: &HumanAddr, len: usize) -> Vec<u128> {
+    let debts_store = ReadonlyPrefixedStorage::new(EXTRA_DEBTS_KEY, storage);
+    let mut debts: Vec<u128> = TypedStore::attach(&debts_store)
+        .load(staker.0.as_bytes())
+        .unwrap_or_default();
+    debts.resize(len, 0);
+
+    debts
+}
+
+fn store_extra_debts<S: Storage>(
+    storage: &mut S,
+    staker: &HumanAddr,
+    stake: u128,
+    extra_rewards: &[ExtraReward],
+    scale: u128,
+) -> StdResult<()> {
+    let debts = extra_rewards
+        .iter()
+        .map(|r| accrued_rewards(stake, r.acc_reward_per_share, scale))
+        .collect::<StdResult<Vec<u128>>>()?;
+
+    let mut debts_store = PrefixedStorage::new(EXTRA_DEBTS_KEY, storage);
+    TypedStoreMut::attach(&mut debts_store).store(staker.0.as_bytes(), &debts)
+}
+
+/// Pays a staker's pending rewards, and sets their balance to `new_locked`. An expired lock stops
+/// boosting the stake along the way. `reward_pool` must be stored afterwards
+fn restake<S: Storage>(
+    storage: &mut S,
+    config: &Config,
+    reward_pool: &mut RewardPool,
+    height: u64,
+    staker: &HumanAddr,
+    user: &mut UserInfo,
+    new_locked: u128,
+) -> StdResult<Vec<CosmosMsg>> {
+    let old_lock = load_lock(storage, staker);
+
+    let mut messages: Vec<CosmosMsg> = vec![];
+    let old_stake = old_lock.boosted(user.locked)?;
+    let pending = pending_rewards(
+        old_stake,
+        reward_pool.acc_reward_per_share,
+        config.reward_scale,
+        user.debt,
+    )?;
+    let pending = pay_referrer(storage, staker, pending)?;
+    messages.extend(harvest(storage, config, staker, staker, pending, height)?);
+
+    let old_locked = user.locked;
+    user.locked = new_locked;
+    let new_lock = old_lock.at(height);
+    let new_stake = new_lock.boosted(user.locked)?;
+    user.debt = accrued_rewards(
+        new_stake,
+        reward_pool.acc_reward_per_share,
+        config.reward_scale,
+    )?;
+    TypedStoreMut::<UserInfo, S>::attach(storage).store(staker.0.as_bytes(), user)?;
+    store_lock(storage, staker, &new_lock)?;
+
+    messages.extend(claim_extra_rewards(
+        storage,
+        height,
+        reward_pool.boosted_supply,
+        staker,
+        staker,
+        old_stake,
+        new_stake,
+    )?);
+    reward_pool.boosted_supply =
+        math::sub(math::add(reward_pool.boosted_supply, new_stake)?, old_stake)?;
+
+    update_stakers_count(storage, old_locked, user.locked)?;
+    messages.extend(update_voting_power(
+        storage,
+        height,
+        staker.clone(),
+        old_locked,
+        user.locked,
+    )?);
+
+    Ok(messages)
+}
+
+fn load_lock<S: ReadonlyStorage>(storage: &S, staker: &HumanAddr) -> Lock {
+    let locks_store = ReadonlyPrefixedStorage::new(LOCKS_KEY, storage);
+    TypedStore::attach(&locks_store)
+        .load(staker.0.as_bytes())
+        .unwrap_or_default() // NotFound is the only possible error
+}
+
+fn store_lock<S: Storage>(storage: &mut S, staker: &HumanAddr, lock: &Lock) -> StdResult<()> {
+    let mut locks_store = PrefixedStorage::new(LOCKS_KEY, storage);
+    TypedStoreMut::attach(&mut locks_store).store(staker.0.as_bytes(), lock)
+}
+
+fn load_vesting<S: ReadonlyStorage>(storage: &S, owner: &HumanAddr) -> Vesting {
+    let vestings_store = ReadonlyPrefixedStorage::new(VESTINGS_KEY, storage);
+    TypedStore::attach(&vestings_store)
+        .load(owner.0.as_bytes())
+        .unwrap_or_default() // NotFound is the only possible error
+}
+
+fn store_vesting<S: Storage>(
+    storage: &mut S,
+    owner: &HumanAddr,
+    vesting: &Vesting,
+) -> StdResult<()> {
+    let mut vestings_store = PrefixedStorage::new(VESTINGS_KEY, storage);
+    TypedStoreMut::attach(&mut vestings_store).store(owner.0.as_bytes(), vesting)
+}
+
+/// Pays out `owner`'s harvested rewards to `recipient`. When vesting is on they're added to the
+/// owner's own vesting schedule instead, so a claim directed elsewhere never vests for someone else
+fn harvest<S: Storage>(
+    storage: &mut S,
+    config: &Config,
+    owner: &HumanAddr,
+    recipient: &HumanAddr,
+    amount: u128,
+    height: u64,
+) -> StdResult<Vec<CosmosMsg>> {
+    if amount == 0 {
+        return Ok(vec![]);
+    }
+
+    let vesting_config: Option<VestingConfig> = TypedStore::attach(storage).load(VESTING_KEY)?;
+    if let Some(vesting_config) = vesting_config {
+        let mut vesting = load_vesting(storage, owner);
+        vesting.vest(amount, height, vesting_config.period)?;
+        store_vesting(storage, owner, &vesting)?;
+
+        return Ok(vec![]);
+    }
+
+    Ok(vec![snip20::transfer_msg(
+        recipient.clone(),
+        Uint128(amount),
+        None,
+        RESPONSE_BLOCK_SIZE,
+        config.reward_token.contract_hash.clone(),
+        config.reward_token.address.clone(),
+    )?])
+}
+
+/// Shares `amount` among the current stakers, the same way as an allocation from the master
+fn redistribute<S: Storage>(storage: &mut S, scale: u128, amount: u128) -> StdResult<()> {
+    let mut rewards_store = TypedStoreMut::<RewardPool, S>::attach(storage);
+    let mut reward_pool = rewards_store.load(REWARD_POOL_KEY)?;
+    if reward_pool.boosted_supply == 0 {
+        reward_pool.residue = math::add(reward_pool.residue, amount)?;
+    } else {
+        reward_pool.acc_reward_per_share = math::add(
+            reward_pool.acc_reward_per_share,
+            reward_per_share(amount, scale, reward_pool.boosted_supply)?,
+        )?;
+    }
+
+    rewards_store.store(REWARD_POOL_KEY, &reward_pool)
+}
+
+fn load_referrer<S: ReadonlyStorage>(storage: &S, staker: &HumanAddr) -> Option<HumanAddr> {
+    let referrers_store = ReadonlyPrefixedStorage::new(REFERRERS_KEY, storage);
+    TypedStore::attach(&referrers_store)
+        .load(staker.0.as_bytes())
+        .ok() // NotFound is the only possible error
+}
+
+/// The staker's referrer and their share of `pending`, if the staker was referred at all
+fn referral_cut<S: ReadonlyStorage>(
+    storage: &S,
+    staker: &HumanAddr,
+    pending: u128,
+) -> StdResult<Option<(HumanAddr, u128)>> {
+    let referrer = match load_referrer(storage, staker) {
+        Some(referrer) => referrer,
+        None => return Ok(None),
+    };
+
+    let rate_bps: u64 = TypedStore::attach(storage).load(REFERRAL_RATE_KEY)?;
+    let cut = math::mul_div(pending, rate_bps as u128, 10_000, Rounding::Down)?;
+
+    Ok(Some((referrer, cut)))
+}
+
+/// Carves the referrer's share out of a staker's rewards and credits it to them. Returns what's left
+/// for the staker
+fn pay_referrer<S: Storage>(storage: &mut S, staker: &HumanAddr, pending: u128) -> StdResult<u128> {
+    let (referrer, cut) = match referral_cut(storage, staker, pending)? {
+        Some((referrer, cut)) if cut > 0 => (referrer, cut),
+        _ => return Ok(pending),
+    };
+
+    let mut referral_store = PrefixedStorage::new(REFERRAL_REWARDS_KEY, storage);
+    let mut rewards_store = TypedStoreMut::<u128, _>::attach(&mut referral_store);
+    let earned = rewards_store.load(referrer.0.as_bytes()).unwrap_or(0); // NotFound is the only possible error
+    rewards_store.store(referrer.0.as_bytes(), &math::add(earned, cut)?)?;
+
+    math::sub(pending, cut)
+}
+
+fn is_fee_exempt<S: ReadonlyStorage>(storage: &S, staker: &HumanAddr) -> StdResult<bool> {
+    let exempt: Vec<HumanAddr> = TypedStore::attach(storage).load(FEE_EXEMPT_KEY)?;
+    Ok(exempt.contains(staker))
+}
+
+fn load_last_deposit<S: ReadonlyStorage>(storage: &S, staker: &HumanAddr) -> u64 {
+    let deposits_store = ReadonlyPrefixedStorage::new(LAST_DEPOSITS_KEY, storage);
+    TypedStore::attach(&deposits_store)
+        .load(staker.0.as_bytes())
+        .unwrap_or(0) // NotFound is the only possible error
+}
+
+fn store_last_deposit<S: Storage>(
+    storage: &mut S,
+    staker: &HumanAddr,
+    height: u64,
+) -> StdResult<()> {
+    let mut deposits_store = PrefixedStorage::new(LAST_DEPOSITS_KEY, storage);
+    TypedStoreMut::attach(&mut deposits_store).store(staker.0.as_bytes(), &height)
+}
+
+/// Adds `amount`, last deposited at `last_deposit`, to the `locked` stake of `staker`. Their last
+/// deposit becomes the amount-weighted average of the two
+fn weigh_last_deposit<S: Storage>(
+    storage: &mut S,
+    staker: &HumanAddr,
+    locked: u128,
+    amount: u128,
+    last_deposit: u64,
+) -> StdResult<()> {
+    let total = math::add(locked, amount)?;
+    if total == 0 {
+        return Ok(());
+    }
+
+    let weighted = math::add(
+        math::mul(locked, load_last_deposit(&*storage, staker) as u128)?,
+        math::mul(amount, last_deposit as u128)?,
+    )?;
+    // Rounding up, so that the fee never ends earlier than the weights say
+    let last_deposit = math::to_u64(math::div(weighted, total, Rounding::Up)?)?;
+    store_last_deposit(storage, staker, last_deposit)
+}
+
+/// The fee for redeeming `amount` at `height`, and the treasury it goes to
+fn redeem_fee<S: ReadonlyStorage>(
+    storage: &S,
+    staker: &HumanAddr,
+    amount: u128,
+    height: u64,
+) -> StdResult<Option<(u128, SecretContract)>> {
+    let fee: Option<RedeemFee> = TypedStore::attach(storage).load(REDEEM_FEE_KEY)?;
+    let fee = match fee {
+        Some(fee) if !is_fee_exempt(storage, staker)? => fee,
+        _ => return Ok(None),
+    };
+
+    let elapsed = height.saturating_sub(load_last_deposit(storage, staker));
+    if elapsed >= fee.decay_period {
+        return Ok(None);
+    }
+
+    // Rounding down, so that the fee never takes more than the advertised rate
+    let remaining = (fee.decay_period - elapsed) as u128;
+    let amount = math::mul_div(
+        math::mul(amount, fee.rate_bps as u128)?,
+        remaining,
+        math::mul(10_000, fee.decay_period as u128)?,
+        Rounding::Down,
+    )?;
+    if amount == 0 {
+        return Ok(None);
+    }
+
+    Ok(Some((amount, fee.treasury)))
+}
+
+fn load_lock_tier<S: ReadonlyStorage>(storage: &S, period: u64) -> StdResult<LockTier> {
+    let tiers: Vec<LockTier> = TypedStore::attach(storage).load(LOCK_TIERS_KEY)?;
+    tiers
+        .into_iter()
+        .find(|t| t.period == period)
+        .ok_or_else(|| StdError::generic_err(format!("there is no lock tier of {} blocks", period)))
+}
+
+/// Returns a staker's lock after they deposit. Depositing into an active lock locks the whole
+/// balance again, for the longer of the active lock's period and `lock_period`
+fn extend_lock<S: ReadonlyStorage>(
+    storage: &S,
+    lock: &Lock,
+    height: u64,
+    lock_period: Option<u64>,
+) -> StdResult<Lock> {
+    let mut lock = lock.at(height);
+    if let Some(period) = lock_period {
+        let tier = load_lock_tier(storage, period)?;
+        if tier.period >= lock.period {
+            lock.period = tier.period;
+            lock.boost = tier.boost;
+        }
+    }
+
+    if lock.period > 0 {
+        lock.unlock_block = lock.unlock_block.max(height + lock.period);
+    }
+
+    Ok(lock)
+}
+
+fn validate_reward_scale(scale: u128, decimals: u8) -> StdResult<()> {
+    let mut rest = scale;
+    while rest > 1 && rest % 10 == 0 {
+        rest /= 10;
+    }
+    if rest != 1 {
+        return Err(StdError::generic_err(
+            "the reward scale must be a power of 10",
+        ));
+    }
+
+    // A smaller scale rounds away the rewards of the token's smallest units
+    let min_scale = min_reward_scale(decimals)?;
+    if scale < min_scale || scale > MAX_REWARD_SCALE {
+        return Err(StdError::generic_err(format!(
+            "the reward scale must be between {} and {} for a token with {} decimals",
+            min_scale, MAX_REWARD_SCALE, decimals
+        )));
+    }
+
+    Ok(())
+}
+
+fn min_reward_scale(decimals: u8) -> StdResult<u128> {
+    10u128.checked_pow(decimals as u32).ok_or_else(|| {
+        StdError::generic_err(format!(
+            "tokens with {} decimals are not supported",
+            decimals
+        ))
+    })
+}
+
+fn load_reward_scale<S: ReadonlyStorage>(storage: &S) -> StdResult<u128> {
+    let config: Config = TypedStore::attach(storage).load(CONFIG_KEY)?;
+    Ok(config.reward_scale)
+}
+
+/// What a stake has earned since the pool started, before subtracting its debt
+fn accrued_rewards(stake: u128, acc_reward_per_share: u128, scale: u128) -> StdResult<u128> {
+    // Rounding down for both the rewards and the debt means a staker can never claim more than what
+    // was allocated to the pool
+    math::mul_div(stake, acc_reward_per_share, scale, Rounding::Down)
+}
+
+fn pending_rewards(
+    stake: u128,
+    acc_reward_per_share: u128,
+    scale: u128,
+    debt: u128,
+) -> StdResult<u128> {
+    math::sub(accrued_rewards(stake, acc_reward_per_share, scale)?, debt)
+}
+
+/// How much `acc_reward_per_share` grows when `amount` is shared by `supply`
+fn reward_per_share(amount: u128, scale: u128, supply: u128) -> StdResult<u128> {
+    math::mul_div(amount, scale, supply, Rounding::Down)
 }
 
-fn update_rewards<S: Storage, A: Api, Q: Querier>(
-    deps: &mut Extern<S, A, Q>,
-    newly_allocated: u128,
-) -> StdResult<RewardPool> {
-    let mut rewards_store = TypedStoreMut::attach(&mut deps.storage);
-    let mut reward_pool: RewardPool = rewards_store.load(REWARD_POOL_KEY)?;
+/// Splits an allocation for the blocks since `last_block` into what was earned until the
+/// deadline, and what was earned after it
+fn split_at_deadline(
+    deadline: Option<u64>,
+    last_block: u64,
+    height: u64,
+    amount: u128,
+) -> StdResult<(u128, u128)> {
+    match deadline {
+        Some(deadline) if height > deadline => {
+            if last_block >= deadline {
+                return Ok((0, amount));
+            }
 
-    // If there's no new allocation - there is nothing to update because the state of the pool stays the same
-    if newly_allocated <= 0 {
-        return Ok(reward_pool);
+            let earned = math::mul_div(
+                amount,
+                (deadline - last_block) as u128,
+                (height - last_block) as u128,
+                Rounding::Down,
+            )?;
+            Ok((earned, math::sub(amount, earned)?))
+        }
+        _ => Ok((amount, 0)),
     }
+}
 
-    if reward_pool.inc_token_supply == 0 {
-        reward_pool.residue += newly_allocated;
-        rewards_store.store(REWARD_POOL_KEY, &reward_pool)?;
-        return Ok(reward_pool);
-    }
+fn load_deadline<S: ReadonlyStorage>(storage: &S) -> StdResult<Option<u64>> {
+    TypedStore::attach(storage).load(DEADLINE_KEY)
+}
 
-    // Effectively distributes the residue to the first one that stakes to an empty pool
-    reward_pool.acc_reward_per_share +=
-        (newly_allocated + reward_pool.residue) * REWARD_SCALE / reward_pool.inc_token_supply;
-    reward_pool.residue = 0;
-    rewards_store.store(REWARD_POOL_KEY, &reward_pool)?;
+fn has_ended<S: ReadonlyStorage>(storage: &S, height: u64) -> StdResult<bool> {
+    Ok(load_deadline(storage)?.map_or(false, |d| height > d))
+}
 
-    Ok(reward_pool)
+/// The block from which the admin can claim whatever stakers left in the pool after `deadline`
+fn claimable_from(deadline: u64) -> StdResult<u64> {
+    math::to_u64(math::add(deadline as u128, SUNSET_GRACE_PERIOD as u128)?)
 }
 
 fn update_allocation(env: Env, config: Config, hook: Option<Binary>) -> StdResult<HandleResponse> {
@@ -664,30 +3021,74 @@ fn update_allocation(env: Env, config: Config, hook: Option<Binary>) -> StdResul
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::msg::LPStakingHandleMsg::{Receive, Redeem, SetViewingKey};
-    use crate::msg::LPStakingQueryMsg::{Deposit, Rewards};
-    use crate::msg::LPStakingReceiveMsg;
-    use crate::state::SecretContract;
-    use cosmwasm_std::testing::{
-        mock_dependencies, MockApi, MockQuerier, MockStorage, MOCK_CONTRACT_ADDR,
-    };
+    use cosmwasm_std::testing::{MockApi, MockStorage, MOCK_CONTRACT_ADDR};
     use cosmwasm_std::{
-        coins, from_binary, BlockInfo, Coin, ContractInfo, Empty, MessageInfo, StdError, WasmMsg,
+        from_slice, BlockInfo, Coin, ContractInfo, Empty, MessageInfo, QuerierResult, QueryRequest,
+        WasmQuery,
     };
     use rand::Rng;
-    use scrt_finance::lp_staking_msg::LPStakingHandleAnswer;
-    use scrt_finance::master_msg::LPStakingHandleMsg::SetViewingKey;
+    use scrt_finance::master_msg::{MasterQueryAnswer, MasterQueryMsg};
     use serde::{Deserialize, Serialize};
 
+    type TestDeps = Extern<MockStorage, MockApi, PoolQuerier>;
+
+    /// Answers the only queries the pool makes: the incentivized token's info, and the master's
+    /// pending allocation, which grows by `reward_per_block` every block
+    struct PoolQuerier {
+        decimals: u8,
+        reward_per_block: u128,
+    }
+
+    impl Querier for PoolQuerier {
+        fn raw_query(&self, bin_request: &[u8]) -> QuerierResult {
+            let request: QueryRequest<Empty> = from_slice(bin_request).unwrap();
+            let (contract_addr, msg) = match request {
+                QueryRequest::Wasm(WasmQuery::Smart {
+                    contract_addr, msg, ..
+                }) => (contract_addr, msg),
+                other => panic!("Unexpected query: {:?}", other),
+            };
+
+            let answer = match contract_addr.0.as_str() {
+                "eth" => Binary::from(
+                    format!(
+                        r#"{{"token_info":{{"name":"eth","symbol":"ETH","decimals":{},"total_supply":null}}}}"#,
+                        self.decimals
+                    )
+                    .as_bytes(),
+                ),
+                "master" => match from_binary(&msg).unwrap() {
+                    MasterQueryMsg::Pending { block, .. } => to_binary(&MasterQueryAnswer::Pending {
+                        amount: Uint128(self.reward_per_block * block as u128),
+                    })
+                    .unwrap(),
+                    other => panic!("Unexpected master query: {:?}", other),
+                },
+                other => panic!("Unexpected query to {}", other),
+            };
+
+            Ok(Ok(answer))
+        }
+    }
+
     // Helper functions
 
-    fn init_helper(
-        deadline: u64,
-    ) -> (
-        StdResult<InitResponse>,
-        Extern<MockStorage, MockApi, MockQuerier>,
-    ) {
-        let mut deps = mock_dependencies(20, &[]);
+    fn init_helper() -> (StdResult<InitResponse>, TestDeps) {
+        init_helper_with(6, None)
+    }
+
+    fn init_helper_with(
+        decimals: u8,
+        reward_scale: Option<Uint128>,
+    ) -> (StdResult<InitResponse>, TestDeps) {
+        let mut deps = Extern {
+            storage: MockStorage::default(),
+            api: MockApi::new(20),
+            querier: PoolQuerier {
+                decimals,
+                reward_per_block: 0,
+            },
+        };
         let env = mock_env("admin", &[], 1);
 
         let init_msg = LPStakingInitMsg {
@@ -702,13 +3103,14 @@ mod tests {
             prng_seed: Binary::from("lolz fun yay".as_bytes()),
             viewing_key: "123".to_string(),
             master: SecretContract {
-                address: Default::default(),
-                contract_hash: "".to_string(),
+                address: HumanAddr("master".to_string()),
+                contract_hash: "3".to_string(),
             },
             token_info: TokenInfo {
                 name: "".to_string(),
                 symbol: "".to_string(),
             },
+            reward_scale,
         };
 
         (init(&mut deps, env, init_msg), deps)
@@ -736,364 +3138,1153 @@ mod tests {
         }
     }
 
-    fn msg_from_action<S: Storage, A: Api, Q: Querier>(
-        deps: &Extern<S, A, Q>,
+    /// The hook that a handler asked the master to call back with, if it asked for an allocation
+    fn extract_hook(response: &HandleResponse) -> Option<Option<Binary>> {
+        match response.messages.first() {
+            Some(CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr, msg, ..
+            })) if contract_addr.0 == "master" => match from_binary(msg).unwrap() {
+                MasterHandleMsg::UpdateAllocation { hook, .. } => Some(hook),
+                other => panic!("Unexpected master message: {:?}", other),
+            },
+            _ => None,
+        }
+    }
+
+    /// Handles `msg` the way it runs on chain: when the pool asks the master to update its
+    /// allocation, the master calls back with `allocated` rewards and the hook
+    fn handle_allocated(
+        deps: &mut TestDeps,
+        sender: &str,
+        height: u64,
+        msg: LPStakingHandleMsg,
+        allocated: u128,
+    ) -> StdResult<HandleResponse> {
+        let response = handle(deps, mock_env(sender, &[], height), msg)?;
+        match extract_hook(&response) {
+            Some(hook) => handle(
+                deps,
+                mock_env("master", &[], height),
+                LPStakingHandleMsg::NotifyAllocation {
+                    amount: Uint128(allocated),
+                    hook,
+                },
+            ),
+            None => Ok(response),
+        }
+    }
+
+    fn deposit_msg(from: &str, amount: u128, lock_period: Option<u64>) -> LPStakingHandleMsg {
+        LPStakingHandleMsg::Receive {
+            sender: HumanAddr(from.to_string()),
+            from: HumanAddr(from.to_string()),
+            amount: Uint128(amount),
+            msg: to_binary(&LPStakingReceiveMsg::Deposit {
+                lock_period,
+                beneficiary: None,
+                referrer: None,
+            })
+            .unwrap(),
+        }
+    }
+
+    fn deposit_helper(
+        deps: &mut TestDeps,
+        from: &str,
+        amount: u128,
+        height: u64,
+        allocated: u128,
+    ) -> StdResult<HandleResponse> {
+        handle_allocated(
+            deps,
+            "eth",
+            height,
+            deposit_msg(from, amount, None),
+            allocated,
+        )
+    }
+
+    fn load_user(deps: &TestDeps, staker: &str) -> UserInfo {
+        TypedStore::<UserInfo, MockStorage>::attach(&deps.storage)
+            .load(staker.as_bytes())
+            .unwrap_or(UserInfo { locked: 0, debt: 0 })
+    }
+
+    fn load_reward_pool(deps: &TestDeps) -> RewardPool {
+        TypedStore::<RewardPool, MockStorage>::attach(&deps.storage)
+            .load(REWARD_POOL_KEY)
+            .unwrap()
+    }
+
+    fn extract_error_msg<T>(error: StdResult<T>) -> String {
+        match error {
+            Ok(_) => panic!("An error was expected"),
+            Err(StdError::GenericErr { msg, .. }) => msg,
+            Err(err) => panic!("Unexpected error: {:?}", err),
+        }
+    }
+
+    /// The token, recipient and amount of every SNIP-20 transfer or send in `messages`
+    fn extract_transfers(messages: &[CosmosMsg]) -> Vec<(String, String, u128)> {
+        messages
+            .iter()
+            .filter_map(|m| match m {
+                CosmosMsg::Wasm(WasmMsg::Execute {
+                    contract_addr, msg, ..
+                }) => match from_binary(msg) {
+                    Ok(Snip20HandleMsg::Transfer {
+                        recipient, amount, ..
+                    })
+                    | Ok(Snip20HandleMsg::Send {
+                        recipient, amount, ..
+                    }) => Some((contract_addr.0.clone(), recipient.0, amount.u128())),
+                    Err(_) => None,
+                },
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// How much of the reward token `messages` pay out to `recipient`
+    fn extract_rewards(messages: &[CosmosMsg], recipient: &str) -> u128 {
+        extract_transfers(messages)
+            .into_iter()
+            .filter(|(token, to, _)| token == "scrt" && to == recipient)
+            .map(|(_, _, amount)| amount)
+            .sum()
+    }
+
+    /// The polls that `messages` notify, and what they notify them of
+    fn extract_notifications(messages: &[CosmosMsg]) -> Vec<(String, PollHandleMsg)> {
+        messages
+            .iter()
+            .filter_map(|m| match m {
+                CosmosMsg::Wasm(WasmMsg::Execute {
+                    contract_addr, msg, ..
+                }) => from_binary(msg).ok().map(|n| (contract_addr.0.clone(), n)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn msg_from_action(
+        deps: &TestDeps,
         action: &str,
         user: HumanAddr,
     ) -> (LPStakingHandleMsg, String) {
         let mut rng = rand::thread_rng();
-        let chance = rng.gen_range(0, 100000);
 
         match action {
             "deposit" => {
-                let amount: u128 = rng.gen_range(10e12 as u128, 1000e18 as u128);
-
-                let msg = LPStakingHandleMsg::Receive {
-                    sender: user.clone(),
-                    from: user,
-                    amount: Uint128(amount),
-                    msg: to_binary(&LPStakingReceiveMsg::Deposit {}).unwrap(),
-                };
-
-                (msg, "eth".to_string())
+                let amount: u128 = rng.gen_range(1e6 as u128, 1e12 as u128);
+                (deposit_msg(&user.0, amount, None), "eth".to_string())
             }
             "redeem" => {
-                let amount: u128 = rng.gen_range(1e12 as u128, 1000e18 as u128);
+                // Only what the user has, so that every action goes through
+                let locked = load_user(deps, &user.0).locked;
+                let amount: u128 = rng.gen_range(0, locked + 1);
 
                 let msg = LPStakingHandleMsg::Redeem {
                     amount: Some(Uint128(amount)),
+                    recipient: None,
                 };
 
                 (msg, user.0)
             }
-            "deadline" if chance == 42 => {
-                let config: Config = TypedStore::attach(&deps.storage).load(CONFIG_KEY).unwrap();
-                let current = config.deadline as f64;
+            _ => (LPStakingHandleMsg::Claim { recipient: None }, user.0),
+        }
+    }
 
-                let new = rng.gen_range(current + 1.0, current * 1.001);
+    fn sanity_run(reward_per_block: u128, blocks: u64) {
+        let mut rng = rand::thread_rng();
 
-                let msg = LPStakingHandleMsg::SetDeadline { block: new as u64 };
+        let (init_result, mut deps) = init_helper();
+        assert!(init_result.is_ok());
 
-                (msg, "admin".to_string())
-            }
-            "rewards" if chance == 7 => {
-                let amount: u128 = rng.gen_range(10000e6 as u128, 100000e6 as u128);
-
-                let msg = LPStakingHandleMsg::Receive {
-                    sender: user.clone(),
-                    from: user,
-                    amount: Uint128(amount),
-                    msg: to_binary(&LPStakingReceiveMsg::DepositRewards {}).unwrap(),
-                };
+        let actions = vec!["deposit", "redeem", "claim"];
+        let users = vec![
+            HumanAddr("Lebron James".to_string()),
+            HumanAddr("Kobe Bryant".to_string()),
+            HumanAddr("Giannis".to_string()),
+            HumanAddr("Steph Curry".to_string()),
+            HumanAddr("Deni Avdija".to_string()),
+        ];
+
+        let mut total_allocated = 0;
+        let mut total_rewards_output = 0;
+        let mut last_allocation = 1;
+
+        for user in &users {
+            deposit_helper(&mut deps, &user.0, 1_000_000, 1, 0).unwrap();
+        }
+
+        for block in 2..blocks {
+            let num_of_actions = rng.gen_range(0, 5);
+
+            for _ in 0..num_of_actions {
+                let action_idx = rng.gen_range(0, actions.len());
+                let user_idx = rng.gen_range(0, users.len());
+                let user = users[user_idx].clone();
 
-                (msg, "scrt".to_string())
+                let (msg, sender) = msg_from_action(&deps, actions[action_idx], user.clone());
+                let allocated = reward_per_block * (block - last_allocation) as u128;
+                let response = handle_allocated(&mut deps, &sender, block, msg, allocated).unwrap();
+
+                total_allocated += allocated;
+                last_allocation = block;
+                total_rewards_output += extract_rewards(&response.messages, &user.0);
             }
-            _ => (
-                LPStakingHandleMsg::Redeem {
-                    amount: Some(Uint128(u128::MAX)), // This will never work but will keep the tests going
-                },
-                "".to_string(),
-            ),
         }
+
+        // Make sure all users are fully redeemed
+        for user in &users {
+            let allocated = reward_per_block * (blocks - last_allocation) as u128;
+            let redeem_msg = LPStakingHandleMsg::Redeem {
+                amount: None,
+                recipient: None,
+            };
+            let response =
+                handle_allocated(&mut deps, &user.0, blocks, redeem_msg, allocated).unwrap();
+
+            total_allocated += allocated;
+            last_allocation = blocks;
+            total_rewards_output += extract_rewards(&response.messages, &user.0);
+        }
+
+        // Whatever was allocated while nothing was staked stays in the pool for the admin
+        let distributed = total_allocated - load_reward_pool(&deps).residue;
+        let error = 1.0 - (total_rewards_output as f64 / distributed as f64);
+        println!("Error is: {}", error);
+        assert!(error >= 0f64 && error < 0.01);
     }
 
-    fn print_status(
-        deps: &Extern<MockStorage, MockApi, MockQuerier>,
-        users: Vec<HumanAddr>,
-        new_mint: u128,
-    ) {
-        let reward_pool = TypedStore::<RewardPool, MockStorage>::attach(&deps.storage)
-            .load(REWARD_POOL_KEY)
-            .unwrap();
-        let config: Config = TypedStore::attach(&deps.storage).load(CONFIG_KEY).unwrap();
-
-        println!("####### Statistics for block: {} #######", block);
-        println!("Deadline: {}", config.deadline);
-        println!("Locked ETH: {}", reward_pool.inc_token_supply);
-        println!("Pending rewards: {}", reward_pool.pending_rewards);
-        println!(
-            "Accumulated rewards per share: {}",
-            reward_pool.acc_reward_per_share
-        );
-        println!("Last reward block: {}", reward_pool.last_reward_block);
-
-        for user in users {
-            println!("## {}:", user.0);
-            let user_info = TypedStore::<UserInfo, MockStorage>::attach(&deps.storage)
-                .load(user.0.as_bytes())
-                .unwrap_or(UserInfo { locked: 0, debt: 0 });
-            let rewards = query_rewards(deps, user.clone(), Uint128(new_mint));
-
-            println!("Locked: {}", user_info.locked);
-            println!("Debt: {}", user_info.debt);
-            println!("Reward: {}", rewards);
+    // Tests
+
+    #[test]
+    fn test_transfer_stake() {
+        let (init_result, mut deps) = init_helper();
+        assert!(init_result.is_ok());
+
+        let tiers_msg = LPStakingHandleMsg::SetLockTiers {
+            tiers: vec![LockTier {
+                period: 100,
+                boost: 200,
+            }],
+        };
+        handle(&mut deps, mock_env("admin", &[], 1), tiers_msg).unwrap();
+        let lock_msg = deposit_msg("locked", 1_000, Some(100));
+        handle_allocated(&mut deps, "eth", 2, lock_msg, 0).unwrap();
+        deposit_helper(&mut deps, "free", 1_000, 2, 0).unwrap();
+
+        let transfer_msg = |recipient: &str, amount: u128| LPStakingHandleMsg::TransferStake {
+            recipient: HumanAddr(recipient.to_string()),
+            amount: Uint128(amount),
+        };
+        let handle_response =
+            handle_allocated(&mut deps, "locked", 3, transfer_msg("new", 1_000), 0);
+        assert_eq!(
+            extract_error_msg(handle_response),
+            "this balance is locked until block 102".to_string()
+        );
+
+        // Otherwise unlocked stake would be boosted as if it was locked
+        let handle_response =
+            handle_allocated(&mut deps, "free", 3, transfer_msg("locked", 400), 0);
+        assert_eq!(
+            extract_error_msg(handle_response),
+            "locked has a balance that is locked until block 102, and cannot receive stake until then"
+                .to_string()
+        );
+
+        handle_allocated(&mut deps, "free", 3, transfer_msg("new", 400), 0).unwrap();
+        assert_eq!(load_user(&deps, "free").locked, 600);
+        assert_eq!(load_user(&deps, "new").locked, 400);
+        assert_eq!(load_reward_pool(&deps).boosted_supply, 2_000 + 600 + 400);
+
+        // Locks are lifted once the pool has ended
+        let deadline_msg = LPStakingHandleMsg::SetDeadline { block: 10 };
+        handle(&mut deps, mock_env("admin", &[], 3), deadline_msg).unwrap();
+        handle_allocated(&mut deps, "locked", 11, transfer_msg("new", 1_000), 0).unwrap();
+        assert_eq!(load_user(&deps, "locked").locked, 0);
+        assert_eq!(load_user(&deps, "new").locked, 1_400);
+        assert_eq!(load_reward_pool(&deps).boosted_supply, 600 + 1_400);
+    }
+
+    #[test]
+    fn test_redeem_fee() {
+        let (init_result, mut deps) = init_helper();
+        assert!(init_result.is_ok());
+
+        let fee_msg = LPStakingHandleMsg::SetRedeemFee {
+            fee: Some(RedeemFee {
+                rate_bps: 100,
+                decay_period: 100,
+                treasury: SecretContract {
+                    address: HumanAddr("treasury".to_string()),
+                    contract_hash: "5".to_string(),
+                },
+            }),
+        };
+        handle(&mut deps, mock_env("admin", &[], 1), fee_msg).unwrap();
+
+        // Only the admin can exempt a contract, not the poll factory
+        let factory_msg = LPStakingHandleMsg::SetPollFactory {
+            address: Some(HumanAddr("factory".to_string())),
+        };
+        handle(&mut deps, mock_env("admin", &[], 1), factory_msg).unwrap();
+        let exempt_msg = LPStakingHandleMsg::AddFeeExempt {
+            contracts: vec![HumanAddr("vault".to_string())],
+        };
+        let handle_response = handle(&mut deps, mock_env("factory", &[], 1), exempt_msg.clone());
+        assert_eq!(
+            extract_error_msg(handle_response),
+            "not an admin: factory".to_string()
+        );
+        handle(&mut deps, mock_env("admin", &[], 1), exempt_msg).unwrap();
+        let answer: LPStakingQueryAnswer =
+            from_binary(&query(&deps, LPStakingQueryMsg::FeeExempt {}).unwrap()).unwrap();
+        match answer {
+            LPStakingQueryAnswer::FeeExempt { contracts } => {
+                assert_eq!(contracts, vec![HumanAddr("vault".to_string())])
+            }
+            _ => panic!("Unexpected answer: {:?}", answer),
         }
 
-        println!();
+        // Trusting a contract to read balances doesn't exempt it
+        let trust_msg = LPStakingHandleMsg::AddTrustedContracts {
+            contracts: vec![HumanAddr("poll".to_string())],
+        };
+        handle(&mut deps, mock_env("factory", &[], 1), trust_msg).unwrap();
+
+        deposit_helper(&mut deps, "user", 10_000, 2, 0).unwrap();
+        deposit_helper(&mut deps, "vault", 10_000, 2, 0).unwrap();
+        deposit_helper(&mut deps, "poll", 10_000, 2, 0).unwrap();
+        let redeem_msg = LPStakingHandleMsg::Redeem {
+            amount: Some(Uint128(5_000)),
+            recipient: None,
+        };
+
+        // 1% right after the deposit, half of it halfway through the decay period
+        let handle_response = handle_allocated(&mut deps, "user", 2, redeem_msg.clone(), 0);
+        assert_eq!(
+            extract_transfers(&handle_response.unwrap().messages),
+            vec![
+                ("eth".to_string(), "treasury".to_string(), 50),
+                ("eth".to_string(), "user".to_string(), 4_950)
+            ]
+        );
+        let handle_response = handle_allocated(&mut deps, "user", 52, redeem_msg.clone(), 0);
+        assert_eq!(
+            extract_transfers(&handle_response.unwrap().messages),
+            vec![
+                ("eth".to_string(), "treasury".to_string(), 25),
+                ("eth".to_string(), "user".to_string(), 4_975)
+            ]
+        );
+
+        // The vault passes on exactly what it redeems to its own users
+        let handle_response = handle_allocated(&mut deps, "vault", 2, redeem_msg.clone(), 0);
+        assert_eq!(
+            extract_transfers(&handle_response.unwrap().messages),
+            vec![("eth".to_string(), "vault".to_string(), 5_000)]
+        );
+        let handle_response = handle_allocated(&mut deps, "poll", 52, redeem_msg.clone(), 0);
+        assert_eq!(
+            extract_transfers(&handle_response.unwrap().messages),
+            vec![
+                ("eth".to_string(), "treasury".to_string(), 25),
+                ("eth".to_string(), "poll".to_string(), 4_975)
+            ]
+        );
+
+        let unexempt_msg = LPStakingHandleMsg::RemoveFeeExempt {
+            contracts: vec![HumanAddr("vault".to_string())],
+        };
+        handle(&mut deps, mock_env("admin", &[], 52), unexempt_msg).unwrap();
+        let handle_response = handle_allocated(&mut deps, "vault", 52, redeem_msg, 0);
+        assert_eq!(
+            extract_transfers(&handle_response.unwrap().messages),
+            vec![
+                ("eth".to_string(), "treasury".to_string(), 25),
+                ("eth".to_string(), "vault".to_string(), 4_975)
+            ]
+        );
     }
 
-    fn query_rewards(
-        deps: &Extern<MockStorage, MockApi, MockQuerier>,
-        user: HumanAddr,
-        new_mint: Uint128,
-    ) -> u128 {
-        let query_msg = LPStakingQueryMsg::Rewards {
-            address: user,
-            new_rewards: new_mint,
-            key: "42".to_string(),
+    #[test]
+    fn test_redeem_fee_clock() {
+        let (init_result, mut deps) = init_helper();
+        assert!(init_result.is_ok());
+
+        let fee_msg = LPStakingHandleMsg::SetRedeemFee {
+            fee: Some(RedeemFee {
+                rate_bps: 100,
+                decay_period: 100,
+                treasury: SecretContract {
+                    address: HumanAddr("treasury".to_string()),
+                    contract_hash: "5".to_string(),
+                },
+            }),
+        };
+        handle(&mut deps, mock_env("admin", &[], 1), fee_msg).unwrap();
+        deposit_helper(&mut deps, "user", 10_000, 2, 0).unwrap();
+        deposit_helper(&mut deps, "other", 100, 2, 0).unwrap();
+        deposit_helper(&mut deps, "griefer", 100, 102, 0).unwrap();
+
+        let redeem_msg = LPStakingHandleMsg::Redeem {
+            amount: Some(Uint128(5_000)),
+            recipient: None,
+        };
+        // A large deposit on behalf of someone else can't get around the fee: (100 * 2 +
+        // 10000 * 102) / 10100, rounded up
+        let deposit_msg = LPStakingHandleMsg::Receive {
+            sender: HumanAddr("whale".to_string()),
+            from: HumanAddr("whale".to_string()),
+            amount: Uint128(10_000),
+            msg: to_binary(&LPStakingReceiveMsg::Deposit {
+                lock_period: None,
+                beneficiary: Some(HumanAddr("other".to_string())),
+                referrer: None,
+            })
+            .unwrap(),
+        };
+        handle_allocated(&mut deps, "eth", 102, deposit_msg, 0).unwrap();
+        let handle_response = handle_allocated(&mut deps, "other", 102, redeem_msg.clone(), 0);
+        assert_eq!(
+            extract_transfers(&handle_response.unwrap().messages),
+            vec![
+                ("eth".to_string(), "treasury".to_string(), 50),
+                ("eth".to_string(), "other".to_string(), 4_950)
+            ]
+        );
+
+        // And a small transfer only moves the recipient's clock a little: (10000 * 2 + 100 * 102)
+        // / 10100, rounded up
+        let transfer_msg = LPStakingHandleMsg::TransferStake {
+            recipient: HumanAddr("user".to_string()),
+            amount: Uint128(100),
+        };
+        handle_allocated(&mut deps, "griefer", 102, transfer_msg, 0).unwrap();
+        let handle_response = handle_allocated(&mut deps, "user", 103, redeem_msg, 0);
+        assert_eq!(
+            extract_transfers(&handle_response.unwrap().messages),
+            vec![("eth".to_string(), "user".to_string(), 5_000)]
+        );
+    }
+
+    #[test]
+    fn test_vesting() {
+        let (init_result, mut deps) = init_helper();
+        assert!(init_result.is_ok());
+
+        let vesting_msg = LPStakingHandleMsg::SetVesting {
+            vesting: Some(VestingConfig {
+                period: 100,
+                penalty_bps: 1_000,
+            }),
+        };
+        handle(&mut deps, mock_env("admin", &[], 1), vesting_msg).unwrap();
+        deposit_helper(&mut deps, "user", 1_000, 1, 0).unwrap();
+
+        let claim_msg = LPStakingHandleMsg::Claim { recipient: None };
+        let handle_response = handle_allocated(&mut deps, "user", 2, claim_msg, 1_000);
+        assert_eq!(
+            extract_rewards(&handle_response.unwrap().messages, "user"),
+            0
+        );
+        assert_eq!(
+            load_vesting(&deps.storage, &HumanAddr("user".to_string())),
+            Vesting {
+                claimable: 0,
+                locked: 1_000,
+                start_block: 2,
+                end_block: 102,
+            }
+        );
+
+        // Claiming to someone else still vests for the staker, and only pushes the end of what's
+        // locked back in proportion: (500 * 102 + 1000 * 152) / 1500, rounded up
+        let claim_msg = LPStakingHandleMsg::Claim {
+            recipient: Some(HumanAddr("other".to_string())),
+        };
+        let handle_response = handle_allocated(&mut deps, "user", 52, claim_msg, 1_000);
+        assert_eq!(
+            extract_rewards(&handle_response.unwrap().messages, "other"),
+            0
+        );
+        assert_eq!(
+            load_vesting(&deps.storage, &HumanAddr("other".to_string())),
+            Vesting::default()
+        );
+        assert_eq!(
+            load_vesting(&deps.storage, &HumanAddr("user".to_string())),
+            Vesting {
+                claimable: 500,
+                locked: 1_500,
+                start_block: 52,
+                end_block: 136,
+            }
+        );
+
+        // Half of what was locked at block 52 has unlocked by block 94
+        let claim_vested_msg = LPStakingHandleMsg::ClaimVested {
+            recipient: None,
+            include_locked: None,
+        };
+        let handle_response = handle(&mut deps, mock_env("user", &[], 94), claim_vested_msg);
+        assert_eq!(
+            extract_rewards(&handle_response.unwrap().messages, "user"),
+            500 + 750
+        );
+
+        // Claiming early forfeits 10% of the rest, which goes back to the stakers
+        let claim_vested_msg = LPStakingHandleMsg::ClaimVested {
+            recipient: Some(HumanAddr("other".to_string())),
+            include_locked: Some(true),
         };
+        let handle_response = handle(&mut deps, mock_env("user", &[], 94), claim_vested_msg);
+        assert_eq!(
+            extract_rewards(&handle_response.unwrap().messages, "other"),
+            675
+        );
+        let claim_msg = LPStakingHandleMsg::Claim { recipient: None };
+        handle_allocated(&mut deps, "user", 95, claim_msg, 0).unwrap();
+        assert_eq!(
+            load_vesting(&deps.storage, &HumanAddr("user".to_string())),
+            Vesting {
+                claimable: 0,
+                locked: 75,
+                start_block: 95,
+                end_block: 195,
+            }
+        );
+    }
 
-        let result: LPStakingQueryAnswer = from_binary(&query(&deps, query_msg).unwrap()).unwrap();
-        match result {
-            LPStakingQueryAnswer::Rewards { rewards } => rewards.u128(),
-            _ => panic!("NOPE"),
+    #[test]
+    fn test_reward_scale() {
+        let (init_result, _) = init_helper_with(6, None);
+        assert!(init_result.is_ok());
+        let (init_result, _) = init_helper_with(18, Some(Uint128(10u128.pow(18))));
+        assert!(init_result.is_ok());
+
+        // Scaled balances can be well past 128 bits at the biggest scale: 10 ^ 20 * 10 ^ 22
+        let (init_result, mut deps) = init_helper_with(18, Some(Uint128(MAX_REWARD_SCALE)));
+        assert!(init_result.is_ok());
+        deposit_helper(&mut deps, "user", 10u128.pow(20), 1, 0).unwrap();
+        let claim_msg = LPStakingHandleMsg::Claim { recipient: None };
+        let handle_response = handle_allocated(&mut deps, "user", 2, claim_msg, 10u128.pow(18));
+        assert_eq!(
+            extract_rewards(&handle_response.unwrap().messages, "user"),
+            10u128.pow(18)
+        );
+
+        for scale in &[0, 20, 999_999_999_999, 10u128.pow(38) + 1, u128::MAX] {
+            let (init_result, _) = init_helper_with(6, Some(Uint128(*scale)));
+            assert_eq!(
+                extract_error_msg(init_result),
+                "the reward scale must be a power of 10".to_string()
+            );
+        }
+
+        let (init_result, _) = init_helper_with(6, Some(Uint128(10u128.pow(5))));
+        assert_eq!(
+            extract_error_msg(init_result),
+            format!(
+                "the reward scale must be between 1000000 and {} for a token with 6 decimals",
+                MAX_REWARD_SCALE
+            )
+        );
+        let (init_result, _) = init_helper_with(6, Some(Uint128(10u128.pow(38))));
+        assert_eq!(
+            extract_error_msg(init_result),
+            format!(
+                "the reward scale must be between 1000000 and {} for a token with 6 decimals",
+                MAX_REWARD_SCALE
+            )
+        );
+
+        for decimals in &[39, u8::MAX] {
+            let (init_result, _) = init_helper_with(*decimals, None);
+            assert_eq!(
+                extract_error_msg(init_result),
+                format!("tokens with {} decimals are not supported", decimals)
+            );
         }
     }
 
-    fn set_vks(deps: &mut Extern<MockStorage, MockApi, MockQuerier>, users: Vec<HumanAddr>) {
-        for user in users {
-            let vk_msg = SetViewingKey {
-                key: "42".to_string(),
-                padding: None,
-            };
-            handle(deps, mock_env(user.0, &[], 2001), vk_msg).unwrap();
+    #[test]
+    fn test_claim_pool() {
+        let (init_result, mut deps) = init_helper();
+        assert!(init_result.is_ok());
+
+        let claim_msg = LPStakingHandleMsg::ClaimRewardPool { recipient: None };
+        let handle_response = handle(&mut deps, mock_env("not_admin", &[], 10), claim_msg.clone());
+        assert_eq!(
+            extract_error_msg(handle_response),
+            "not an admin: not_admin".to_string()
+        );
+
+        let handle_response = handle(&mut deps, mock_env("admin", &[], 10), claim_msg.clone());
+        assert_eq!(
+            extract_error_msg(handle_response),
+            "this pool has no deadline".to_string()
+        );
+
+        let deadline_msg = LPStakingHandleMsg::SetDeadline { block: 100 };
+        handle(&mut deps, mock_env("admin", &[], 10), deadline_msg).unwrap();
+
+        let handle_response = handle(&mut deps, mock_env("admin", &[], 101), claim_msg.clone());
+        assert_eq!(
+            extract_error_msg(handle_response),
+            format!(
+                "the reward pool can be claimed from block {}",
+                100 + SUNSET_GRACE_PERIOD
+            )
+        );
+
+        // Nothing was staked, so the whole allocation stays in the pool
+        let handle_response = handle_allocated(
+            &mut deps,
+            "admin",
+            100 + SUNSET_GRACE_PERIOD,
+            claim_msg,
+            500,
+        )
+        .unwrap();
+        assert_eq!(extract_rewards(&handle_response.messages, "admin"), 500);
+        assert_eq!(load_reward_pool(&deps).residue, 0);
+    }
+
+    #[test]
+    fn test_deadline_overflow() {
+        let (init_result, mut deps) = init_helper();
+        assert!(init_result.is_ok());
+
+        let deadline_msg = LPStakingHandleMsg::SetDeadline { block: u64::MAX };
+        let handle_response = handle(&mut deps, mock_env("admin", &[], 10), deadline_msg);
+        assert_eq!(
+            extract_error_msg(handle_response),
+            format!(
+                "overflow: {} exceeds u64",
+                u64::MAX as u128 + SUNSET_GRACE_PERIOD as u128
+            )
+        );
+
+        let deadline_msg = LPStakingHandleMsg::SetDeadline {
+            block: u64::MAX - SUNSET_GRACE_PERIOD,
+        };
+        handle(&mut deps, mock_env("admin", &[], 10), deadline_msg).unwrap();
+        match from_binary(&query(&deps, LPStakingQueryMsg::Deadline {}).unwrap()).unwrap() {
+            LPStakingQueryAnswer::Deadline { claimable_from, .. } => {
+                assert_eq!(claimable_from, Some(u64::MAX))
+            }
+            other => panic!("Unexpected answer: {:?}", other),
         }
     }
 
-    fn extract_rewards(result: StdResult<HandleResponse>) -> u128 {
-        match result {
-            Ok(resp) => {
-                for message in resp.messages {
-                    match message {
-                        CosmosMsg::Wasm(w) => match w {
-                            WasmMsg::Execute {
-                                contract_addr, msg, ..
-                            } => {
-                                if contract_addr == HumanAddr("scrt".to_string()) {
-                                    let transfer_msg: Snip20HandleMsg = from_binary(&msg).unwrap();
-
-                                    match transfer_msg {
-                                        Snip20HandleMsg::Transfer { amount, .. } => {
-                                            return amount.u128();
-                                        }
-                                        _ => panic!(),
-                                    }
-                                }
-                            }
-                            _ => panic!(),
-                        },
-                        _ => panic!(),
-                    }
-                }
-            }
-            Err(e) => match e {
-                StdError::NotFound { .. } => {}
-                StdError::GenericErr { msg, backtrace } => {
-                    if !msg.contains("insufficient") {
-                        panic!(format!("{}", msg))
-                    }
-                }
-                _ => panic!(format!("{:?}", e)),
-            },
-        }
+    #[test]
+    fn test_lock_tiers() {
+        let (init_result, mut deps) = init_helper();
+        assert!(init_result.is_ok());
+
+        let tiers_msg = |tiers: &[(u64, u64)]| LPStakingHandleMsg::SetLockTiers {
+            tiers: tiers
+                .iter()
+                .map(|&(period, boost)| LockTier { period, boost })
+                .collect(),
+        };
+        let handle_response = handle(
+            &mut deps,
+            mock_env("user", &[], 1),
+            tiers_msg(&[(100, 200)]),
+        );
+        assert_eq!(
+            extract_error_msg(handle_response),
+            "not an admin: user".to_string()
+        );
+        for tiers in &[&[(0, 200)][..], &[(100, 99)][..]] {
+            let handle_response = handle(&mut deps, mock_env("admin", &[], 1), tiers_msg(*tiers));
+            assert_eq!(
+                extract_error_msg(handle_response),
+                "invalid lock tier: a lock must last at least one block, and boost by at least 100%"
+                    .to_string()
+            );
+        }
+        let handle_response = handle(
+            &mut deps,
+            mock_env("admin", &[], 1),
+            tiers_msg(&[(100, 200), (100, 300)]),
+        );
+        assert_eq!(
+            extract_error_msg(handle_response),
+            "there is more than one lock tier of 100 blocks".to_string()
+        );
+        handle(
+            &mut deps,
+            mock_env("admin", &[], 1),
+            tiers_msg(&[(100, 200), (300, 300)]),
+        )
+        .unwrap();
+
+        let handle_response =
+            handle_allocated(&mut deps, "eth", 1, deposit_msg("user", 1_000, Some(50)), 0);
+        assert_eq!(
+            extract_error_msg(handle_response),
+            "there is no lock tier of 50 blocks".to_string()
+        );
+        handle_allocated(
+            &mut deps,
+            "eth",
+            1,
+            deposit_msg("locked", 1_000, Some(100)),
+            0,
+        )
+        .unwrap();
+        deposit_helper(&mut deps, "free", 1_000, 1, 0).unwrap();
+        assert_eq!(load_reward_pool(&deps).boosted_supply, 2_000 + 1_000);
+
+        // The locked balance earns twice as much
+        let claim_msg = LPStakingHandleMsg::Claim { recipient: None };
+        let handle_response = handle_allocated(&mut deps, "locked", 2, claim_msg.clone(), 3_000);
+        assert_eq!(
+            extract_rewards(&handle_response.unwrap().messages, "locked"),
+            2_000
+        );
+        let handle_response = handle_allocated(&mut deps, "free", 2, claim_msg, 0);
+        assert_eq!(
+            extract_rewards(&handle_response.unwrap().messages, "free"),
+            1_000
+        );
+
+        let redeem_msg = LPStakingHandleMsg::Redeem {
+            amount: None,
+            recipient: None,
+        };
+        let handle_response = handle_allocated(&mut deps, "locked", 100, redeem_msg.clone(), 0);
+        assert_eq!(
+            extract_error_msg(handle_response),
+            "this balance is locked until block 101".to_string()
+        );
+
+        // Anyone can drop the boost of an expired lock
+        let expire_msg = LPStakingHandleMsg::ExpireLock {
+            address: HumanAddr("locked".to_string()),
+        };
+        let handle_response = handle_allocated(&mut deps, "anyone", 100, expire_msg.clone(), 0);
+        assert_eq!(
+            extract_error_msg(handle_response),
+            "locked has no expired lock".to_string()
+        );
+        handle_allocated(&mut deps, "anyone", 101, expire_msg, 0).unwrap();
+        assert_eq!(load_reward_pool(&deps).boosted_supply, 1_000 + 1_000);
 
-        0
+        let handle_response = handle_allocated(&mut deps, "locked", 101, redeem_msg, 0);
+        assert_eq!(
+            extract_transfers(&handle_response.unwrap().messages),
+            vec![("eth".to_string(), "locked".to_string(), 1_000)]
+        );
     }
 
-    fn extract_reward_deposit(msg: LPStakingHandleMsg) -> u128 {
-        match msg {
-            LPStakingHandleMsg::Receive { amount, msg, .. } => {
-                let transfer_msg: LPStakingReceiveMsg = from_binary(&msg).unwrap();
+    #[test]
+    fn test_claim() {
+        let (init_result, mut deps) = init_helper();
+        assert!(init_result.is_ok());
+        deposit_helper(&mut deps, "user", 1_000, 1, 0).unwrap();
 
-                match transfer_msg {
-                    LPStakingReceiveMsg::DepositRewards {} => amount.u128(),
-                    _ => 0,
-                }
-            }
-            _ => 0,
-        }
-    }
+        let claim_msg = LPStakingHandleMsg::Claim { recipient: None };
+        let handle_response = handle_allocated(&mut deps, "user", 2, claim_msg.clone(), 500);
+        assert_eq!(
+            extract_transfers(&handle_response.unwrap().messages),
+            vec![("scrt".to_string(), "user".to_string(), 500)]
+        );
 
-    fn sanity_run(mut rewards: u128, mut deadline: u64) {
-        let mut rng = rand::thread_rng();
+        // The debt was reset, so there's nothing more to claim until the next allocation
+        let handle_response = handle_allocated(&mut deps, "user", 2, claim_msg, 0);
+        assert!(extract_transfers(&handle_response.unwrap().messages).is_empty());
 
-        let (init_result, mut deps) = init_helper(deadline);
+        let claim_msg = LPStakingHandleMsg::Claim {
+            recipient: Some(HumanAddr("cold".to_string())),
+        };
+        let handle_response = handle_allocated(&mut deps, "user", 3, claim_msg, 300);
+        assert_eq!(
+            extract_transfers(&handle_response.unwrap().messages),
+            vec![("scrt".to_string(), "cold".to_string(), 300)]
+        );
+        assert_eq!(load_user(&deps, "user").locked, 1_000);
+        assert_eq!(load_reward_pool(&deps).inc_token_supply, 1_000);
+    }
 
-        deposit_rewards(&mut deps, mock_env("scrt", &[], 1), rewards).unwrap();
+    #[test]
+    fn test_beneficiary_and_recipient() {
+        let (init_result, mut deps) = init_helper();
+        assert!(init_result.is_ok());
+
+        let tiers_msg = LPStakingHandleMsg::SetLockTiers {
+            tiers: vec![LockTier {
+                period: 100,
+                boost: 200,
+            }],
+        };
+        handle(&mut deps, mock_env("admin", &[], 1), tiers_msg).unwrap();
+
+        let zap_msg = |lock_period: Option<u64>| LPStakingHandleMsg::Receive {
+            sender: HumanAddr("zapper".to_string()),
+            from: HumanAddr("zapper".to_string()),
+            amount: Uint128(1_000),
+            msg: to_binary(&LPStakingReceiveMsg::Deposit {
+                lock_period,
+                beneficiary: Some(HumanAddr("user".to_string())),
+                referrer: None,
+            })
+            .unwrap(),
+        };
+        let handle_response = handle_allocated(&mut deps, "eth", 1, zap_msg(Some(100)), 0);
+        assert_eq!(
+            extract_error_msg(handle_response),
+            "cannot lock a deposit on behalf of someone else".to_string()
+        );
+        handle_allocated(&mut deps, "eth", 1, zap_msg(None), 0).unwrap();
+        assert_eq!(load_user(&deps, "zapper").locked, 0);
+        assert_eq!(load_user(&deps, "user").locked, 1_000);
 
-        let actions = vec!["deposit", "redeem", "deadline", "rewards"];
-        let users = vec![
-            HumanAddr("Lebron James".to_string()),
-            HumanAddr("Kobe Bryant".to_string()),
-            HumanAddr("Giannis".to_string()),
-            HumanAddr("Steph Curry".to_string()),
-            HumanAddr("Deni Avdija".to_string()),
-        ];
+        let redeem_msg = LPStakingHandleMsg::Redeem {
+            amount: Some(Uint128(400)),
+            recipient: Some(HumanAddr("cold".to_string())),
+        };
+        let handle_response = handle_allocated(&mut deps, "user", 2, redeem_msg.clone(), 100);
+        assert_eq!(
+            extract_transfers(&handle_response.unwrap().messages),
+            vec![
+                ("scrt".to_string(), "cold".to_string(), 100),
+                ("eth".to_string(), "cold".to_string(), 400)
+            ]
+        );
+        assert_eq!(load_user(&deps, "user").locked, 600);
 
-        let mut total_rewards_output = 0;
+        // Only the staker's own balance can be redeemed
+        let handle_response = handle_allocated(&mut deps, "zapper", 2, redeem_msg, 0);
+        assert_eq!(
+            extract_error_msg(handle_response),
+            "insufficient funds to redeem: balance=0, required=400".to_string()
+        );
+    }
 
-        set_vks(&mut deps, users.clone());
-        let mut block: u64 = 2;
-        while block < (deadline + 10_000) {
-            let num_of_actions = rng.gen_range(0, 5);
+    #[test]
+    fn test_pool_stats() {
+        let (init_result, mut deps) = init_helper();
+        assert!(init_result.is_ok());
+        deps.querier.reward_per_block = 10;
+
+        deposit_helper(&mut deps, "user", 1_000, 1, 0).unwrap();
+        deposit_helper(&mut deps, "other", 3_000, 1, 0).unwrap();
+        let claim_msg = LPStakingHandleMsg::Claim { recipient: None };
+        handle_allocated(&mut deps, "user", 2, claim_msg, 400).unwrap();
+
+        let query_stats = |deps: &TestDeps, height: u64| {
+            let query_msg = LPStakingQueryMsg::PoolStats { height };
+            match from_binary(&query(deps, query_msg).unwrap()).unwrap() {
+                LPStakingQueryAnswer::PoolStats {
+                    total_locked,
+                    boosted_supply,
+                    acc_reward_per_share,
+                    residue,
+                    stakers,
+                    yearly_rewards,
+                    apr_bps,
+                } => (
+                    total_locked.u128(),
+                    boosted_supply.u128(),
+                    acc_reward_per_share.u128(),
+                    residue.u128(),
+                    stakers,
+                    yearly_rewards.u128(),
+                    apr_bps.u128(),
+                ),
+                other => panic!("Unexpected answer: {:?}", other),
+            }
+        };
 
-            for i in 0..num_of_actions {
-                let action_idx = rng.gen_range(0, actions.len());
+        let yearly_rewards = 10 * BLOCKS_PER_YEAR as u128;
+        assert_eq!(
+            query_stats(&deps, 2),
+            (
+                4_000,
+                4_000,
+                load_reward_pool(&deps).acc_reward_per_share,
+                0,
+                2,
+                yearly_rewards,
+                yearly_rewards * 10_000 / 4_000
+            )
+        );
+        assert_eq!(
+            load_reward_pool(&deps).acc_reward_per_share,
+            400 * DEFAULT_REWARD_SCALE / 4_000
+        );
 
-                let user_idx = rng.gen_range(0, users.len());
-                let user = users[user_idx].clone();
+        // Nothing is estimated past the deadline
+        let redeem_msg = LPStakingHandleMsg::Redeem {
+            amount: None,
+            recipient: None,
+        };
+        handle_allocated(&mut deps, "other", 3, redeem_msg, 0).unwrap();
+        let deadline_msg = LPStakingHandleMsg::SetDeadline { block: 1_000 };
+        handle(&mut deps, mock_env("admin", &[], 3), deadline_msg).unwrap();
+        let (total_locked, _, _, _, stakers, yearly_rewards, apr_bps) = query_stats(&deps, 10);
+        assert_eq!(
+            (total_locked, stakers, yearly_rewards, apr_bps),
+            (1_000, 1, 9_900, 99_000)
+        );
+        let (_, _, _, _, _, yearly_rewards, apr_bps) = query_stats(&deps, 1_000);
+        assert_eq!((yearly_rewards, apr_bps), (0, 0));
+    }
 
-                let (msg, sender) = msg_from_action(&deps, actions[action_idx], user.clone());
-                rewards += extract_reward_deposit(msg.clone());
-                let result = handle(&mut deps, mock_env(sender, &[], block), msg);
-                total_rewards_output += extract_rewards(result);
-            }
+    #[test]
+    fn test_deadline() {
+        let (init_result, mut deps) = init_helper();
+        assert!(init_result.is_ok());
 
-            if block % 10000 == 0 {
-                print_status(&deps, users.clone(), block);
+        let deadline_msg = |block: u64| LPStakingHandleMsg::SetDeadline { block };
+        let handle_response = handle(&mut deps, mock_env("user", &[], 1), deadline_msg(10));
+        assert_eq!(
+            extract_error_msg(handle_response),
+            "not an admin: user".to_string()
+        );
+        let handle_response = handle(&mut deps, mock_env("admin", &[], 5), deadline_msg(4));
+        assert_eq!(
+            extract_error_msg(handle_response),
+            "the deadline cannot be set to a past block".to_string()
+        );
+        handle(&mut deps, mock_env("admin", &[], 1), deadline_msg(10)).unwrap();
+        match from_binary(&query(&deps, LPStakingQueryMsg::Deadline {}).unwrap()).unwrap() {
+            LPStakingQueryAnswer::Deadline {
+                deadline,
+                claimable_from,
+            } => {
+                assert_eq!(deadline, Some(10));
+                assert_eq!(claimable_from, Some(10 + SUNSET_GRACE_PERIOD));
             }
-
-            deadline = TypedStore::<Config, MockStorage>::attach(&deps.storage)
-                .load(CONFIG_KEY)
-                .unwrap()
-                .deadline;
-            block += 1;
+            other => panic!("Unexpected answer: {:?}", other),
         }
+        deposit_helper(&mut deps, "user", 1_000, 1, 0).unwrap();
 
-        // Make sure all users are fully redeemed
-        for user in users.clone() {
-            let redeem_msg = LPStakingHandleMsg::Redeem { amount: None };
-            let result = handle(&mut deps, mock_env(user.0, &[], 1_700_000), redeem_msg);
-            total_rewards_output += extract_rewards(result);
-        }
+        // Only what was allocated for blocks 1 to 10 is shared, the rest is held back
+        let claim_msg = LPStakingHandleMsg::Claim { recipient: None };
+        let handle_response = handle_allocated(&mut deps, "user", 21, claim_msg, 2_000);
+        assert_eq!(
+            extract_rewards(&handle_response.unwrap().messages, "user"),
+            900
+        );
+        assert_eq!(load_reward_pool(&deps).residue, 1_100);
 
-        let error = 1.0 - (total_rewards_output as f64 / rewards as f64);
-        println!("Error is: {}", error);
-        assert!(error >= 0f64 && error < 0.01);
+        let handle_response = deposit_helper(&mut deps, "user", 1_000, 21, 0);
+        assert_eq!(
+            extract_error_msg(handle_response),
+            "this pool has ended and doesn't accept deposits".to_string()
+        );
+        let handle_response = handle(&mut deps, mock_env("admin", &[], 21), deadline_msg(100));
+        assert_eq!(
+            extract_error_msg(handle_response),
+            "this pool has already ended".to_string()
+        );
 
-        // Do another run after first iteration is ended
-        continue_after_ended(&mut deps, deadline, actions, users);
+        // Stakers can still leave, and whatever is allocated from now on is held back too
+        let redeem_msg = LPStakingHandleMsg::Redeem {
+            amount: None,
+            recipient: None,
+        };
+        let handle_response = handle_allocated(&mut deps, "user", 22, redeem_msg, 50);
+        assert_eq!(
+            extract_transfers(&handle_response.unwrap().messages),
+            vec![("eth".to_string(), "user".to_string(), 1_000)]
+        );
+        assert_eq!(load_reward_pool(&deps).residue, 1_150);
     }
 
-    fn continue_after_ended(
-        deps: &mut Extern<MockStorage, MockApi, MockQuerier>,
-        deadline: u64,
-        actions: Vec<&str>,
-        users: Vec<HumanAddr>,
-    ) {
-        let mut rng = rand::thread_rng();
-
-        let start_block = deadline + 10_001;
-        let mut new_deadline = start_block + 500_000;
-        let mut rewards = 500_000_000000;
-        let mut total_rewards_output = 0;
+    #[test]
+    fn test_migrate() {
+        let (init_result, mut deps) = init_helper();
+        assert!(init_result.is_ok());
+        deposit_helper(&mut deps, "user", 1_000, 1, 0).unwrap();
 
-        let msg = LPStakingHandleMsg::SetDeadline {
-            block: new_deadline,
-        };
-        let result = handle(deps, mock_env("admin".to_string(), &[], start_block), msg);
-        let msg = LPStakingHandleMsg::Receive {
-            sender: HumanAddr("admin".to_string()),
-            from: HumanAddr("admin".to_string()),
-            amount: Uint128(rewards),
-            msg: to_binary(&LPStakingReceiveMsg::DepositRewards {}).unwrap(),
+        let migrate_msg = |new_pool: &str| LPStakingHandleMsg::MigrateTo {
+            new_pool: HumanAddr(new_pool.to_string()),
         };
-        let result = handle(
-            deps,
-            mock_env("scrt".to_string(), &[], start_block + 1),
-            msg,
+        let handle_response = handle_allocated(&mut deps, "user", 2, migrate_msg("new_pool"), 0);
+        assert_eq!(
+            extract_error_msg(handle_response),
+            "migrating is not enabled for this pool".to_string()
         );
 
-        let mut block = start_block + 2;
-        while block < (new_deadline + 10_000) {
-            let num_of_actions = rng.gen_range(0, 5);
-
-            for i in 0..num_of_actions {
-                let action_idx = rng.gen_range(0, actions.len());
-
-                let user_idx = rng.gen_range(0, users.len());
-                let user = users[user_idx].clone();
+        let target_msg = |address: &str| LPStakingHandleMsg::SetMigrationTarget {
+            new_pool: Some(SecretContract {
+                address: HumanAddr(address.to_string()),
+                contract_hash: "9".to_string(),
+            }),
+        };
+        let handle_response = handle(&mut deps, mock_env("user", &[], 2), target_msg("new_pool"));
+        assert_eq!(
+            extract_error_msg(handle_response),
+            "not an admin: user".to_string()
+        );
+        let handle_response = handle(
+            &mut deps,
+            mock_env("admin", &[], 2),
+            target_msg(MOCK_CONTRACT_ADDR),
+        );
+        assert_eq!(
+            extract_error_msg(handle_response),
+            "a pool cannot be migrated to itself".to_string()
+        );
+        handle(&mut deps, mock_env("admin", &[], 2), target_msg("new_pool")).unwrap();
+        let fee_msg = LPStakingHandleMsg::SetRedeemFee {
+            fee: Some(RedeemFee {
+                rate_bps: 100,
+                decay_period: 100,
+                treasury: SecretContract {
+                    address: HumanAddr("treasury".to_string()),
+                    contract_hash: "5".to_string(),
+                },
+            }),
+        };
+        handle(&mut deps, mock_env("admin", &[], 2), fee_msg).unwrap();
 
-                let (msg, sender) = msg_from_action(&deps, actions[action_idx], user.clone());
-                rewards += extract_reward_deposit(msg.clone());
-                let result = handle(deps, mock_env(sender, &[], block), msg);
-                total_rewards_output += extract_rewards(result);
-            }
+        let handle_response = handle_allocated(&mut deps, "user", 2, migrate_msg("other_pool"), 0);
+        assert_eq!(
+            extract_error_msg(handle_response),
+            "this pool can only be migrated to new_pool".to_string()
+        );
 
-            if block % 10000 == 0 {
-                print_status(&deps, users.clone(), block);
+        // Rewards are settled, and the whole balance moves to the new pool free of the redeem fee
+        let handle_response = handle_allocated(&mut deps, "user", 2, migrate_msg("new_pool"), 100);
+        let messages = handle_response.unwrap().messages;
+        assert_eq!(
+            extract_transfers(&messages),
+            vec![
+                ("scrt".to_string(), "user".to_string(), 100),
+                ("eth".to_string(), "new_pool".to_string(), 1_000)
+            ]
+        );
+        match messages.last() {
+            Some(CosmosMsg::Wasm(WasmMsg::Execute { msg, .. })) => {
+                match from_binary(msg).unwrap() {
+                    Snip20HandleMsg::Send {
+                        msg: Some(payload), ..
+                    } => assert_eq!(
+                        from_binary::<LPStakingReceiveMsg>(&payload).unwrap(),
+                        LPStakingReceiveMsg::Deposit {
+                            lock_period: None,
+                            beneficiary: Some(HumanAddr("user".to_string())),
+                            referrer: None,
+                        }
+                    ),
+                    other => panic!("Unexpected message: {:?}", other),
+                }
             }
-
-            new_deadline = TypedStore::<Config, MockStorage>::attach(&deps.storage)
-                .load(CONFIG_KEY)
-                .unwrap()
-                .deadline;
-            block += 1;
-        }
-
-        // Make sure all users are fully redeemed
-        for user in users {
-            let redeem_msg = LPStakingHandleMsg::Redeem { amount: None };
-            let result = handle(deps, mock_env(user.0, &[], 1_700_000), redeem_msg);
-            total_rewards_output += extract_rewards(result);
+            other => panic!("Unexpected message: {:?}", other),
         }
-
-        let error = 1.0 - (total_rewards_output as f64 / rewards as f64);
-        println!("Error is: {}", error);
-        assert!(error >= 0f64 && error < 0.01);
+        assert_eq!(load_user(&deps, "user").locked, 0);
+        assert_eq!(load_reward_pool(&deps).inc_token_supply, 0);
     }
 
-    // Tests
-
     #[test]
-    fn test_claim_pool() {
-        let (init_result, mut deps) = init_helper(10000000); // Claim height is deadline + 1
+    fn test_referrals() {
+        let (init_result, mut deps) = init_helper();
+        assert!(init_result.is_ok());
 
-        let claim_msg = LPStakingHandleMsg::ClaimRewardPool { to: None };
-        let handle_response = handle(&mut deps, mock_env("not_admin", &[], 10), claim_msg.clone());
+        let rate_msg = |rate_bps: u64| LPStakingHandleMsg::SetReferralRate { rate_bps };
+        let handle_response = handle(&mut deps, mock_env("admin", &[], 1), rate_msg(2_001));
         assert_eq!(
-            handle_response.unwrap_err(),
-            StdError::GenericErr {
-                msg: "not an admin: not_admin".to_string(),
-                backtrace: None
-            }
+            extract_error_msg(handle_response),
+            "the referral rate cannot be more than 2000 basis points".to_string()
+        );
+        handle(&mut deps, mock_env("admin", &[], 1), rate_msg(1_000)).unwrap();
+
+        let referred_msg =
+            |from: &str, beneficiary: &str, referrer: &str| LPStakingHandleMsg::Receive {
+                sender: HumanAddr(from.to_string()),
+                from: HumanAddr(from.to_string()),
+                amount: Uint128(1_000),
+                msg: to_binary(&LPStakingReceiveMsg::Deposit {
+                    lock_period: None,
+                    beneficiary: Some(HumanAddr(beneficiary.to_string())),
+                    referrer: Some(HumanAddr(referrer.to_string())),
+                })
+                .unwrap(),
+            };
+        let handle_response = handle_allocated(
+            &mut deps,
+            "eth",
+            1,
+            referred_msg("zapper", "user", "ref"),
+            0,
+        );
+        assert_eq!(
+            extract_error_msg(handle_response),
+            "cannot set a referrer on behalf of someone else".to_string()
         );
+        handle_allocated(&mut deps, "eth", 1, referred_msg("user", "user", "ref"), 0).unwrap();
 
-        let handle_response = handle(&mut deps, mock_env("admin", &[], 10), claim_msg.clone());
+        // The referrer's cut comes out of the staker's rewards
+        let claim_msg = LPStakingHandleMsg::Claim { recipient: None };
+        let handle_response = handle_allocated(&mut deps, "user", 2, claim_msg.clone(), 1_000);
         assert_eq!(
-            handle_response.unwrap_err(),
-            StdError::GenericErr {
-                msg: format!("minimum claim height hasn't passed yet: {}", 10000001),
-                backtrace: None
-            }
+            extract_rewards(&handle_response.unwrap().messages, "user"),
+            900
+        );
+
+        // A referrer is only recorded once
+        handle_allocated(
+            &mut deps,
+            "eth",
+            3,
+            referred_msg("user", "user", "other"),
+            0,
+        )
+        .unwrap();
+        let handle_response = handle_allocated(&mut deps, "user", 4, claim_msg, 1_000);
+        assert_eq!(
+            extract_rewards(&handle_response.unwrap().messages, "user"),
+            900
         );
 
+        let referral_msg = |recipient: Option<&str>| LPStakingHandleMsg::ClaimReferralRewards {
+            recipient: recipient.map(|r| HumanAddr(r.to_string())),
+        };
+        let handle_response = handle(&mut deps, mock_env("other", &[], 5), referral_msg(None));
+        assert!(extract_transfers(&handle_response.unwrap().messages).is_empty());
         let handle_response = handle(
             &mut deps,
-            mock_env("admin", &[], 10000001),
-            claim_msg.clone(),
+            mock_env("ref", &[], 5),
+            referral_msg(Some("cold")),
         );
         assert_eq!(
-            handle_response.unwrap_err(),
-            StdError::GenericErr {
-                msg: "Error performing Balance query: Generic error: Querier system error: No such contract: scrt".to_string(), // No way to test external queries yet
-                backtrace: None
-            }
+            extract_transfers(&handle_response.unwrap().messages),
+            vec![("scrt".to_string(), "cold".to_string(), 200)]
+        );
+        let handle_response = handle(&mut deps, mock_env("ref", &[], 5), referral_msg(None));
+        assert!(extract_transfers(&handle_response.unwrap().messages).is_empty());
+
+        // Referring oneself records no referrer
+        handle_allocated(&mut deps, "eth", 5, referred_msg("self", "self", "self"), 0).unwrap();
+        let claim_msg = LPStakingHandleMsg::Claim { recipient: None };
+        let handle_response = handle_allocated(&mut deps, "self", 6, claim_msg, 3_000);
+        assert_eq!(
+            extract_rewards(&handle_response.unwrap().messages, "self"),
+            1_000
         );
     }
 
     #[test]
     fn test_stop_contract() {
-        let (init_result, mut deps) = init_helper(10000000);
+        let (init_result, mut deps) = init_helper();
+        assert!(init_result.is_ok());
 
         let stop_msg = LPStakingHandleMsg::StopContract {};
         let handle_response = handle(&mut deps, mock_env("not_admin", &[], 10), stop_msg.clone());
         assert_eq!(
-            handle_response.unwrap_err(),
-            StdError::GenericErr {
-                msg: "not an admin: not_admin".to_string(),
-                backtrace: None
-            }
+            extract_error_msg(handle_response),
+            "not an admin: not_admin".to_string()
         );
 
         let handle_response = handle(&mut deps, mock_env("admin", &[], 10), stop_msg);
@@ -1104,14 +4295,14 @@ mod tests {
             to_binary(&LPStakingHandleAnswer::StopContract { status: Success }).unwrap()
         );
 
-        let redeem_msg = LPStakingHandleMsg::Redeem { amount: None };
-        let handle_response = handle(&mut deps, mock_env("user", &[], 20), redeem_msg);
+        let redeem_msg = LPStakingHandleMsg::Redeem {
+            amount: None,
+            recipient: None,
+        };
+        let handle_response = handle(&mut deps, mock_env("user", &[], 20), redeem_msg.clone());
         assert_eq!(
-            handle_response.unwrap_err(),
-            StdError::GenericErr {
-                msg: "this contract is stopped and this action is not allowed".to_string(),
-                backtrace: None
-            }
+            extract_error_msg(handle_response),
+            "this contract is stopped and this action is not allowed".to_string()
         );
 
         let resume_msg = LPStakingHandleMsg::ResumeContract {};
@@ -1123,8 +4314,7 @@ mod tests {
             to_binary(&LPStakingHandleAnswer::ResumeContract { status: Success }).unwrap()
         );
 
-        let redeem_msg = LPStakingHandleMsg::Redeem { amount: None };
-        let handle_response = handle(&mut deps, mock_env("user", &[], 20), redeem_msg);
+        let handle_response = handle_allocated(&mut deps, "user", 22, redeem_msg, 0);
         let unwrapped_result: LPStakingHandleAnswer =
             from_binary(&handle_response.unwrap().data.unwrap()).unwrap();
         assert_eq!(
@@ -1135,18 +4325,16 @@ mod tests {
 
     #[test]
     fn test_admin() {
-        let (init_result, mut deps) = init_helper(10000000);
+        let (init_result, mut deps) = init_helper();
+        assert!(init_result.is_ok());
 
         let admin_action_msg = LPStakingHandleMsg::ChangeAdmin {
             address: HumanAddr("not_admin".to_string()),
         };
         let handle_response = handle(&mut deps, mock_env("not_admin", &[], 1), admin_action_msg);
         assert_eq!(
-            handle_response.unwrap_err(),
-            StdError::GenericErr {
-                msg: "not an admin: not_admin".to_string(),
-                backtrace: None
-            }
+            extract_error_msg(handle_response),
+            "not an admin: not_admin".to_string()
         );
 
         let admin_action_msg = LPStakingHandleMsg::ChangeAdmin {
@@ -1165,11 +4353,8 @@ mod tests {
         };
         let handle_response = handle(&mut deps, mock_env("admin", &[], 1), admin_action_msg);
         assert_eq!(
-            handle_response.unwrap_err(),
-            StdError::GenericErr {
-                msg: "not an admin: admin".to_string(),
-                backtrace: None
-            }
+            extract_error_msg(handle_response),
+            "not an admin: admin".to_string()
         );
 
         let admin_action_msg = LPStakingHandleMsg::ChangeAdmin {
@@ -1184,14 +4369,375 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_rewards_query() {
+        let (init_result, mut deps) = init_helper();
+        assert!(init_result.is_ok());
+
+        let extra_token = SecretContract {
+            address: HumanAddr("xyz".to_string()),
+            contract_hash: "4".to_string(),
+        };
+        let add_msg = LPStakingHandleMsg::AddRewardToken {
+            token: extra_token.clone(),
+        };
+        handle(&mut deps, mock_env("admin", &[], 1), add_msg).unwrap();
+
+        deposit_helper(&mut deps, "user", 1_000, 2, 0).unwrap();
+        let rewards_msg = LPStakingHandleMsg::Receive {
+            sender: HumanAddr("admin".to_string()),
+            from: HumanAddr("admin".to_string()),
+            amount: Uint128(1_000),
+            msg: to_binary(&LPStakingReceiveMsg::DepositRewards { end_block: 12 }).unwrap(),
+        };
+        handle(&mut deps, mock_env("xyz", &[], 2), rewards_msg).unwrap();
+        let key_msg = LPStakingHandleMsg::SetViewingKey {
+            key: "user_key".to_string(),
+            padding: None,
+        };
+        handle(&mut deps, mock_env("user", &[], 2), key_msg).unwrap();
+
+        // The user has the pool to themselves until block 3, and half of it after that
+        deposit_helper(&mut deps, "other", 1_000, 3, 500).unwrap();
+
+        let query_msg = LPStakingQueryMsg::Rewards {
+            address: HumanAddr("user".to_string()),
+            key: "user_key".to_string(),
+            height: 7,
+        };
+        match from_binary(&query(&deps, query_msg).unwrap()).unwrap() {
+            LPStakingQueryAnswer::Rewards {
+                rewards,
+                extra_rewards,
+            } => {
+                assert_eq!(rewards, Uint128(500));
+                assert_eq!(
+                    extra_rewards,
+                    vec![RewardAmount {
+                        token: extra_token,
+                        amount: Uint128(100 + 200),
+                    }]
+                );
+            }
+            other => panic!("Unexpected answer: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_deposit_rewards() {
+        let (init_result, mut deps) = init_helper();
+        assert!(init_result.is_ok());
+
+        let add_msg = LPStakingHandleMsg::AddRewardToken {
+            token: SecretContract {
+                address: HumanAddr("xyz".to_string()),
+                contract_hash: "4".to_string(),
+            },
+        };
+        handle(&mut deps, mock_env("admin", &[], 1), add_msg).unwrap();
+        deposit_helper(&mut deps, "user", 1_000, 1, 0).unwrap();
+
+        let rewards_msg = |from: &str, amount: u128, end_block: u64| LPStakingHandleMsg::Receive {
+            sender: HumanAddr(from.to_string()),
+            from: HumanAddr(from.to_string()),
+            amount: Uint128(amount),
+            msg: to_binary(&LPStakingReceiveMsg::DepositRewards { end_block }).unwrap(),
+        };
+
+        // Otherwise anyone could reschedule the campaign with a dust deposit
+        let handle_response = handle(
+            &mut deps,
+            mock_env("xyz", &[], 1),
+            rewards_msg("user", 1, u64::MAX),
+        );
+        assert_eq!(
+            extract_error_msg(handle_response),
+            "only the admin can fund extra rewards".to_string()
+        );
+
+        // 333 per block, and what doesn't divide evenly is paid with the last block
+        handle(
+            &mut deps,
+            mock_env("xyz", &[], 1),
+            rewards_msg("admin", 1_000, 4),
+        )
+        .unwrap();
+        let extra_paid = |messages: &[CosmosMsg]| -> u128 {
+            extract_transfers(messages)
+                .into_iter()
+                .filter(|(token, to, _)| token == "xyz" && to == "user")
+                .map(|(_, _, amount)| amount)
+                .sum()
+        };
+        let claim_msg = LPStakingHandleMsg::Claim { recipient: None };
+        let handle_response = handle_allocated(&mut deps, "user", 2, claim_msg.clone(), 0);
+        assert_eq!(extra_paid(&handle_response.unwrap().messages), 333);
+        let handle_response = handle_allocated(&mut deps, "user", 5, claim_msg, 0);
+        assert_eq!(extra_paid(&handle_response.unwrap().messages), 666 + 1);
+    }
+
+    #[test]
+    fn test_subscribers() {
+        let (init_result, mut deps) = init_helper();
+        assert!(init_result.is_ok());
+
+        let poll = |address: &str| SecretContract {
+            address: HumanAddr(address.to_string()),
+            contract_hash: "poll_hash".to_string(),
+        };
+
+        let add_msg = LPStakingHandleMsg::AddSubscribers {
+            contracts: vec![poll("poll_1")],
+        };
+        let handle_response = handle(&mut deps, mock_env("factory", &[], 1), add_msg.clone());
+        assert_eq!(
+            extract_error_msg(handle_response),
+            "not an admin or a poll factory: factory".to_string()
+        );
+
+        handle(&mut deps, mock_env("admin", &[], 1), add_msg).unwrap();
+        let factory_msg = LPStakingHandleMsg::SetPollFactory {
+            address: Some(HumanAddr("factory".to_string())),
+        };
+        handle(&mut deps, mock_env("admin", &[], 1), factory_msg).unwrap();
+        let add_msg = LPStakingHandleMsg::AddSubscribers {
+            contracts: vec![poll("poll_1"), poll("poll_2")],
+        };
+        handle(&mut deps, mock_env("factory", &[], 1), add_msg).unwrap();
+
+        // Every subscribed poll hears about a staker's new balance
+        let handle_response = deposit_helper(&mut deps, "user", 1_000, 2, 0).unwrap();
+        let expected = PollHandleMsg::UpdateVotingPower {
+            voter: HumanAddr("user".to_string()),
+            old_power: Uint128(0),
+            new_power: Uint128(1_000),
+            delegate: None,
+        };
+        assert_eq!(
+            extract_notifications(&handle_response.messages),
+            vec![
+                ("poll_1".to_string(), expected.clone()),
+                ("poll_2".to_string(), expected)
+            ]
+        );
+
+        let remove_msg = LPStakingHandleMsg::RemoveSubscribers {
+            contracts: vec![HumanAddr("poll_1".to_string())],
+        };
+        handle(&mut deps, mock_env("factory", &[], 3), remove_msg).unwrap();
+
+        let redeem_msg = LPStakingHandleMsg::Redeem {
+            amount: Some(Uint128(400)),
+            recipient: None,
+        };
+        let handle_response = handle_allocated(&mut deps, "user", 4, redeem_msg, 0).unwrap();
+        assert_eq!(
+            extract_notifications(&handle_response.messages),
+            vec![(
+                "poll_2".to_string(),
+                PollHandleMsg::UpdateVotingPower {
+                    voter: HumanAddr("user".to_string()),
+                    old_power: Uint128(1_000),
+                    new_power: Uint128(600),
+                    delegate: None,
+                }
+            )]
+        );
+
+        let add_msg = LPStakingHandleMsg::AddSubscribers {
+            contracts: (0..MAX_SUBSCRIBERS)
+                .map(|i| poll(&format!("poll_{}", i + 3)))
+                .collect(),
+        };
+        let handle_response = handle(&mut deps, mock_env("factory", &[], 5), add_msg);
+        assert_eq!(
+            extract_error_msg(handle_response),
+            format!(
+                "a pool cannot have more than {} subscribers",
+                MAX_SUBSCRIBERS
+            )
+        );
+        let add_msg = LPStakingHandleMsg::AddSubscribers {
+            contracts: (1..MAX_SUBSCRIBERS)
+                .map(|i| poll(&format!("poll_{}", i + 2)))
+                .collect(),
+        };
+        handle(&mut deps, mock_env("factory", &[], 5), add_msg).unwrap();
+        let answer: LPStakingQueryAnswer =
+            from_binary(&query(&deps, LPStakingQueryMsg::Subscribers {}).unwrap()).unwrap();
+        match answer {
+            LPStakingQueryAnswer::Subscribers { contracts } => {
+                assert_eq!(contracts.len(), MAX_SUBSCRIBERS)
+            }
+            _ => panic!("Unexpected answer: {:?}", answer),
+        }
+
+    #[test]
+    fn test_emergency_redeem_skips_subscribers() {
+        let (init_result, mut deps) = init_helper();
+        assert!(init_result.is_ok());
+
+        let add_msg = LPStakingHandleMsg::AddSubscribers {
+            contracts: vec![SecretContract {
+                address: HumanAddr("poll".to_string()),
+                contract_hash: "poll_hash".to_string(),
+            }],
+        };
+        handle(&mut deps, mock_env("admin", &[], 1), add_msg).unwrap();
+        deposit_helper(&mut deps, "user", 1_000, 2, 0).unwrap();
+        let delegate_msg = LPStakingHandleMsg::Delegate {
+            delegate: Some(HumanAddr("delegate".to_string())),
+        };
+        handle(&mut deps, mock_env("user", &[], 2), delegate_msg).unwrap();
+        let trust_msg = LPStakingHandleMsg::AddTrustedContracts {
+            contracts: vec![HumanAddr("poll".to_string())],
+        };
+        handle(&mut deps, mock_env("admin", &[], 2), trust_msg).unwrap();
+        let key_msg = LPStakingHandleMsg::SetViewingKey {
+            key: "poll_key".to_string(),
+            padding: None,
+        };
+        handle(&mut deps, mock_env("poll", &[], 2), key_msg).unwrap();
+
+        handle(
+            &mut deps,
+            mock_env("admin", &[], 3),
+            LPStakingHandleMsg::StopContract {},
+        )
+        .unwrap();
+
+        // A failing poll would otherwise revert the only way out of a stopped contract
+        let handle_response = handle(
+            &mut deps,
+            mock_env("user", &[], 4),
+            LPStakingHandleMsg::EmergencyRedeem {},
+        )
+        .unwrap();
+        assert_eq!(
+            extract_transfers(&handle_response.messages),
+            vec![("eth".to_string(), "user".to_string(), 1_000)]
+        );
+        assert!(extract_notifications(&handle_response.messages).is_empty());
+        assert_eq!(load_user(&deps, "user").locked, 0);
+
+        // The balances that polls read when they are finalized are still up to date
+        let balance_msg = LPStakingQueryMsg::StakerBalance {
+            staker: HumanAddr("user".to_string()),
+            height: Some(4),
+            contract: HumanAddr("poll".to_string()),
+            key: "poll_key".to_string(),
+        };
+        match from_binary(&query(&deps, balance_msg).unwrap()).unwrap() {
+            LPStakingQueryAnswer::Balance { amount } => assert_eq!(amount, Uint128(0)),
+            other => panic!("Unexpected answer: {:?}", other),
+        }
+        let delegation_msg = LPStakingQueryMsg::StakerDelegation {
+            staker: HumanAddr("delegate".to_string()),
+            height: Some(4),
+            contract: HumanAddr("poll".to_string()),
+            key: "poll_key".to_string(),
+        };
+        match from_binary(&query(&deps, delegation_msg).unwrap()).unwrap() {
+            LPStakingQueryAnswer::Delegation {
+                delegated_power, ..
+            } => assert_eq!(delegated_power, Uint128(0)),
+            other => panic!("Unexpected answer: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_staker_balance() {
+        let (init_result, mut deps) = init_helper();
+        assert!(init_result.is_ok());
+
+        deposit_helper(&mut deps, "user", 1_000, 2, 0).unwrap();
+        let key_msg = LPStakingHandleMsg::SetViewingKey {
+            key: "poll_key".to_string(),
+            padding: None,
+        };
+        handle(&mut deps, mock_env("poll", &[], 2), key_msg).unwrap();
+
+        let balance_msg = |key: &str| LPStakingQueryMsg::StakerBalance {
+            staker: HumanAddr("user".to_string()),
+            height: None,
+            contract: HumanAddr("poll".to_string()),
+            key: key.to_string(),
+        };
+        let delegation_msg = LPStakingQueryMsg::StakerDelegation {
+            staker: HumanAddr("user".to_string()),
+            height: Some(2),
+            contract: HumanAddr("poll".to_string()),
+            key: "poll_key".to_string(),
+        };
+
+        // A contract that isn't trusted can't read anyone's balance, even with its own key
+        assert_eq!(
+            query(&deps, balance_msg("poll_key")).unwrap_err(),
+            StdError::unauthorized()
+        );
+        assert_eq!(
+            query(&deps, delegation_msg.clone()).unwrap_err(),
+            StdError::unauthorized()
+        );
+
+        let factory_msg = LPStakingHandleMsg::SetPollFactory {
+            address: Some(HumanAddr("factory".to_string())),
+        };
+        handle(&mut deps, mock_env("admin", &[], 3), factory_msg).unwrap();
+        let trust_msg = LPStakingHandleMsg::AddTrustedContracts {
+            contracts: vec![HumanAddr("poll".to_string())],
+        };
+        let handle_response = handle(&mut deps, mock_env("user", &[], 3), trust_msg.clone());
+        assert_eq!(
+            extract_error_msg(handle_response),
+            "not an admin or a poll factory: user".to_string()
+        );
+        handle(&mut deps, mock_env("factory", &[], 3), trust_msg).unwrap();
+
+        // A trusted contract still has to prove who it is
+        let answer: LPStakingQueryAnswer =
+            from_binary(&query(&deps, balance_msg("wrong_key")).unwrap()).unwrap();
+        match answer {
+            LPStakingQueryAnswer::QueryError { .. } => {}
+            other => panic!("Unexpected answer: {:?}", other),
+        }
+
+        let answer: LPStakingQueryAnswer =
+            from_binary(&query(&deps, balance_msg("poll_key")).unwrap()).unwrap();
+        match answer {
+            LPStakingQueryAnswer::Balance { amount } => assert_eq!(amount, Uint128(1_000)),
+            other => panic!("Unexpected answer: {:?}", other),
+        }
+
+        let answer: LPStakingQueryAnswer =
+            from_binary(&query(&deps, delegation_msg.clone()).unwrap()).unwrap();
+        match answer {
+            LPStakingQueryAnswer::Delegation {
+                delegate,
+                delegated_power,
+            } => {
+                assert_eq!(delegate, None);
+                assert_eq!(delegated_power, Uint128(0));
+            }
+            other => panic!("Unexpected answer: {:?}", other),
+        }
+
+        let untrust_msg = LPStakingHandleMsg::RemoveTrustedContracts {
+            contracts: vec![HumanAddr("poll".to_string())],
+        };
+        handle(&mut deps, mock_env("factory", &[], 4), untrust_msg).unwrap();
+        assert_eq!(
+            query(&deps, delegation_msg).unwrap_err(),
+            StdError::unauthorized()
+        );
+    }
+
     #[test]
     fn test_single_run() {
         let mut rng = rand::thread_rng();
+        let reward_per_block: u128 = rng.gen_range(1_000_000000, 10_000_000000); // 1k-10k SCRT
 
-        let deadline: u64 = rng.gen_range(100_000, 5_000_000);
-        let rewards: u128 = rng.gen_range(1_000_000000, 10_000_000_000000); // 1k-10mn SCRT
-
-        sanity_run(rewards, deadline);
+        sanity_run(reward_per_block, 2_000);
     }
 
     #[test]
@@ -1200,16 +4746,16 @@ mod tests {
         let mut rng = rand::thread_rng();
 
         for run in 0..100 {
-            let deadline: u64 = rng.gen_range(100_000, 5_000_000);
-            let rewards: u128 = rng.gen_range(1_000_000000, 10_000_000_000000); // 1k-10mn SCRT
+            let reward_per_block: u128 = rng.gen_range(1_000_000000, 10_000_000000); // 1k-10k SCRT
+            let blocks: u64 = rng.gen_range(10_000, 100_000);
 
             println!("$$$$$$$$$$$$$$$$$$ Run Parameters $$$$$$$$$$$$$$$$$$");
             println!("Run number: {}", run + 1);
-            println!("Rewards: {}", rewards);
-            println!("Deadline: {}", deadline);
+            println!("Rewards per block: {}", reward_per_block);
+            println!("Blocks: {}", blocks);
             println!();
 
-            sanity_run(rewards, deadline);
+            sanity_run(reward_per_block, blocks);
         }
     }
 
@@ -1223,5 +4769,11 @@ mod tests {
             amount: Uint128,
             padding: Option<String>,
         },
+        Send {
+            recipient: HumanAddr,
+            amount: Uint128,
+            msg: Option<Binary>,
+            padding: Option<String>,
+        },
     }
 }
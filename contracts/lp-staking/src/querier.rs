@@ -1,10 +1,11 @@
-use crate::constants::CONFIG_KEY;
+use crate::constants::{BLOCKS_PER_YEAR, CONFIG_KEY, DEADLINE_KEY};
 use crate::state::Config;
 use cosmwasm_std::{
     to_binary, Api, Extern, HumanAddr, Querier, QueryRequest, StdError, StdResult, Storage,
     WasmQuery,
 };
 use scrt_finance::master_msg::{MasterQueryAnswer, MasterQueryMsg};
+use scrt_finance::math;
 use secret_toolkit::storage::TypedStore;
 
 pub fn query_pending<S: Storage, A: Api, Q: Querier>(
@@ -29,3 +30,22 @@ pub fn query_pending<S: Storage, A: Api, Q: Querier>(
         )),
     }
 }
+
+/// Estimates the rewards that this pool will be allocated over the year that follows `block`,
+/// according to the master's current schedule and this pool's weight. Nothing is earned after
+/// the pool's deadline
+pub fn query_yearly_rewards<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    block: u64,
+) -> StdResult<u128> {
+    let deadline: Option<u64> = TypedStore::attach(&deps.storage).load(DEADLINE_KEY)?;
+    let until = deadline.map_or(block + BLOCKS_PER_YEAR, |d| d.min(block + BLOCKS_PER_YEAR));
+    if until <= block {
+        return Ok(0);
+    }
+
+    let pending_now = query_pending(deps, block)?;
+    let pending_until = query_pending(deps, until)?;
+
+    math::sub(pending_until, pending_now)
+}
@@ -1,5 +1,9 @@
-use cosmwasm_std::HumanAddr;
+use crate::constants::NO_BOOST;
+use cosmwasm_std::{HumanAddr, ReadonlyStorage, StdResult, Storage};
+use scrt_finance::math::{self, Rounding};
 use scrt_finance::types::SecretContract;
+use secret_toolkit::storage::{AppendStore, AppendStoreMut};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Clone)]
@@ -12,4 +16,164 @@ pub struct Config {
     pub prng_seed: Vec<u8>,
     pub is_stopped: bool,
     pub own_addr: HumanAddr,
+    pub reward_scale: u128, // acc_reward_per_share is scaled up by it to keep its precision
+}
+
+/// A reward token other than the one allocated by the master. It is funded directly through
+/// `Receive`, and paid out evenly per block until `end_block`
+#[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Clone)]
+pub struct ExtraReward {
+    pub token: SecretContract,
+    pub reward_per_block: u128,
+    pub end_block: u64,
+    pub last_update_block: u64,
+    pub residue: u128,   // Allocated while nothing was staked
+    pub remainder: u128, // Didn't divide evenly among the blocks, paid out at `end_block`
+    pub acc_reward_per_share: u128,
+}
+
+/// A staker's lock. Rewards are shared by each stake multiplied by its lock's `boost`
+#[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Clone)]
+pub struct Lock {
+    pub unlock_block: u64,
+    pub period: u64,
+    pub boost: u64,
+}
+
+impl Default for Lock {
+    fn default() -> Self {
+        Lock {
+            unlock_block: 0,
+            period: 0,
+            boost: NO_BOOST,
+        }
+    }
+}
+
+impl Lock {
+    /// The lock as it stands at `height`. An expired lock no longer boosts anything
+    pub fn at(&self, height: u64) -> Lock {
+        if height >= self.unlock_block {
+            Lock::default()
+        } else {
+            self.clone()
+        }
+    }
+
+    pub fn boosted(&self, locked: u128) -> StdResult<u128> {
+        math::mul_div(locked, self.boost as u128, NO_BOOST as u128, Rounding::Down)
+    }
+}
+
+/// Harvested rewards that unlock linearly from `start_block` until `end_block`. What already
+/// unlocked but wasn't claimed yet is kept in `claimable`
+#[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Clone, Default)]
+pub struct Vesting {
+    pub claimable: u128,
+    pub locked: u128,
+    pub start_block: u64,
+    pub end_block: u64,
+}
+
+impl Vesting {
+    /// How much of `locked` has unlocked by `height`
+    pub fn unlocked_at(&self, height: u64) -> StdResult<u128> {
+        if height >= self.end_block {
+            return Ok(self.locked);
+        }
+        if height <= self.start_block {
+            return Ok(0);
+        }
+
+        math::mul_div(
+            self.locked,
+            (height - self.start_block) as u128,
+            (self.end_block - self.start_block) as u128,
+            Rounding::Down,
+        )
+    }
+
+    /// Moves whatever unlocked by `height` to `claimable`
+    pub fn settle(&mut self, height: u64) -> StdResult<()> {
+        let unlocked = self.unlocked_at(height)?;
+        self.claimable = math::add(self.claimable, unlocked)?;
+        self.locked = math::sub(self.locked, unlocked)?;
+        self.start_block = self.start_block.max(height);
+
+        Ok(())
+    }
+
+    /// Adds `amount` to what's locked, to vest over `period` blocks from `height`. The end block
+    /// becomes the amount-weighted average of the current one and `height + period`, so that a
+    /// small harvest only delays what's already locked by a proportionally small amount
+    pub fn vest(&mut self, amount: u128, height: u64, period: u64) -> StdResult<()> {
+        self.settle(height)?;
+
+        let end_block = math::add(height as u128, period as u128)?;
+        let locked = math::add(self.locked, amount)?;
+        let weighted_end = math::add(
+            math::mul(self.locked, self.end_block.max(height) as u128)?,
+            math::mul(amount, end_block)?,
+        )?;
+        // Rounding up, so that the schedule never ends earlier than the weights say
+        self.end_block = math::to_u64(math::div(weighted_end, locked, Rounding::Up)?)?;
+        self.start_block = height;
+        self.locked = locked;
+
+        Ok(())
+    }
+}
+
+/// A value as of the end of block `height`
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Checkpoint<T> {
+    pub height: u64,
+    pub value: T,
+}
+
+/// Records `value` as of `height`. If several updates happen in the same block, only the last one
+/// is kept
+pub fn push_checkpoint<T: Serialize + DeserializeOwned, S: Storage>(
+    storage: &mut S,
+    height: u64,
+    value: T,
+) -> StdResult<()> {
+    let mut checkpoints = AppendStoreMut::<Checkpoint<T>, _>::attach_or_create(storage)?;
+    let checkpoint = Checkpoint { height, value };
+
+    let len = checkpoints.len();
+    if len > 0 && checkpoints.get_at(len - 1)?.height == height {
+        checkpoints.set_at(len - 1, &checkpoint)
+    } else {
+        checkpoints.push(&checkpoint)
+    }
+}
+
+/// Returns the last value that was recorded at or before `height`, if any
+pub fn checkpoint_at<T: Serialize + DeserializeOwned, S: ReadonlyStorage>(
+    storage: &S,
+    height: u64,
+) -> StdResult<Option<T>> {
+    let checkpoints = if let Some(result) = AppendStore::<Checkpoint<T>, _>::attach(storage) {
+        result?
+    } else {
+        return Ok(None);
+    };
+
+    // Binary search for the first checkpoint after `height`
+    let (mut low, mut high) = (0, checkpoints.len());
+    while low < high {
+        let mid = (low + high) / 2;
+        if checkpoints.get_at(mid)?.height <= height {
+            low = mid + 1;
+        } else {
+            high = mid;
+        }
+    }
+
+    if low == 0 {
+        Ok(None)
+    } else {
+        Ok(Some(checkpoints.get_at(low - 1)?.value))
+    }
 }
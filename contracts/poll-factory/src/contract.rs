@@ -0,0 +1,1069 @@
+use cosmwasm_std::{
+    from_binary, log, to_binary, Api, Binary, CosmosMsg, Env, Extern, HandleResponse, HumanAddr,
+    InitResponse, Querier, ReadonlyStorage, StdError, StdResult, Storage, Uint128, WasmMsg,
+};
+use cosmwasm_storage::PrefixedStorage;
+use secret_toolkit::crypto::sha_256;
+use secret_toolkit::snip20;
+use secret_toolkit::storage::{TypedStore, TypedStoreMut};
+
+use crate::msg::ResponseStatus::Success;
+use crate::msg::{HandleAnswer, HandleMsg, InitMsg, QueryAnswer, QueryMsg, ReceiveMsg};
+use crate::querier::query_staked_balance;
+use crate::state::{
+    finish_poll, load_finished_poll_ids, load_poll, load_polls, store_new_poll, Config, PollInfo,
+    ACTIVE_POLLS_KEY, CONFIG_KEY, PENDING_POLL_KEY, PREFIX_EXECUTED_POLLS,
+};
+use scrt_finance::lp_staking_msg::LPStakingHandleMsg;
+use scrt_finance::secret_poll_msg::{PollConfig, PollInitMsg, PollMetadata, ProposalExecution};
+use scrt_finance::types::SecretContract;
+use scrt_finance::viewing_key::ViewingKey;
+
+pub const RESPONSE_BLOCK_SIZE: usize = 256;
+// Every active poll is notified by the staking pool on each balance change, so there can't be
+// too many of them, and they can't last too long
+pub const MAX_ACTIVE_POLLS: usize = 20;
+pub const MAX_ACTIVE_POLLS_PER_AUTHOR: usize = 2;
+pub const MAX_POLL_DURATION: u64 = 403_200; // About 4 weeks, assuming 6 second blocks
+
+pub fn init<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    msg: InitMsg,
+) -> StdResult<InitResponse> {
+    TypedStoreMut::attach(&mut deps.storage).store(
+        CONFIG_KEY,
+        &Config {
+            admin: env.message.sender,
+            poll_code_id: msg.poll_code_id,
+            poll_code_hash: msg.poll_code_hash,
+            staking_pool: msg.staking_pool,
+            sefi: msg.sefi.clone(),
+            min_staking_amount: msg.min_staking_amount.u128(),
+            proposal_deposit: msg.proposal_deposit.u128(),
+            prng_seed: sha_256(&msg.prng_seed.0).to_vec(),
+        },
+    )?;
+
+    let pending: Option<PollInfo> = None;
+    TypedStoreMut::attach(&mut deps.storage).store(PENDING_POLL_KEY, &pending)?;
+    let active: Vec<u32> = vec![];
+    TypedStoreMut::attach(&mut deps.storage).store(ACTIVE_POLLS_KEY, &active)?;
+
+    // Register SEFI, so proposal deposits can be paid with `Send`
+    let messages = vec![snip20::register_receive_msg(
+        env.contract_code_hash,
+        None,
+        1, // This is public data, no need to pad
+        msg.sefi.contract_hash,
+        msg.sefi.address,
+    )?];
+
+    Ok(InitResponse {
+        messages,
+        log: vec![],
+    })
+}
+
+pub fn handle<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    msg: HandleMsg,
+) -> StdResult<HandleResponse> {
+    match msg {
+        HandleMsg::NewPoll {
+            title,
+            description,
+            config,
+            choices,
+            execution,
+            staking_pool_viewing_key,
+        } => new_poll(
+            deps,
+            env,
+            title,
+            description,
+            config,
+            choices,
+            execution,
+            staking_pool_viewing_key,
+        ),
+        HandleMsg::Receive {
+            from, amount, msg, ..
+        } => receive(deps, env, from, amount.u128(), msg),
+        HandleMsg::RegisterPoll {} => register_poll(deps, env),
+        HandleMsg::PollFinalized { quorum_reached } => poll_finalized(deps, env, quorum_reached),
+        HandleMsg::ExecuteProposal { contract, msg } => execute_proposal(deps, env, contract, msg),
+        HandleMsg::UpdateConfig {
+            poll_code_id,
+            poll_code_hash,
+            min_staking_amount,
+            proposal_deposit,
+        } => update_config(
+            deps,
+            env,
+            poll_code_id,
+            poll_code_hash,
+            min_staking_amount,
+            proposal_deposit,
+        ),
+        HandleMsg::ChangeAdmin { address } => change_admin(deps, env, address),
+    }
+}
+
+pub fn query<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    msg: QueryMsg,
+) -> StdResult<Binary> {
+    match msg {
+        QueryMsg::Config {} => query_config(deps),
+        QueryMsg::ActivePolls { page, page_size } => {
+            query_active_polls(deps, page.unwrap_or(0), page_size)
+        }
+        QueryMsg::FinishedPolls { page, page_size } => {
+            query_finished_polls(deps, page.unwrap_or(0), page_size)
+        }
+    }
+}
+
+// Handle functions
+
+fn new_poll<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    title: String,
+    description: String,
+    poll_config: PollConfig,
+    choices: Vec<String>,
+    execution: Option<ProposalExecution>,
+    staking_pool_viewing_key: String,
+) -> StdResult<HandleResponse> {
+    let config: Config = TypedStore::attach(&deps.storage).load(CONFIG_KEY)?;
+
+    let staked = query_staked_balance(deps, env.message.sender.clone(), staking_pool_viewing_key)?;
+    if staked < config.min_staking_amount {
+        return Err(StdError::generic_err(format!(
+            "insufficient stake to create a poll: staked={}, required={}",
+            staked, config.min_staking_amount,
+        )));
+    }
+
+    let author = env.message.sender.clone();
+    create_poll(
+        deps,
+        env,
+        config,
+        PollMetadata {
+            title,
+            description,
+            author,
+        },
+        poll_config,
+        choices,
+        execution,
+        0,
+    )
+}
+
+fn receive<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    from: HumanAddr,
+    amount: u128,
+    msg: Binary,
+) -> StdResult<HandleResponse> {
+    let config: Config = TypedStore::attach(&deps.storage).load(CONFIG_KEY)?;
+    if env.message.sender != config.sefi.address {
+        return Err(StdError::generic_err(format!(
+            "This token is not supported. Supported: {}, given: {}",
+            config.sefi.address, env.message.sender
+        )));
+    }
+
+    let msg: ReceiveMsg = from_binary(&msg)?;
+    match msg {
+        ReceiveMsg::NewPoll {
+            title,
+            description,
+            config: poll_config,
+            choices,
+            execution,
+        } => {
+            if amount < config.proposal_deposit {
+                return Err(StdError::generic_err(format!(
+                    "insufficient proposal deposit: given={}, required={}",
+                    amount, config.proposal_deposit,
+                )));
+            }
+
+            create_poll(
+                deps,
+                env,
+                config,
+                PollMetadata {
+                    title,
+                    description,
+                    author: from,
+                },
+                poll_config,
+                choices,
+                execution,
+                amount,
+            )
+        }
+    }
+}
+
+fn register_poll<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+) -> StdResult<HandleResponse> {
+    // A poll is pending only between its instantiation and its registration callback, which
+    // both happen in the same transaction
+    let pending: Option<PollInfo> = TypedStore::attach(&deps.storage).load(PENDING_POLL_KEY)?;
+    let mut poll = pending.ok_or_else(StdError::unauthorized)?;
+    poll.address = env.message.sender.clone();
+    let code_hash = poll.code_hash.clone();
+
+    let id = store_new_poll(&mut deps.storage, poll)?;
+    let pending: Option<PollInfo> = None;
+    TypedStoreMut::attach(&mut deps.storage).store(PENDING_POLL_KEY, &pending)?;
+
+    // Subscribe the new poll to the staking pool's voting power updates, and let it read its
+    // voters' balances
+    let config: Config = TypedStore::attach(&deps.storage).load(CONFIG_KEY)?;
+    let messages = vec![
+        staking_pool_msg(
+            &config,
+            LPStakingHandleMsg::AddSubscribers {
+                contracts: vec![SecretContract {
+                    address: env.message.sender.clone(),
+                    contract_hash: code_hash,
+                }],
+            },
+        )?,
+        staking_pool_msg(
+            &config,
+            LPStakingHandleMsg::AddTrustedContracts {
+                contracts: vec![env.message.sender.clone()],
+            },
+        )?,
+    ];
+
+    Ok(HandleResponse {
+        messages,
+        log: vec![
+            log("poll_id", id.to_string()),
+            log("poll_address", env.message.sender.to_string()),
+        ],
+        data: Some(to_binary(&HandleAnswer::RegisterPoll { status: Success })?),
+    })
+}
+
+fn poll_finalized<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    quorum_reached: bool,
+) -> StdResult<HandleResponse> {
+    let config: Config = TypedStore::attach(&deps.storage).load(CONFIG_KEY)?;
+    let poll = finish_poll(&mut deps.storage, &env.message.sender, quorum_reached)?;
+
+    let mut messages = vec![
+        staking_pool_msg(
+            &config,
+            LPStakingHandleMsg::RemoveSubscribers {
+                contracts: vec![env.message.sender.clone()],
+            },
+        )?,
+        staking_pool_msg(
+            &config,
+            LPStakingHandleMsg::RemoveTrustedContracts {
+                contracts: vec![env.message.sender],
+            },
+        )?,
+    ];
+
+    // Deposits are returned to the author if the poll reached quorum, otherwise they are forfeited
+    // to the admin
+    if poll.deposit.u128() > 0 {
+        let recipient = if quorum_reached {
+            poll.metadata.author
+        } else {
+            config.admin
+        };
+
+        messages.push(snip20::transfer_msg(
+            recipient,
+            poll.deposit,
+            None,
+            RESPONSE_BLOCK_SIZE,
+            config.sefi.contract_hash,
+            config.sefi.address,
+        )?);
+    }
+
+    Ok(HandleResponse {
+        messages,
+        log: vec![log("poll_id", poll.id.to_string())],
+        data: Some(to_binary(&HandleAnswer::PollFinalized { status: Success })?),
+    })
+}
+
+fn execute_proposal<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    contract: SecretContract,
+    msg: Binary,
+) -> StdResult<HandleResponse> {
+    // Only polls that were instantiated by this factory can get here, so we trust them to call
+    // this only when their proposal has passed and its timelock is over. What they execute must
+    // still be exactly what they were created with
+    let poll = load_poll(&deps.storage, &env.message.sender)?;
+    if poll.quorum_reached != Some(true) {
+        return Err(StdError::generic_err(format!(
+            "poll {} did not pass",
+            poll.id
+        )));
+    }
+    match &poll.execution {
+        Some(execution) if execution.contract == contract && execution.msg == msg => {}
+        _ => {
+            return Err(StdError::generic_err(format!(
+                "poll {} did not propose this execution",
+                poll.id
+            )))
+        }
+    }
+
+    let mut executed = PrefixedStorage::new(PREFIX_EXECUTED_POLLS, &mut deps.storage);
+    if executed.get(&poll.id.to_be_bytes()).is_some() {
+        return Err(StdError::generic_err(format!(
+            "poll {} has already been executed",
+            poll.id
+        )));
+    }
+    executed.set(&poll.id.to_be_bytes(), &[1]);
+
+    Ok(HandleResponse {
+        messages: vec![WasmMsg::Execute {
+            contract_addr: contract.address,
+            callback_code_hash: contract.contract_hash,
+            msg,
+            send: vec![],
+        }
+        .into()],
+        log: vec![log("executed_poll_id", poll.id.to_string())],
+        data: Some(to_binary(&HandleAnswer::ExecuteProposal {
+            status: Success,
+        })?),
+    })
+}
+
+fn update_config<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    poll_code_id: Option<u64>,
+    poll_code_hash: Option<String>,
+    min_staking_amount: Option<Uint128>,
+    proposal_deposit: Option<Uint128>,
+) -> StdResult<HandleResponse> {
+    let mut config_store = TypedStoreMut::attach(&mut deps.storage);
+    let mut config: Config = config_store.load(CONFIG_KEY)?;
+
+    enforce_admin(&config, &env)?;
+
+    if let Some(poll_code_id) = poll_code_id {
+        config.poll_code_id = poll_code_id;
+    }
+    if let Some(poll_code_hash) = poll_code_hash {
+        config.poll_code_hash = poll_code_hash;
+    }
+    if let Some(min_staking_amount) = min_staking_amount {
+        config.min_staking_amount = min_staking_amount.u128();
+    }
+    if let Some(proposal_deposit) = proposal_deposit {
+        config.proposal_deposit = proposal_deposit.u128();
+    }
+    config_store.store(CONFIG_KEY, &config)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![],
+        data: Some(to_binary(&HandleAnswer::UpdateConfig { status: Success })?),
+    })
+}
+
+fn change_admin<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    address: HumanAddr,
+) -> StdResult<HandleResponse> {
+    let mut config_store = TypedStoreMut::attach(&mut deps.storage);
+    let mut config: Config = config_store.load(CONFIG_KEY)?;
+
+    enforce_admin(&config, &env)?;
+
+    config.admin = address;
+    config_store.store(CONFIG_KEY, &config)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![],
+        data: Some(to_binary(&HandleAnswer::ChangeAdmin { status: Success })?),
+    })
+}
+
+// Query functions
+
+fn query_config<S: Storage, A: Api, Q: Querier>(deps: &Extern<S, A, Q>) -> StdResult<Binary> {
+    let config: Config = TypedStore::attach(&deps.storage).load(CONFIG_KEY)?;
+
+    to_binary(&QueryAnswer::Config {
+        admin: config.admin,
+        poll_code_id: config.poll_code_id,
+        poll_code_hash: config.poll_code_hash,
+        staking_pool: config.staking_pool,
+        sefi: config.sefi,
+        min_staking_amount: Uint128(config.min_staking_amount),
+        proposal_deposit: Uint128(config.proposal_deposit),
+    })
+}
+
+fn query_active_polls<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    page: u32,
+    page_size: u32,
+) -> StdResult<Binary> {
+    let active: Vec<u32> = TypedStore::attach(&deps.storage).load(ACTIVE_POLLS_KEY)?;
+
+    // Newest polls first
+    let ids = active
+        .iter()
+        .rev()
+        .skip((page * page_size) as _)
+        .take(page_size as _)
+        .cloned();
+    let polls = load_polls(&deps.storage, ids)?;
+
+    to_binary(&QueryAnswer::ActivePolls {
+        polls,
+        total: active.len() as u32,
+    })
+}
+
+fn query_finished_polls<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    page: u32,
+    page_size: u32,
+) -> StdResult<Binary> {
+    let finished = load_finished_poll_ids(&deps.storage)?;
+
+    // Most recently finished polls first
+    let ids = finished
+        .iter()
+        .rev()
+        .skip((page * page_size) as _)
+        .take(page_size as _)
+        .cloned();
+    let polls = load_polls(&deps.storage, ids)?;
+
+    to_binary(&QueryAnswer::FinishedPolls {
+        polls,
+        total: finished.len() as u32,
+    })
+}
+
+// Helper functions
+
+fn create_poll<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    config: Config,
+    metadata: PollMetadata,
+    poll_config: PollConfig,
+    choices: Vec<String>,
+    execution: Option<ProposalExecution>,
+    deposit: u128,
+) -> StdResult<HandleResponse> {
+    // Polls created by the factory can't be finalized by an owner, so they must have an end
+    match poll_config.duration {
+        None => {
+            return Err(StdError::generic_err(
+                "polls created by the factory must have a duration",
+            ))
+        }
+        Some(duration) if duration > MAX_POLL_DURATION => {
+            return Err(StdError::generic_err(format!(
+                "polls cannot last more than {} blocks",
+                MAX_POLL_DURATION
+            )))
+        }
+        _ => {}
+    }
+
+    let active: Vec<u32> = TypedStore::attach(&deps.storage).load(ACTIVE_POLLS_KEY)?;
+    if active.len() >= MAX_ACTIVE_POLLS {
+        return Err(StdError::generic_err(format!(
+            "there are already {} active polls, which is the most there can be",
+            MAX_ACTIVE_POLLS
+        )));
+    }
+    // Stake can be moved to a fresh address, so this only slows authors down. The cap above is
+    // what bounds the staking pool's notifications
+    let authored = load_polls(&deps.storage, active.iter().cloned())?
+        .iter()
+        .filter(|p| p.metadata.author == metadata.author)
+        .count();
+    if authored >= MAX_ACTIVE_POLLS_PER_AUTHOR {
+        return Err(StdError::generic_err(format!(
+            "{} already has {} active polls, which is the most an author can have",
+            metadata.author, MAX_ACTIVE_POLLS_PER_AUTHOR
+        )));
+    }
+
+    let pending = Some(PollInfo {
+        id: 0,                         // Set on registration
+        address: HumanAddr::default(), // Set on registration
+        code_hash: config.poll_code_hash.clone(),
+        metadata: metadata.clone(),
+        deposit: Uint128(deposit),
+        quorum_reached: None,
+        execution: execution.clone(),
+    });
+    TypedStoreMut::attach(&mut deps.storage).store(PENDING_POLL_KEY, &pending)?;
+
+    let finished = load_finished_poll_ids(&deps.storage)?;
+    let label = format!(
+        "{}-poll-{}",
+        env.contract.address,
+        active.len() + finished.len()
+    );
+    let viewing_key = ViewingKey::new(&env, &config.prng_seed, label.as_bytes());
+
+    let messages: Vec<CosmosMsg> = vec![WasmMsg::Instantiate {
+        code_id: config.poll_code_id,
+        callback_code_hash: config.poll_code_hash,
+        msg: to_binary(&PollInitMsg {
+            metadata,
+            config: poll_config,
+            choices,
+            staking_pool: config.staking_pool,
+            factory: Some(SecretContract {
+                address: env.contract.address,
+                contract_hash: env.contract_code_hash,
+            }),
+            execution,
+            viewing_key: viewing_key.to_string(),
+        })?,
+        send: vec![],
+        label,
+    }
+    .into()];
+
+    Ok(HandleResponse {
+        messages,
+        log: vec![],
+        data: Some(to_binary(&HandleAnswer::NewPoll { status: Success })?),
+    })
+}
+
+fn staking_pool_msg(config: &Config, msg: LPStakingHandleMsg) -> StdResult<CosmosMsg> {
+    Ok(WasmMsg::Execute {
+        contract_addr: config.staking_pool.address.clone(),
+        callback_code_hash: config.staking_pool.contract_hash.clone(),
+        msg: to_binary(&msg)?,
+        send: vec![],
+    }
+    .into())
+}
+
+fn enforce_admin(config: &Config, env: &Env) -> StdResult<()> {
+    if config.admin != env.message.sender {
+        return Err(StdError::generic_err(format!(
+            "not an admin: {}",
+            env.message.sender
+        )));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::msg::ResponseStatus;
+    use cosmwasm_std::testing::*;
+    use cosmwasm_std::{from_binary, StdError};
+    use scrt_finance::secret_poll_msg::VotingMode;
+    use serde::{Deserialize, Serialize};
+
+    const PROPOSAL_DEPOSIT: u128 = 1_000;
+
+    // Helper functions
+
+    fn init_helper() -> (
+        StdResult<InitResponse>,
+        Extern<MockStorage, MockApi, MockQuerier>,
+    ) {
+        let mut deps = mock_dependencies(20, &[]);
+        let env = mock_env("admin", &[]);
+
+        let init_msg = InitMsg {
+            poll_code_id: 1,
+            poll_code_hash: "poll_hash".to_string(),
+            staking_pool: SecretContract {
+                address: HumanAddr("staking_pool".to_string()),
+                contract_hash: "staking_pool_hash".to_string(),
+            },
+            sefi: SecretContract {
+                address: HumanAddr("sefi".to_string()),
+                contract_hash: "sefi_hash".to_string(),
+            },
+            min_staking_amount: Uint128(100),
+            proposal_deposit: Uint128(PROPOSAL_DEPOSIT),
+            prng_seed: Binary::from("seed".as_bytes()),
+        };
+
+        (init(&mut deps, env, init_msg), deps)
+    }
+
+    fn poll_config(duration: Option<u64>) -> PollConfig {
+        PollConfig {
+            duration,
+            quorum: Some(10),
+            min_threshold: Some(50),
+            private_ballot: None,
+            voting_mode: Some(VotingMode::SingleChoice),
+            snapshot: None,
+        }
+    }
+
+    fn execution() -> ProposalExecution {
+        ProposalExecution {
+            contract: SecretContract {
+                address: HumanAddr("master".to_string()),
+                contract_hash: "master_hash".to_string(),
+            },
+            msg: Binary::from("set weights".as_bytes()),
+            winning_choice: 1,
+            timelock: 10,
+        }
+    }
+
+    /// Creates a poll for `deposit` SEFI, and registers it at the address "poll"
+    fn create_poll_helper(
+        deps: &mut Extern<MockStorage, MockApi, MockQuerier>,
+        deposit: u128,
+        execution: Option<ProposalExecution>,
+    ) -> StdResult<HandleResponse> {
+        let new_poll_msg = ReceiveMsg::NewPoll {
+            title: "Weights".to_string(),
+            description: "Change the pool weights".to_string(),
+            config: poll_config(Some(100)),
+            choices: vec!["yes".to_string(), "no".to_string()],
+            execution,
+        };
+        let receive_msg = HandleMsg::Receive {
+            sender: HumanAddr("author".to_string()),
+            from: HumanAddr("author".to_string()),
+            amount: Uint128(deposit),
+            msg: to_binary(&new_poll_msg).unwrap(),
+        };
+        handle(deps, mock_env("sefi", &[]), receive_msg)?;
+
+        handle(deps, mock_env("poll", &[]), HandleMsg::RegisterPoll {})
+    }
+
+    fn extract_error_msg<T>(error: StdResult<T>) -> String {
+        match error {
+            Ok(_) => panic!("An error was expected"),
+            Err(StdError::GenericErr { msg, .. }) => msg,
+            Err(err) => panic!("Unexpected error: {:?}", err),
+        }
+    }
+
+    /// The recipient and amount of every SNIP-20 transfer in `messages`
+    fn extract_transfers(messages: &[CosmosMsg]) -> Vec<(HumanAddr, u128)> {
+        messages
+            .iter()
+            .filter_map(|m| match m {
+                CosmosMsg::Wasm(WasmMsg::Execute { msg, .. }) => from_binary(msg).ok(),
+                _ => None,
+            })
+            .map(|m| match m {
+                Snip20HandleMsg::Transfer {
+                    recipient, amount, ..
+                } => (recipient, amount.u128()),
+            })
+            .collect()
+    }
+
+    /// Every message in `messages` that is sent to the staking pool
+    fn extract_staking_pool_msgs(messages: &[CosmosMsg]) -> Vec<LPStakingHandleMsg> {
+        messages
+            .iter()
+            .filter_map(|m| match m {
+                CosmosMsg::Wasm(WasmMsg::Execute {
+                    contract_addr, msg, ..
+                }) if contract_addr.0 == "staking_pool" => Some(from_binary(msg).unwrap()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    // Tests
+
+    #[test]
+    fn test_new_poll() {
+        let (init_result, mut deps) = init_helper();
+        assert!(init_result.is_ok());
+
+        let result = create_poll_helper(&mut deps, PROPOSAL_DEPOSIT - 1, None);
+        assert_eq!(
+            extract_error_msg(result),
+            format!(
+                "insufficient proposal deposit: given={}, required={}",
+                PROPOSAL_DEPOSIT - 1,
+                PROPOSAL_DEPOSIT
+            )
+        );
+
+        let result = handle(&mut deps, mock_env("poll", &[]), HandleMsg::RegisterPoll {});
+        assert_eq!(result.unwrap_err(), StdError::unauthorized());
+
+        let response = create_poll_helper(&mut deps, PROPOSAL_DEPOSIT, None).unwrap();
+        assert_eq!(
+            extract_staking_pool_msgs(&response.messages),
+            vec![
+                LPStakingHandleMsg::AddSubscribers {
+                    contracts: vec![SecretContract {
+                        address: HumanAddr("poll".to_string()),
+                        contract_hash: "poll_hash".to_string(),
+                    }],
+                },
+                LPStakingHandleMsg::AddTrustedContracts {
+                    contracts: vec![HumanAddr("poll".to_string())],
+                },
+            ]
+        );
+        let answer: HandleAnswer = from_binary(&response.data.unwrap()).unwrap();
+        assert_eq!(
+            to_binary(&answer).unwrap(),
+            to_binary(&HandleAnswer::RegisterPoll { status: Success }).unwrap()
+        );
+
+        let active: QueryAnswer = from_binary(
+            &query(
+                &deps,
+                QueryMsg::ActivePolls {
+                    page: None,
+                    page_size: 10,
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        match active {
+            QueryAnswer::ActivePolls { polls, total } => {
+                assert_eq!(total, 1);
+                assert_eq!(polls[0].address, HumanAddr("poll".to_string()));
+                assert_eq!(polls[0].deposit, Uint128(PROPOSAL_DEPOSIT));
+            }
+            _ => panic!("Unexpected query answer"),
+        }
+    }
+
+    #[test]
+    fn test_new_poll_requires_duration() {
+        let (_, mut deps) = init_helper();
+
+        let new_poll_msg = ReceiveMsg::NewPoll {
+            title: "Forever".to_string(),
+            description: "".to_string(),
+            config: poll_config(None),
+            choices: vec!["yes".to_string(), "no".to_string()],
+            execution: None,
+        };
+        let receive_msg = HandleMsg::Receive {
+            sender: HumanAddr("author".to_string()),
+            from: HumanAddr("author".to_string()),
+            amount: Uint128(PROPOSAL_DEPOSIT),
+            msg: to_binary(&new_poll_msg).unwrap(),
+        };
+        let result = handle(&mut deps, mock_env("sefi", &[]), receive_msg);
+        assert_eq!(
+            extract_error_msg(result),
+            "polls created by the factory must have a duration"
+        );
+    }
+
+    #[test]
+    fn test_poll_limits() {
+        let (_, mut deps) = init_helper();
+
+        let receive_msg = |author: &str, duration: u64| {
+            let new_poll_msg = ReceiveMsg::NewPoll {
+                title: "Weights".to_string(),
+                description: "".to_string(),
+                config: poll_config(Some(duration)),
+                choices: vec!["yes".to_string(), "no".to_string()],
+                execution: None,
+            };
+            HandleMsg::Receive {
+                sender: HumanAddr(author.to_string()),
+                from: HumanAddr(author.to_string()),
+                amount: Uint128(PROPOSAL_DEPOSIT),
+                msg: to_binary(&new_poll_msg).unwrap(),
+            }
+        };
+
+        let result = handle(
+            &mut deps,
+            mock_env("sefi", &[]),
+            receive_msg("author", MAX_POLL_DURATION + 1),
+        );
+        assert_eq!(
+            extract_error_msg(result),
+            format!("polls cannot last more than {} blocks", MAX_POLL_DURATION)
+        );
+
+        // Authors can only have a few active polls at a time
+        for i in 0..MAX_ACTIVE_POLLS_PER_AUTHOR {
+            let msg = receive_msg("author", MAX_POLL_DURATION);
+            handle(&mut deps, mock_env("sefi", &[]), msg).unwrap();
+            let poll = format!("poll{}", i);
+            handle(&mut deps, mock_env(poll, &[]), HandleMsg::RegisterPoll {}).unwrap();
+        }
+        let result = handle(&mut deps, mock_env("sefi", &[]), receive_msg("author", 100));
+        assert_eq!(
+            extract_error_msg(result),
+            format!(
+                "author already has {} active polls, which is the most an author can have",
+                MAX_ACTIVE_POLLS_PER_AUTHOR
+            )
+        );
+
+        // And there can only be a few active polls overall
+        for i in MAX_ACTIVE_POLLS_PER_AUTHOR..MAX_ACTIVE_POLLS {
+            let author = format!("author{}", i);
+            handle(&mut deps, mock_env("sefi", &[]), receive_msg(&author, 100)).unwrap();
+            let poll = format!("poll{}", i);
+            handle(&mut deps, mock_env(poll, &[]), HandleMsg::RegisterPoll {}).unwrap();
+        }
+        let result = handle(
+            &mut deps,
+            mock_env("sefi", &[]),
+            receive_msg("someone", 100),
+        );
+        assert_eq!(
+            extract_error_msg(result),
+            format!(
+                "there are already {} active polls, which is the most there can be",
+                MAX_ACTIVE_POLLS
+            )
+        );
+
+        // Finalized polls free up their slots
+        let finalized_msg = HandleMsg::PollFinalized {
+            quorum_reached: true,
+        };
+        handle(&mut deps, mock_env("poll0", &[]), finalized_msg).unwrap();
+        handle(&mut deps, mock_env("sefi", &[]), receive_msg("author", 100)).unwrap();
+    }
+
+    #[test]
+    fn test_deposit_returned_on_quorum() {
+        let (_, mut deps) = init_helper();
+        create_poll_helper(&mut deps, PROPOSAL_DEPOSIT, None).unwrap();
+
+        let finalized_msg = HandleMsg::PollFinalized {
+            quorum_reached: true,
+        };
+        let response = handle(&mut deps, mock_env("poll", &[]), finalized_msg).unwrap();
+        assert_eq!(
+            extract_transfers(&response.messages),
+            vec![(HumanAddr("author".to_string()), PROPOSAL_DEPOSIT)]
+        );
+        assert_eq!(
+            extract_staking_pool_msgs(&response.messages),
+            vec![
+                LPStakingHandleMsg::RemoveSubscribers {
+                    contracts: vec![HumanAddr("poll".to_string())],
+                },
+                LPStakingHandleMsg::RemoveTrustedContracts {
+                    contracts: vec![HumanAddr("poll".to_string())],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_deposit_forfeited_without_quorum() {
+        let (_, mut deps) = init_helper();
+        create_poll_helper(&mut deps, PROPOSAL_DEPOSIT, None).unwrap();
+
+        let finalized_msg = HandleMsg::PollFinalized {
+            quorum_reached: false,
+        };
+        let response = handle(&mut deps, mock_env("poll", &[]), finalized_msg.clone()).unwrap();
+        assert_eq!(
+            extract_transfers(&response.messages),
+            vec![(HumanAddr("admin".to_string()), PROPOSAL_DEPOSIT)]
+        );
+
+        // A poll can only be finalized once
+        let result = handle(&mut deps, mock_env("poll", &[]), finalized_msg);
+        assert_eq!(
+            extract_error_msg(result),
+            "poll is not an active poll of this factory"
+        );
+    }
+
+    #[test]
+    fn test_execute_proposal() {
+        let (_, mut deps) = init_helper();
+        create_poll_helper(&mut deps, PROPOSAL_DEPOSIT, Some(execution())).unwrap();
+
+        let execute_msg = HandleMsg::ExecuteProposal {
+            contract: execution().contract,
+            msg: execution().msg,
+        };
+        let result = handle(&mut deps, mock_env("poll", &[]), execute_msg.clone());
+        assert_eq!(extract_error_msg(result), "poll 0 did not pass");
+
+        let finalized_msg = HandleMsg::PollFinalized {
+            quorum_reached: true,
+        };
+        handle(&mut deps, mock_env("poll", &[]), finalized_msg).unwrap();
+
+        // Only what the poll was created with can be executed
+        let other_msg = HandleMsg::ExecuteProposal {
+            contract: execution().contract,
+            msg: Binary::from("change admin".as_bytes()),
+        };
+        let result = handle(&mut deps, mock_env("poll", &[]), other_msg);
+        assert_eq!(
+            extract_error_msg(result),
+            "poll 0 did not propose this execution"
+        );
+        let other_contract_msg = HandleMsg::ExecuteProposal {
+            contract: SecretContract {
+                address: HumanAddr("dev_fund".to_string()),
+                contract_hash: "dev_fund_hash".to_string(),
+            },
+            msg: execution().msg,
+        };
+        let result = handle(&mut deps, mock_env("poll", &[]), other_contract_msg);
+        assert_eq!(
+            extract_error_msg(result),
+            "poll 0 did not propose this execution"
+        );
+
+        let result = handle(&mut deps, mock_env("not_a_poll", &[]), execute_msg.clone());
+        assert_eq!(
+            extract_error_msg(result),
+            "not_a_poll is not a poll of this factory"
+        );
+
+        let response = handle(&mut deps, mock_env("poll", &[]), execute_msg.clone()).unwrap();
+        assert_eq!(
+            response.messages,
+            vec![CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: HumanAddr("master".to_string()),
+                callback_code_hash: "master_hash".to_string(),
+                msg: execution().msg,
+                send: vec![],
+            })]
+        );
+
+        let result = handle(&mut deps, mock_env("poll", &[]), execute_msg);
+        assert_eq!(
+            extract_error_msg(result),
+            "poll 0 has already been executed"
+        );
+    }
+
+    #[test]
+    fn test_execute_without_proposal() {
+        let (_, mut deps) = init_helper();
+        create_poll_helper(&mut deps, PROPOSAL_DEPOSIT, None).unwrap();
+
+        let finalized_msg = HandleMsg::PollFinalized {
+            quorum_reached: true,
+        };
+        handle(&mut deps, mock_env("poll", &[]), finalized_msg).unwrap();
+
+        let execute_msg = HandleMsg::ExecuteProposal {
+            contract: execution().contract,
+            msg: execution().msg,
+        };
+        let result = handle(&mut deps, mock_env("poll", &[]), execute_msg);
+        assert_eq!(
+            extract_error_msg(result),
+            "poll 0 did not propose this execution"
+        );
+    }
+
+    #[test]
+    fn test_update_config() {
+        let (_, mut deps) = init_helper();
+
+        create_poll_helper(&mut deps, PROPOSAL_DEPOSIT, None).unwrap();
+
+        let update_msg = HandleMsg::UpdateConfig {
+            poll_code_id: Some(2),
+            poll_code_hash: Some("new_hash".to_string()),
+            min_staking_amount: None,
+            proposal_deposit: Some(Uint128(5)),
+        };
+        let result = handle(&mut deps, mock_env("not_admin", &[]), update_msg.clone());
+        assert_eq!(extract_error_msg(result), "not an admin: not_admin");
+
+        let response = handle(&mut deps, mock_env("admin", &[]), update_msg).unwrap();
+        let answer: HandleAnswer = from_binary(&response.data.unwrap()).unwrap();
+        assert!(matches!(
+            answer,
+            HandleAnswer::UpdateConfig {
+                status: ResponseStatus::Success
+            }
+        ));
+
+        let config: QueryAnswer = from_binary(&query(&deps, QueryMsg::Config {}).unwrap()).unwrap();
+        match config {
+            QueryAnswer::Config {
+                poll_code_id,
+                poll_code_hash,
+                proposal_deposit,
+                ..
+            } => {
+                assert_eq!(poll_code_id, 2);
+                assert_eq!(poll_code_hash, "new_hash".to_string());
+                assert_eq!(proposal_deposit, Uint128(5));
+            }
+            _ => panic!("Unexpected query answer"),
+        }
+
+        // Existing polls keep the hash they were instantiated with
+        let query_msg = QueryMsg::ActivePolls {
+            page: None,
+            page_size: 10,
+        };
+        match from_binary(&query(&deps, query_msg).unwrap()).unwrap() {
+            QueryAnswer::ActivePolls { polls, .. } => {
+                assert_eq!(polls[0].code_hash, "poll_hash".to_string())
+            }
+            _ => panic!("Unexpected query answer"),
+        }
+    }
+
+    /// SNIP20 token handle messages
+    #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+    #[serde(rename_all = "snake_case")]
+    pub enum Snip20HandleMsg {
+        Transfer {
+            recipient: HumanAddr,
+            amount: Uint128,
+            padding: Option<String>,
+        },
+    }
+}
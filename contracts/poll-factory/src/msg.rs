@@ -0,0 +1,124 @@
+use crate::state::PollInfo;
+use cosmwasm_std::{Binary, HumanAddr, Uint128};
+use schemars::JsonSchema;
+use scrt_finance::secret_poll_msg::{PollConfig, ProposalExecution};
+use scrt_finance::types::SecretContract;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct InitMsg {
+    pub poll_code_id: u64,
+    pub poll_code_hash: String,
+    pub staking_pool: SecretContract,
+    pub sefi: SecretContract,
+    pub min_staking_amount: Uint128,
+    pub proposal_deposit: Uint128,
+    pub prng_seed: Binary, // Used to generate the viewing keys of the polls
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum HandleMsg {
+    // Creates a poll for an author with at least `min_staking_amount` staked. Like polls created
+    // with a deposit, it can't last more than `MAX_POLL_DURATION` blocks, and it counts towards the
+    // caps on active polls overall and per author
+    NewPoll {
+        title: String,
+        description: String,
+        config: PollConfig,
+        choices: Vec<String>,
+        execution: Option<ProposalExecution>,
+        staking_pool_viewing_key: String,
+    },
+
+    // Registered commands
+    Receive {
+        sender: HumanAddr,
+        from: HumanAddr,
+        amount: Uint128,
+        msg: Binary,
+    },
+
+    // Poll callbacks
+    RegisterPoll {},
+    PollFinalized {
+        quorum_reached: bool,
+    },
+    ExecuteProposal {
+        contract: SecretContract,
+        msg: Binary,
+    },
+
+    // Admin commands
+    UpdateConfig {
+        poll_code_id: Option<u64>,
+        poll_code_hash: Option<String>,
+        min_staking_amount: Option<Uint128>,
+        proposal_deposit: Option<Uint128>,
+    },
+    ChangeAdmin {
+        address: HumanAddr,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ReceiveMsg {
+    // Creates a poll for a SEFI deposit of at least `proposal_deposit`. The deposit is returned
+    // to the author once the poll reaches quorum. Otherwise it is forfeited to the factory's admin
+    NewPoll {
+        title: String,
+        description: String,
+        config: PollConfig,
+        choices: Vec<String>,
+        execution: Option<ProposalExecution>,
+    },
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum HandleAnswer {
+    NewPoll { status: ResponseStatus },
+    RegisterPoll { status: ResponseStatus },
+    PollFinalized { status: ResponseStatus },
+    ExecuteProposal { status: ResponseStatus },
+    UpdateConfig { status: ResponseStatus },
+    ChangeAdmin { status: ResponseStatus },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum QueryMsg {
+    Config {},
+    ActivePolls { page: Option<u32>, page_size: u32 },
+    FinishedPolls { page: Option<u32>, page_size: u32 },
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum QueryAnswer {
+    Config {
+        admin: HumanAddr,
+        poll_code_id: u64,
+        poll_code_hash: String,
+        staking_pool: SecretContract,
+        sefi: SecretContract,
+        min_staking_amount: Uint128,
+        proposal_deposit: Uint128,
+    },
+    ActivePolls {
+        polls: Vec<PollInfo>,
+        total: u32,
+    },
+    FinishedPolls {
+        polls: Vec<PollInfo>,
+        total: u32,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ResponseStatus {
+    Success,
+    Failure,
+}
@@ -0,0 +1,23 @@
+use crate::state::{Config, CONFIG_KEY};
+use cosmwasm_std::{Api, Extern, HumanAddr, Querier, StdResult, Storage};
+use secret_toolkit::snip20;
+use secret_toolkit::storage::TypedStore;
+
+pub fn query_staked_balance<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    staker: HumanAddr,
+    key: String,
+) -> StdResult<u128> {
+    let config: Config = TypedStore::attach(&deps.storage).load(CONFIG_KEY)?;
+
+    let balance = snip20::balance_query(
+        &deps.querier,
+        staker,
+        key,
+        256,
+        config.staking_pool.contract_hash,
+        config.staking_pool.address,
+    )?;
+
+    Ok(balance.amount.u128())
+}
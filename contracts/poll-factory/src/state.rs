@@ -0,0 +1,133 @@
+use cosmwasm_std::{HumanAddr, ReadonlyStorage, StdError, StdResult, Storage, Uint128};
+use cosmwasm_storage::{PrefixedStorage, ReadonlyPrefixedStorage};
+use schemars::JsonSchema;
+use scrt_finance::secret_poll_msg::{PollMetadata, ProposalExecution};
+use scrt_finance::types::SecretContract;
+use secret_toolkit::storage::{AppendStore, AppendStoreMut, TypedStore, TypedStoreMut};
+use serde::{Deserialize, Serialize};
+
+pub const CONFIG_KEY: &[u8] = b"config";
+pub const PENDING_POLL_KEY: &[u8] = b"pendingpoll";
+pub const ACTIVE_POLLS_KEY: &[u8] = b"activepolls";
+pub const PREFIX_POLLS: &[u8] = b"polls";
+pub const PREFIX_POLL_IDS: &[u8] = b"pollids";
+pub const PREFIX_FINISHED_POLLS: &[u8] = b"finishedpolls";
+pub const PREFIX_EXECUTED_POLLS: &[u8] = b"executedpolls";
+
+#[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Clone)]
+pub struct Config {
+    pub admin: HumanAddr,
+    pub poll_code_id: u64,
+    pub poll_code_hash: String,
+    pub staking_pool: SecretContract,
+    pub sefi: SecretContract,
+    pub min_staking_amount: u128,
+    pub proposal_deposit: u128,
+    pub prng_seed: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+pub struct PollInfo {
+    pub id: u32,
+    pub address: HumanAddr,
+    pub code_hash: String, // The hash the poll was instantiated with
+    pub metadata: PollMetadata,
+    pub deposit: Uint128,
+    pub quorum_reached: Option<bool>, // None while the poll is active
+    pub execution: Option<ProposalExecution>, // What the poll may execute through the factory
+}
+
+/// Stores a newly registered poll and marks it as active. Returns the poll's id
+pub fn store_new_poll<S: Storage>(storage: &mut S, mut poll: PollInfo) -> StdResult<u32> {
+    let mut polls_storage = PrefixedStorage::new(PREFIX_POLLS, storage);
+    let mut polls = AppendStoreMut::attach_or_create(&mut polls_storage)?;
+    poll.id = polls.len();
+    polls.push(&poll)?;
+
+    let mut ids_storage = PrefixedStorage::new(PREFIX_POLL_IDS, storage);
+    TypedStoreMut::attach(&mut ids_storage).store(poll.address.0.as_bytes(), &poll.id)?;
+
+    let mut active: Vec<u32> = TypedStore::attach(&*storage).load(ACTIVE_POLLS_KEY)?;
+    active.push(poll.id);
+    TypedStoreMut::attach(storage).store(ACTIVE_POLLS_KEY, &active)?;
+
+    Ok(poll.id)
+}
+
+/// Moves an active poll to the finished polls index
+pub fn finish_poll<S: Storage>(
+    storage: &mut S,
+    address: &HumanAddr,
+    quorum_reached: bool,
+) -> StdResult<PollInfo> {
+    let id = load_active_poll_id(&*storage, address)?;
+
+    let mut polls_storage = PrefixedStorage::new(PREFIX_POLLS, storage);
+    let mut polls = AppendStoreMut::<PollInfo, _>::attach_or_create(&mut polls_storage)?;
+    let mut poll = polls.get_at(id)?;
+    poll.quorum_reached = Some(quorum_reached);
+    polls.set_at(id, &poll)?;
+
+    let mut active: Vec<u32> = TypedStore::attach(&*storage).load(ACTIVE_POLLS_KEY)?;
+    active.retain(|p| *p != id);
+    TypedStoreMut::attach(storage).store(ACTIVE_POLLS_KEY, &active)?;
+
+    let mut finished_storage = PrefixedStorage::new(PREFIX_FINISHED_POLLS, storage);
+    AppendStoreMut::attach_or_create(&mut finished_storage)?.push(&id)?;
+
+    Ok(poll)
+}
+
+pub fn load_poll<S: ReadonlyStorage>(storage: &S, address: &HumanAddr) -> StdResult<PollInfo> {
+    let ids_storage = ReadonlyPrefixedStorage::new(PREFIX_POLL_IDS, storage);
+    let id: u32 = TypedStore::attach(&ids_storage)
+        .load(address.0.as_bytes())
+        .map_err(|_| StdError::generic_err(format!("{} is not a poll of this factory", address)))?;
+
+    let polls = load_polls(storage, vec![id].into_iter())?;
+    polls
+        .into_iter()
+        .next()
+        .ok_or_else(|| StdError::generic_err(format!("{} is not a poll of this factory", address)))
+}
+
+pub fn load_active_poll_id<S: ReadonlyStorage>(storage: &S, address: &HumanAddr) -> StdResult<u32> {
+    let ids_storage = ReadonlyPrefixedStorage::new(PREFIX_POLL_IDS, storage);
+    let id: Option<u32> = TypedStore::attach(&ids_storage)
+        .load(address.0.as_bytes())
+        .ok();
+
+    let active: Vec<u32> = TypedStore::attach(storage).load(ACTIVE_POLLS_KEY)?;
+    match id {
+        Some(id) if active.contains(&id) => Ok(id),
+        _ => Err(StdError::generic_err(format!(
+            "{} is not an active poll of this factory",
+            address
+        ))),
+    }
+}
+
+pub fn load_polls<S: ReadonlyStorage>(
+    storage: &S,
+    ids: impl Iterator<Item = u32>,
+) -> StdResult<Vec<PollInfo>> {
+    let polls_storage = ReadonlyPrefixedStorage::new(PREFIX_POLLS, storage);
+    let polls = if let Some(result) = AppendStore::<PollInfo, _>::attach(&polls_storage) {
+        result?
+    } else {
+        return Ok(vec![]);
+    };
+
+    ids.map(|id| polls.get_at(id)).collect()
+}
+
+pub fn load_finished_poll_ids<S: ReadonlyStorage>(storage: &S) -> StdResult<Vec<u32>> {
+    let finished_storage = ReadonlyPrefixedStorage::new(PREFIX_FINISHED_POLLS, storage);
+    let finished = if let Some(result) = AppendStore::<u32, _>::attach(&finished_storage) {
+        result?
+    } else {
+        return Ok(vec![]);
+    };
+
+    finished.iter().collect()
+}
@@ -1,28 +1,43 @@
 use cosmwasm_std::{
-    log, to_binary, Api, Binary, Env, Extern, HandleResponse, HumanAddr, InitResponse, Querier,
-    StdError, StdResult, Storage, Uint128, WasmMsg,
+    log, to_binary, Api, Binary, CosmosMsg, Env, Extern, HandleResponse, HumanAddr, InitResponse,
+    Querier, ReadonlyStorage, StdError, StdResult, Storage, Uint128, WasmMsg,
 };
+use cosmwasm_storage::{PrefixedStorage, ReadonlyPrefixedStorage};
 
-use crate::msg::{HandleMsg, InitMsg, QueryMsg, ResponseStatus};
+use crate::msg::{HandleMsg, QueryAnswer, QueryMsg, QueryWithPermit, ResponseStatus};
+use crate::querier::{
+    authenticate_staker, query_delegation, query_total_staked, query_voting_power,
+};
 use crate::state::{
-    ChoiceIdMap, Tally, Vote, CHOICE_ID_MAP_KEY, CONFIG_KEY, METADATA_KEY, OWNER_KEY,
-    STAKING_POOL_KEY, TALLY_KEY,
+    load_voters, push_voter, voters_count, ChoiceIdMap, DelegatedPower, Delegation, PollResult,
+    Rankings, Tally, Vote, CHOICE_ID_MAP_KEY, CONFIG_KEY, CONTRACT_ADDRESS_KEY, END_HEIGHT_KEY,
+    EXECUTED_KEY, EXECUTION_KEY, FACTORY_KEY, FINALIZE_CURSOR_KEY, MAX_RANKED_CHOICES,
+    METADATA_KEY, OWNER_KEY, PREFIX_DELEGATED_POWER, PREFIX_DELEGATIONS, PREFIX_VOTES,
+    PREFIX_WITHHELD_FROM, RANKINGS_KEY, RESULT_KEY, SNAPSHOT_HEIGHT_KEY, STAKING_POOL_KEY,
+    TALLY_KEY, TOTAL_VOTING_POWER_KEY, VIEWING_KEY_KEY,
+};
+use scrt_finance::lp_staking_msg::LPStakingHandleMsg;
+use scrt_finance::math;
+use scrt_finance::permit::{self, Permission, Permit};
+use scrt_finance::secret_poll_msg::{
+    PollConfig, PollFactoryHandleMsg, PollInitMsg, PollMetadata, ProposalExecution, VotingMode,
 };
 use scrt_finance::types::SecretContract;
-use secret_toolkit::snip20;
 use secret_toolkit::storage::{TypedStore, TypedStoreMut};
 use std::collections::HashMap;
 
 pub fn init<S: Storage, A: Api, Q: Querier>(
     deps: &mut Extern<S, A, Q>,
     env: Env,
-    msg: InitMsg,
+    msg: PollInitMsg,
 ) -> StdResult<InitResponse> {
     let owner = env.message.sender;
     TypedStoreMut::attach(&mut deps.storage).store(OWNER_KEY, &owner)?;
+    TypedStoreMut::attach(&mut deps.storage).store(CONTRACT_ADDRESS_KEY, &env.contract.address)?;
     TypedStoreMut::attach(&mut deps.storage).store(METADATA_KEY, &msg.metadata)?;
     TypedStoreMut::attach(&mut deps.storage).store(CONFIG_KEY, &msg.config)?;
     TypedStoreMut::attach(&mut deps.storage).store(STAKING_POOL_KEY, &msg.staking_pool)?;
+    TypedStoreMut::attach(&mut deps.storage).store(VIEWING_KEY_KEY, &msg.viewing_key)?;
 
     if msg.choices.len() > (u8::MAX - 1) as usize {
         return Err(StdError::generic_err(format!(
@@ -30,6 +45,36 @@ pub fn init<S: Storage, A: Api, Q: Querier>(
             u8::MAX - 1
         )));
     }
+    if msg.config.voting_mode == Some(VotingMode::RankedChoice)
+        && msg.choices.len() > MAX_RANKED_CHOICES
+    {
+        return Err(StdError::generic_err(format!(
+            "the number of choices for a ranked-choice poll cannot exceed {}",
+            MAX_RANKED_CHOICES
+        )));
+    }
+
+    for percentage in vec![msg.config.quorum, msg.config.min_threshold] {
+        if percentage.unwrap_or(0) > 100 {
+            return Err(StdError::generic_err(
+                "quorum and minimum threshold are percentages and cannot exceed 100",
+            ));
+        }
+    }
+
+    let end_height = msg
+        .config
+        .duration
+        .map(|d| math::to_u64(math::add(env.block.height as u128, d as u128)?))
+        .transpose()?;
+    TypedStoreMut::attach(&mut deps.storage).store(END_HEIGHT_KEY, &end_height)?;
+
+    let snapshot_height = if msg.config.snapshot.unwrap_or(false) {
+        Some(env.block.height)
+    } else {
+        None
+    };
+    TypedStoreMut::attach(&mut deps.storage).store(SNAPSHOT_HEIGHT_KEY, &snapshot_height)?;
 
     // Creating a mapping between a choice's text and it's ID for convenience
     let mut i = 0;
@@ -48,8 +93,41 @@ pub fn init<S: Storage, A: Api, Q: Querier>(
         tally.insert(choice.0, 0);
     }
     TypedStoreMut::attach(&mut deps.storage).store(TALLY_KEY, &tally)?;
+    TypedStoreMut::attach(&mut deps.storage).store(TOTAL_VOTING_POWER_KEY, &0u128)?;
+    TypedStoreMut::<Rankings, S>::attach(&mut deps.storage).store(RANKINGS_KEY, &vec![])?;
+
+    TypedStoreMut::attach(&mut deps.storage).store(FACTORY_KEY, &msg.factory)?;
+
+    if let Some(execution) = &msg.execution {
+        if !tally.contains_key(&execution.winning_choice) {
+            return Err(StdError::generic_err(format!(
+                "choice {} does not exist in this poll",
+                execution.winning_choice
+            )));
+        }
+    }
+    TypedStoreMut::attach(&mut deps.storage).store(EXECUTION_KEY, &msg.execution)?;
+    TypedStoreMut::attach(&mut deps.storage).store(EXECUTED_KEY, &false)?;
+
+    // The staking pool only lets the poll read its voters' balances with the poll's own key
+    let mut messages: Vec<CosmosMsg> = vec![WasmMsg::Execute {
+        contract_addr: msg.staking_pool.address,
+        callback_code_hash: msg.staking_pool.contract_hash,
+        msg: to_binary(&LPStakingHandleMsg::SetViewingKey {
+            key: msg.viewing_key,
+            padding: None,
+        })?,
+        send: vec![],
+    }
+    .into()];
+    if let Some(factory) = msg.factory {
+        messages.push(factory_msg(factory, PollFactoryHandleMsg::RegisterPoll {})?);
+    }
 
-    Ok(InitResponse::default())
+    Ok(InitResponse {
+        messages,
+        log: vec![],
+    })
 }
 
 pub fn handle<S: Storage, A: Api, Q: Querier>(
@@ -58,9 +136,37 @@ pub fn handle<S: Storage, A: Api, Q: Querier>(
     msg: HandleMsg,
 ) -> StdResult<HandleResponse> {
     match msg {
-        HandleMsg::Vote { .. } => unimplemented!(),
-        HandleMsg::UpdateVotingPower { .. } => unimplemented!(),
-        HandleMsg::Finalize { .. } => unimplemented!(),
+        HandleMsg::Vote { choices } => vote(deps, env, choices),
+        HandleMsg::Delegate { delegate } => delegate_vote(deps, env, delegate),
+        HandleMsg::UpdateVotingPower {
+            voter,
+            old_power,
+            new_power,
+            delegate,
+        } => update_voting_power(
+            deps,
+            env,
+            voter,
+            old_power.u128(),
+            new_power.u128(),
+            delegate,
+        ),
+        HandleMsg::UpdateDelegation {
+            delegator,
+            voting_power,
+            old_delegate,
+            new_delegate,
+        } => update_delegation(
+            deps,
+            env,
+            delegator,
+            voting_power.u128(),
+            old_delegate,
+            new_delegate,
+        ),
+        HandleMsg::Finalize { batch_size } => finalize(deps, env, batch_size),
+        HandleMsg::Execute {} => execute(deps, env),
+        HandleMsg::RevokePermit { permit_name } => revoke_permit(deps, env, permit_name),
     }
 }
 
@@ -68,73 +174,1544 @@ pub fn query<S: Storage, A: Api, Q: Querier>(
     deps: &Extern<S, A, Q>,
     msg: QueryMsg,
 ) -> StdResult<Binary> {
-    unimplemented!()
+    match msg {
+        QueryMsg::Choices {} => query_choices(deps),
+        QueryMsg::HasVoted { voter } => query_has_voted(deps, voter),
+        QueryMsg::Voters { page, page_size } => query_voters(deps, page.unwrap_or(0), page_size),
+        QueryMsg::Tally {} => query_tally(deps),
+        QueryMsg::Metadata {} => query_metadata(deps),
+        QueryMsg::Result {} => query_result(deps),
+        QueryMsg::Execution {} => query_execution(deps),
+        QueryMsg::Vote { voter, key } => {
+            authenticate_voter(deps, &voter, key)?;
+            query_vote(deps, voter)
+        }
+        QueryMsg::Delegation { voter, key } => {
+            authenticate_voter(deps, &voter, key)?;
+            query_delegation_of(deps, voter)
+        }
+        QueryMsg::WithPermit { permit, query } => permit_queries(deps, permit, query),
+    }
 }
 
+fn permit_queries<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    permit: Permit,
+    query: QueryWithPermit,
+) -> StdResult<Binary> {
+    let contract_address: HumanAddr =
+        TypedStore::attach(&deps.storage).load(CONTRACT_ADDRESS_KEY)?;
+    let voter = permit::validate(&deps.storage, &deps.api, &permit, &contract_address)?;
+
+    if !permit.check_permission(&Permission::Owner) {
+        return Err(StdError::generic_err(
+            "this query requires the Owner permission",
+        ));
+    }
+
+    match query {
+        QueryWithPermit::Vote {} => query_vote(deps, voter),
+        QueryWithPermit::Delegation {} => query_delegation_of(deps, voter),
+    }
+}
+
+// Handle functions
+
 pub fn vote<S: Storage, A: Api, Q: Querier>(
     deps: &mut Extern<S, A, Q>,
     env: Env,
-    choice: u8,
-    key: String,
+    choices: Vec<u8>,
 ) -> StdResult<HandleResponse> {
-    let mut tally: Tally = TypedStoreMut::attach(&mut deps.storage).load(TALLY_KEY)?;
-
-    if let Some(choice_tally) = tally.get_mut(&choice) {
-        let staking_pool: SecretContract =
-            TypedStore::attach(&deps.storage).load(STAKING_POOL_KEY)?;
-        let voting_power = snip20::balance_query(
-            &deps.querier,
-            env.message.sender.clone(),
-            key,
-            256,
-            staking_pool.contract_hash,
-            staking_pool.address,
+    enforce_poll_open(deps, &env)?;
+    enforce_valid_choices(deps, &choices)?;
+
+    let voter = env.message.sender;
+    if let Some(previous) = load_vote(deps, &voter) {
+        // Changing a vote moves all of its weight, including delegated power, to the new choice
+        let weight = math::add(
+            previous.voting_power,
+            load_delegated_power(&deps.storage, &voter).effective(),
         )?;
-        *choice_tally += voting_power.amount.u128();
-        TypedStoreMut::attach(&mut deps.storage).store(TALLY_KEY, &tally)?;
+        adjust_tally(deps, &previous.choices, weight, 0, Overflow::Fail)?;
+        adjust_tally(deps, &choices, 0, weight, Overflow::Fail)?;
+
+        store_vote(deps, voter.clone(), choices, previous.voting_power)?;
+    } else {
+        let voting_power = query_voting_power(deps, voter.clone())?;
+        let (global_delegate, global_power) = query_delegation(deps, voter.clone())?;
 
-        store_vote(
+        // Voting takes precedence over any delegation the voter has made
+        if let Some(delegation) = load_delegation(&deps.storage, &voter) {
+            update_delegated_power(deps, &delegation.delegate, Overflow::Fail, |p| {
+                p.local = math::sub(p.local, delegation.voting_power)?;
+                Ok(())
+            })?;
+            release_withheld_power(deps, &voter, delegation.voting_power, Overflow::Fail)?;
+            store_delegation(&mut deps.storage, &voter, None)?;
+        }
+        withhold_power(deps, &voter, global_delegate, voting_power, Overflow::Fail)?;
+
+        let mut delegated_power = load_delegated_power(&deps.storage, &voter);
+        delegated_power.global = global_power;
+        store_delegated_power(&mut deps.storage, &voter, &delegated_power)?;
+
+        adjust_tally(
             deps,
-            env.message.sender.clone(),
-            choice,
-            voting_power.amount.u128(),
+            &choices,
+            0,
+            math::add(voting_power, delegated_power.effective())?,
+            Overflow::Fail,
         )?;
-    } else {
+        store_vote(deps, voter.clone(), choices, voting_power)?;
+    }
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![log("voted", voter.to_string())],
+        data: Some(to_binary(&ResponseStatus::Success)?),
+    })
+}
+
+fn delegate_vote<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    delegate: Option<HumanAddr>,
+) -> StdResult<HandleResponse> {
+    enforce_poll_open(deps, &env)?;
+
+    let delegator = env.message.sender;
+    if load_vote(deps, &delegator).is_some() {
         return Err(StdError::generic_err(format!(
-            "choice {} does not exist in this poll",
-            choice
+            "{} has already voted in this poll",
+            delegator
         )));
     }
+    if delegate == Some(delegator.clone()) {
+        return Err(StdError::generic_err("cannot delegate to yourself"));
+    }
+
+    if let Some(previous) = load_delegation(&deps.storage, &delegator) {
+        update_delegated_power(deps, &previous.delegate, Overflow::Fail, |p| {
+            p.local = math::sub(p.local, previous.voting_power)?;
+            Ok(())
+        })?;
+        release_withheld_power(deps, &delegator, previous.voting_power, Overflow::Fail)?;
+        store_delegation(&mut deps.storage, &delegator, None)?;
+    }
+
+    if let Some(delegate) = delegate {
+        let voting_power = query_voting_power(deps, delegator.clone())?;
+        let (global_delegate, _) = query_delegation(deps, delegator.clone())?;
+
+        // A delegation for this poll overrides the global one
+        withhold_power(
+            deps,
+            &delegator,
+            global_delegate,
+            voting_power,
+            Overflow::Fail,
+        )?;
+        update_delegated_power(deps, &delegate, Overflow::Fail, |p| {
+            p.local = math::add(p.local, voting_power)?;
+            Ok(())
+        })?;
+        store_delegation(
+            &mut deps.storage,
+            &delegator,
+            Some(&Delegation {
+                delegate,
+                voting_power,
+            }),
+        )?;
+    }
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![log("delegated", delegator.to_string())],
+        data: Some(to_binary(&ResponseStatus::Success)?),
+    })
+}
+
+fn update_voting_power<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    voter: HumanAddr,
+    old_power: u128,
+    new_power: u128,
+    delegate: Option<HumanAddr>,
+) -> StdResult<HandleResponse> {
+    let staking_pool: SecretContract = TypedStore::attach(&deps.storage).load(STAKING_POOL_KEY)?;
+    if env.message.sender != staking_pool.address {
+        return Err(StdError::unauthorized());
+    }
+
+    // The staking pool calls this on every deposit and redeem, so we must not fail here once the
+    // poll has ended. A voting power update simply has no effect at that point, or at any point
+    // for polls that use a snapshot of the voting power. Before that, the update saturates rather
+    // than fail on numbers that don't add up, which would revert the staker's transaction
+    if enforce_poll_open(deps, &env).is_err() || is_snapshot(deps)? {
+        return Ok(HandleResponse {
+            messages: vec![],
+            log: vec![],
+            data: Some(to_binary(&ResponseStatus::Success)?),
+        });
+    }
+
+    if let Some(delegate) = &delegate {
+        if load_vote(deps, delegate).is_some() {
+            update_delegated_power(deps, delegate, Overflow::Saturate, |p| {
+                p.global = Overflow::Saturate.replace(p.global, old_power, new_power)?;
+                Ok(())
+            })?;
+        }
+    }
+
+    set_voting_power(deps, &voter, new_power, Overflow::Saturate)?;
 
     Ok(HandleResponse {
         messages: vec![],
-        log: vec![log("voted", env.message.sender.to_string())],
+        log: vec![log("voting_power_update", voter.to_string())],
         data: Some(to_binary(&ResponseStatus::Success)?),
     })
 }
 
+fn update_delegation<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    delegator: HumanAddr,
+    voting_power: u128,
+    old_delegate: Option<HumanAddr>,
+    new_delegate: Option<HumanAddr>,
+) -> StdResult<HandleResponse> {
+    let staking_pool: SecretContract = TypedStore::attach(&deps.storage).load(STAKING_POOL_KEY)?;
+    if env.message.sender != staking_pool.address {
+        return Err(StdError::unauthorized());
+    }
+
+    // Same as voting power updates, a closed or snapshot poll simply ignores this, and an open one
+    // saturates
+    if enforce_poll_open(deps, &env).is_err() || is_snapshot(deps)? {
+        return Ok(HandleResponse {
+            messages: vec![],
+            log: vec![],
+            data: Some(to_binary(&ResponseStatus::Success)?),
+        });
+    }
+
+    if let Some(old_delegate) = &old_delegate {
+        if load_vote(deps, old_delegate).is_some() {
+            update_delegated_power(deps, old_delegate, Overflow::Saturate, |p| {
+                p.global = Overflow::Saturate.sub(p.global, voting_power)?;
+                Ok(())
+            })?;
+        }
+    }
+    if let Some(new_delegate) = &new_delegate {
+        if load_vote(deps, new_delegate).is_some() {
+            update_delegated_power(deps, new_delegate, Overflow::Saturate, |p| {
+                p.global = Overflow::Saturate.add(p.global, voting_power)?;
+                Ok(())
+            })?;
+        }
+    }
+
+    // If the delegator voted or delegated in this poll, their power is now withheld from the new
+    // delegate instead
+    let own_power = load_vote(deps, &delegator)
+        .map(|v| v.voting_power)
+        .or_else(|| load_delegation(&deps.storage, &delegator).map(|d| d.voting_power));
+    if let Some(own_power) = own_power {
+        release_withheld_power(deps, &delegator, own_power, Overflow::Saturate)?;
+        withhold_power(
+            deps,
+            &delegator,
+            new_delegate,
+            own_power,
+            Overflow::Saturate,
+        )?;
+    }
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![log("delegation_update", delegator.to_string())],
+        data: Some(to_binary(&ResponseStatus::Success)?),
+    })
+}
+
+fn finalize<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    batch_size: Option<u32>,
+) -> StdResult<HandleResponse> {
+    let result: Option<PollResult> = TypedStore::attach(&deps.storage).load(RESULT_KEY).ok();
+    if result.is_some() {
+        return Err(StdError::generic_err(
+            "this poll has already been finalized",
+        ));
+    }
+
+    let end_height: Option<u64> = TypedStore::attach(&deps.storage).load(END_HEIGHT_KEY)?;
+    match end_height {
+        Some(end_height) if env.block.height < end_height => {
+            return Err(StdError::generic_err(format!(
+                "this poll is still open until block {}",
+                end_height
+            )));
+        }
+        // A poll without a duration can only be ended by its owner
+        None => {
+            let owner: HumanAddr = TypedStore::attach(&deps.storage).load(OWNER_KEY)?;
+            if env.message.sender != owner {
+                return Err(StdError::unauthorized());
+            }
+        }
+        _ => {}
+    }
+
+    // The tally follows balance changes through the staking pool's callbacks, which saturate
+    // rather than fail. So unless the poll counts a snapshot, every voter's balance is re-read
+    // before the result is counted, in a single batch if no batch size is given
+    let batch_size = match batch_size {
+        None if !is_snapshot(deps)? => Some(voters_count(&deps.storage)?),
+        batch_size => batch_size,
+    };
+    if let Some(batch_size) = batch_size {
+        let mut cursor_store = TypedStoreMut::<u32, S>::attach(&mut deps.storage);
+        let cursor = cursor_store.load(FINALIZE_CURSOR_KEY).unwrap_or(0);
+        // Storing the cursor right away also closes a poll without a duration for voting
+        cursor_store.store(FINALIZE_CURSOR_KEY, &cursor)?;
+
+        let voters = load_voters(&deps.storage, cursor, batch_size)?;
+        for voter in &voters {
+            refresh_voting_power(deps, voter)?;
+        }
+
+        let cursor = cursor + voters.len() as u32;
+        TypedStoreMut::attach(&mut deps.storage).store(FINALIZE_CURSOR_KEY, &cursor)?;
+
+        let total_voters = voters_count(&deps.storage)?;
+        if cursor < total_voters {
+            return Ok(HandleResponse {
+                messages: vec![],
+                log: vec![log("voters_left", (total_voters - cursor).to_string())],
+                data: Some(to_binary(&ResponseStatus::Success)?),
+            });
+        }
+    }
+
+    let config: PollConfig = TypedStore::attach(&deps.storage).load(CONFIG_KEY)?;
+    let tally: Tally = TypedStore::attach(&deps.storage).load(TALLY_KEY)?;
+    let total_voting_power: u128 =
+        TypedStore::attach(&deps.storage).load(TOTAL_VOTING_POWER_KEY)?;
+    let total_staked = query_total_staked(deps)?;
+
+    let quorum_reached = math::mul(total_voting_power, 100)?
+        >= math::mul(config.quorum.unwrap_or(0) as u128, total_staked)?
+        && total_voting_power > 0;
+
+    let mut winning_choice = None;
+    if quorum_reached {
+        let min_threshold = config.min_threshold.unwrap_or(0);
+        winning_choice = match config.voting_mode.unwrap_or(VotingMode::SingleChoice) {
+            VotingMode::RankedChoice => {
+                let rankings: Rankings = TypedStore::attach(&deps.storage).load(RANKINGS_KEY)?;
+                instant_runoff_winner(&rankings, min_threshold)?
+            }
+            // Every voter can approve several choices, so a choice's share is measured against
+            // the voting power that took part rather than the sum of the tally
+            VotingMode::Approval => plurality_winner(&tally, total_voting_power, min_threshold)?,
+            _ => plurality_winner(&tally, sum(tally.values())?, min_threshold)?,
+        };
+    }
+
+    let result = PollResult {
+        finalized_at: env.block.height,
+        total_voting_power,
+        total_staked,
+        quorum_reached,
+        winning_choice,
+    };
+    TypedStoreMut::attach(&mut deps.storage).store(RESULT_KEY, &result)?;
+
+    let mut messages = vec![];
+    let factory: Option<SecretContract> = TypedStore::attach(&deps.storage).load(FACTORY_KEY)?;
+    if let Some(factory) = factory {
+        messages.push(factory_msg(
+            factory,
+            PollFactoryHandleMsg::PollFinalized { quorum_reached },
+        )?);
+    }
+
+    Ok(HandleResponse {
+        messages,
+        log: vec![
+            log("quorum_reached", quorum_reached.to_string()),
+            log(
+                "winning_choice",
+                winning_choice.map_or("none".to_string(), |c| c.to_string()),
+            ),
+        ],
+        data: Some(to_binary(&ResponseStatus::Success)?),
+    })
+}
+
+fn execute<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+) -> StdResult<HandleResponse> {
+    let execution: Option<ProposalExecution> =
+        TypedStore::attach(&deps.storage).load(EXECUTION_KEY)?;
+    let execution =
+        execution.ok_or_else(|| StdError::generic_err("this poll has nothing to execute"))?;
+
+    let result: PollResult = TypedStore::attach(&deps.storage)
+        .load(RESULT_KEY)
+        .map_err(|_| StdError::generic_err("this poll has not been finalized yet"))?;
+    if result.winning_choice != Some(execution.winning_choice) {
+        return Err(StdError::generic_err("this proposal did not pass"));
+    }
+
+    let executable_at = math::to_u64(math::add(
+        result.finalized_at as u128,
+        execution.timelock as u128,
+    )?)?;
+    if env.block.height < executable_at {
+        return Err(StdError::generic_err(format!(
+            "this proposal is timelocked until block {}",
+            executable_at
+        )));
+    }
+
+    let mut executed_store = TypedStoreMut::<bool, S>::attach(&mut deps.storage);
+    if executed_store.load(EXECUTED_KEY)? {
+        return Err(StdError::generic_err(
+            "this proposal has already been executed",
+        ));
+    }
+    executed_store.store(EXECUTED_KEY, &true)?;
+
+    // Polls created by a factory execute through it, so that the factory can be the one admin of
+    // the target contract
+    let factory: Option<SecretContract> = TypedStore::attach(&deps.storage).load(FACTORY_KEY)?;
+    let message = if let Some(factory) = factory {
+        factory_msg(
+            factory,
+            PollFactoryHandleMsg::ExecuteProposal {
+                contract: execution.contract,
+                msg: execution.msg,
+            },
+        )?
+    } else {
+        WasmMsg::Execute {
+            contract_addr: execution.contract.address,
+            callback_code_hash: execution.contract.contract_hash,
+            msg: execution.msg,
+            send: vec![],
+        }
+        .into()
+    };
+
+    Ok(HandleResponse {
+        messages: vec![message],
+        log: vec![log("executed", env.contract.address.to_string())],
+        data: Some(to_binary(&ResponseStatus::Success)?),
+    })
+}
+
+fn revoke_permit<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    permit_name: String,
+) -> StdResult<HandleResponse> {
+    permit::revoke_permit(&mut deps.storage, &env.message.sender, &permit_name);
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![],
+        data: Some(to_binary(&ResponseStatus::Success)?),
+    })
+}
+
+// Query functions
+
+fn query_choices<S: Storage, A: Api, Q: Querier>(deps: &Extern<S, A, Q>) -> StdResult<Binary> {
+    let choices: ChoiceIdMap = TypedStore::attach(&deps.storage).load(CHOICE_ID_MAP_KEY)?;
+
+    to_binary(&QueryAnswer::Choices { choices })
+}
+
+fn query_has_voted<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    voter: HumanAddr,
+) -> StdResult<Binary> {
+    enforce_ballot_revealed(deps)?;
+
+    to_binary(&QueryAnswer::HasVoted {
+        has_voted: load_vote(deps, &voter).is_some(),
+    })
+}
+
+fn query_voters<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    page: u32,
+    page_size: u32,
+) -> StdResult<Binary> {
+    enforce_ballot_revealed(deps)?;
+
+    let voters = load_voters(&deps.storage, page * page_size, page_size)?;
+
+    to_binary(&QueryAnswer::Voters {
+        voters,
+        total: voters_count(&deps.storage)?,
+    })
+}
+
+fn query_tally<S: Storage, A: Api, Q: Querier>(deps: &Extern<S, A, Q>) -> StdResult<Binary> {
+    enforce_ballot_revealed(deps)?;
+
+    let choice_id_map: ChoiceIdMap = TypedStore::attach(&deps.storage).load(CHOICE_ID_MAP_KEY)?;
+    let tally: Tally = TypedStore::attach(&deps.storage).load(TALLY_KEY)?;
+
+    let mut choices = vec![];
+    let mut votes = vec![];
+    for (id, choice) in choice_id_map {
+        choices.push(choice);
+        votes.push(Uint128(*tally.get(&id).unwrap_or(&0)));
+    }
+
+    to_binary(&QueryAnswer::Tally {
+        choices,
+        tally: votes,
+    })
+}
+
+fn query_metadata<S: Storage, A: Api, Q: Querier>(deps: &Extern<S, A, Q>) -> StdResult<Binary> {
+    let metadata: PollMetadata = TypedStore::attach(&deps.storage).load(METADATA_KEY)?;
+    let config: PollConfig = TypedStore::attach(&deps.storage).load(CONFIG_KEY)?;
+    let end_height: Option<u64> = TypedStore::attach(&deps.storage).load(END_HEIGHT_KEY)?;
+
+    to_binary(&QueryAnswer::Metadata {
+        metadata,
+        config,
+        end_height,
+    })
+}
+
+fn query_result<S: Storage, A: Api, Q: Querier>(deps: &Extern<S, A, Q>) -> StdResult<Binary> {
+    let result: Option<PollResult> = TypedStore::attach(&deps.storage).load(RESULT_KEY).ok();
+
+    to_binary(&QueryAnswer::Result { result })
+}
+
+fn query_execution<S: Storage, A: Api, Q: Querier>(deps: &Extern<S, A, Q>) -> StdResult<Binary> {
+    let execution: Option<ProposalExecution> =
+        TypedStore::attach(&deps.storage).load(EXECUTION_KEY)?;
+    let executed: bool = TypedStore::attach(&deps.storage).load(EXECUTED_KEY)?;
+
+    to_binary(&QueryAnswer::Execution {
+        execution,
+        executed,
+    })
+}
+
+fn query_vote<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    voter: HumanAddr,
+) -> StdResult<Binary> {
+    if let Some(vote) = load_vote(deps, &voter) {
+        to_binary(&QueryAnswer::Vote {
+            choices: vote.choices,
+            voting_power: Uint128(vote.voting_power),
+            delegated_power: Uint128(load_delegated_power(&deps.storage, &voter).effective()),
+        })
+    } else {
+        Err(StdError::generic_err(format!(
+            "{} has not voted in this poll",
+            voter
+        )))
+    }
+}
+
+fn query_delegation_of<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    voter: HumanAddr,
+) -> StdResult<Binary> {
+    if let Some(delegation) = load_delegation(&deps.storage, &voter) {
+        to_binary(&QueryAnswer::Delegation {
+            delegate: delegation.delegate,
+            voting_power: Uint128(delegation.voting_power),
+        })
+    } else {
+        Err(StdError::generic_err(format!(
+            "{} has not delegated in this poll",
+            voter
+        )))
+    }
+}
+
+// Helper functions
+
+fn authenticate_voter<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    voter: &HumanAddr,
+    key: String,
+) -> StdResult<()> {
+    // We don't keep viewing keys of our own - the staking pool's viewing key is used to
+    // authenticate the voter. The balance query will fail if the key is wrong
+    authenticate_staker(deps, voter.clone(), key)
+}
+
 pub fn store_vote<S: Storage, A: Api, Q: Querier>(
     deps: &mut Extern<S, A, Q>,
     voter: HumanAddr,
-    choice: u8,
+    choices: Vec<u8>,
     voting_power: u128,
 ) -> StdResult<()> {
-    TypedStoreMut::attach(&mut deps.storage).store(
-        // TODO: We might want to iterate over every voter at some point (or e.g. return a list of voters).
-        // TODO: In that case we'd want to store it differently
+    if load_vote(deps, &voter).is_none() {
+        push_voter(&mut deps.storage, &voter)?;
+    }
+
+    let mut votes = PrefixedStorage::new(PREFIX_VOTES, &mut deps.storage);
+    TypedStoreMut::attach(&mut votes).store(
         voter.0.as_bytes(),
         &Vote {
-            choice,
+            choices,
             voting_power,
         },
+    )
+}
+
+fn load_vote<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    voter: &HumanAddr,
+) -> Option<Vote> {
+    let votes = ReadonlyPrefixedStorage::new(PREFIX_VOTES, &deps.storage);
+    TypedStore::attach(&votes).load(voter.0.as_bytes()).ok() // NotFound is the only possible error
+}
+
+/// Re-reads a voter's own and globally delegated power from the staking pool. Finalizing fails
+/// rather than counting a stale balance if the staking pool doesn't answer
+fn refresh_voting_power<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    voter: &HumanAddr,
+) -> StdResult<()> {
+    let voting_power = query_voting_power(deps, voter.clone())?;
+    set_voting_power(deps, voter, voting_power, Overflow::Fail)?;
+
+    let (_, global_power) = query_delegation(deps, voter.clone())?;
+    update_delegated_power(deps, voter, Overflow::Fail, |p| {
+        p.global = global_power;
+        Ok(())
+    })
+}
+
+/// Sets the own voting power of someone who voted or delegated in this poll
+fn set_voting_power<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    voter: &HumanAddr,
+    new_power: u128,
+    overflow: Overflow,
+) -> StdResult<()> {
+    if let Some(vote) = load_vote(deps, voter) {
+        let delegated_power = load_delegated_power(&deps.storage, voter).effective();
+        adjust_tally(
+            deps,
+            &vote.choices,
+            overflow.add(vote.voting_power, delegated_power)?,
+            overflow.add(new_power, delegated_power)?,
+            overflow,
+        )?;
+        update_withheld_power(deps, voter, vote.voting_power, new_power, overflow)?;
+
+        store_vote(deps, voter.clone(), vote.choices, new_power)?;
+    } else if let Some(delegation) = load_delegation(&deps.storage, voter) {
+        update_delegated_power(deps, &delegation.delegate, overflow, |p| {
+            p.local = overflow.replace(p.local, delegation.voting_power, new_power)?;
+            Ok(())
+        })?;
+        update_withheld_power(deps, voter, delegation.voting_power, new_power, overflow)?;
+
+        store_delegation(
+            &mut deps.storage,
+            voter,
+            Some(&Delegation {
+                delegate: delegation.delegate,
+                voting_power: new_power,
+            }),
+        )?;
+    }
+
+    Ok(())
+}
+
+fn load_delegation<S: ReadonlyStorage>(storage: &S, delegator: &HumanAddr) -> Option<Delegation> {
+    let delegations = ReadonlyPrefixedStorage::new(PREFIX_DELEGATIONS, storage);
+    TypedStore::attach(&delegations)
+        .load(delegator.0.as_bytes())
+        .ok() // NotFound is the only possible error
+}
+
+fn store_delegation<S: Storage>(
+    storage: &mut S,
+    delegator: &HumanAddr,
+    delegation: Option<&Delegation>,
+) -> StdResult<()> {
+    let mut delegations = PrefixedStorage::new(PREFIX_DELEGATIONS, storage);
+    if let Some(delegation) = delegation {
+        TypedStoreMut::attach(&mut delegations).store(delegator.0.as_bytes(), delegation)
+    } else {
+        delegations.remove(delegator.0.as_bytes());
+        Ok(())
+    }
+}
+
+fn load_delegated_power<S: ReadonlyStorage>(storage: &S, address: &HumanAddr) -> DelegatedPower {
+    let delegated_power = ReadonlyPrefixedStorage::new(PREFIX_DELEGATED_POWER, storage);
+    TypedStore::attach(&delegated_power)
+        .load(address.0.as_bytes())
+        .unwrap_or_default()
+}
+
+fn store_delegated_power<S: Storage>(
+    storage: &mut S,
+    address: &HumanAddr,
+    delegated_power: &DelegatedPower,
+) -> StdResult<()> {
+    let mut store = PrefixedStorage::new(PREFIX_DELEGATED_POWER, storage);
+    TypedStoreMut::attach(&mut store).store(address.0.as_bytes(), delegated_power)
+}
+
+/// Applies `update` to the power delegated to `address`, and moves the tally accordingly if
+/// `address` has voted
+fn update_delegated_power<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    address: &HumanAddr,
+    overflow: Overflow,
+    update: impl FnOnce(&mut DelegatedPower) -> StdResult<()>,
+) -> StdResult<()> {
+    let mut delegated_power = load_delegated_power(&deps.storage, address);
+    let old_power = delegated_power.effective();
+    update(&mut delegated_power)?;
+    store_delegated_power(&mut deps.storage, address, &delegated_power)?;
+
+    if let Some(vote) = load_vote(deps, address) {
+        adjust_tally(
+            deps,
+            &vote.choices,
+            overflow.add(vote.voting_power, old_power)?,
+            overflow.add(vote.voting_power, delegated_power.effective())?,
+            overflow,
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Stops counting `power` as part of what `address` delegated to `global_delegate`, because
+/// `address` voted or delegated in this poll
+fn withhold_power<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    address: &HumanAddr,
+    global_delegate: Option<HumanAddr>,
+    power: u128,
+    overflow: Overflow,
+) -> StdResult<()> {
+    if let Some(global_delegate) = global_delegate {
+        update_delegated_power(deps, &global_delegate, overflow, |p| {
+            p.withheld = overflow.add(p.withheld, power)?;
+            Ok(())
+        })?;
+
+        let mut withheld_from = PrefixedStorage::new(PREFIX_WITHHELD_FROM, &mut deps.storage);
+        TypedStoreMut::attach(&mut withheld_from).store(address.0.as_bytes(), &global_delegate)?;
+    }
+
+    Ok(())
+}
+
+fn release_withheld_power<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    address: &HumanAddr,
+    power: u128,
+    overflow: Overflow,
+) -> StdResult<()> {
+    if let Some(global_delegate) = load_withheld_from(&deps.storage, address) {
+        update_delegated_power(deps, &global_delegate, overflow, |p| {
+            p.withheld = overflow.sub(p.withheld, power)?;
+            Ok(())
+        })?;
+
+        let mut withheld_from = PrefixedStorage::new(PREFIX_WITHHELD_FROM, &mut deps.storage);
+        withheld_from.remove(address.0.as_bytes());
+    }
+
+    Ok(())
+}
+
+fn update_withheld_power<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    address: &HumanAddr,
+    old_power: u128,
+    new_power: u128,
+    overflow: Overflow,
+) -> StdResult<()> {
+    if let Some(global_delegate) = load_withheld_from(&deps.storage, address) {
+        update_delegated_power(deps, &global_delegate, overflow, |p| {
+            p.withheld = overflow.replace(p.withheld, old_power, new_power)?;
+            Ok(())
+        })?;
+    }
+
+    Ok(())
+}
+
+fn load_withheld_from<S: ReadonlyStorage>(storage: &S, address: &HumanAddr) -> Option<HumanAddr> {
+    let withheld_from = ReadonlyPrefixedStorage::new(PREFIX_WITHHELD_FROM, storage);
+    TypedStore::attach(&withheld_from)
+        .load(address.0.as_bytes())
+        .ok() // NotFound is the only possible error
+}
+
+/// Replaces a voter's `old_weight` with `new_weight` in the tally of every choice they voted for.
+/// The weights are always the voter's total voting power, including delegated power
+fn adjust_tally<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    choices: &[u8],
+    old_weight: u128,
+    new_weight: u128,
+    overflow: Overflow,
+) -> StdResult<()> {
+    let config: PollConfig = TypedStore::attach(&deps.storage).load(CONFIG_KEY)?;
+    let voting_mode = config.voting_mode.unwrap_or(VotingMode::SingleChoice);
+
+    let mut total_store = TypedStoreMut::<u128, S>::attach(&mut deps.storage);
+    let total_voting_power = total_store.load(TOTAL_VOTING_POWER_KEY)?;
+    total_store.store(
+        TOTAL_VOTING_POWER_KEY,
+        &overflow.replace(total_voting_power, old_weight, new_weight)?,
     )?;
 
+    let (old_votes, new_votes) = match voting_mode {
+        VotingMode::Quadratic => (integer_sqrt(old_weight), integer_sqrt(new_weight)),
+        _ => (old_weight, new_weight),
+    };
+
+    // The tally only shows the first preference of ranked ballots. The full rankings are kept
+    // aside for the instant-runoff at finalization
+    let counted_choices = match voting_mode {
+        VotingMode::RankedChoice => &choices[..1],
+        _ => choices,
+    };
+
+    let mut tally_store = TypedStoreMut::<Tally, S>::attach(&mut deps.storage);
+    let mut tally = tally_store.load(TALLY_KEY)?;
+    for choice in counted_choices {
+        if let Some(choice_tally) = tally.get_mut(choice) {
+            *choice_tally = overflow.replace(*choice_tally, old_votes, new_votes)?;
+        }
+    }
+    tally_store.store(TALLY_KEY, &tally)?;
+
+    if voting_mode == VotingMode::RankedChoice {
+        let mut rankings_store = TypedStoreMut::<Rankings, S>::attach(&mut deps.storage);
+        let mut rankings = rankings_store.load(RANKINGS_KEY)?;
+        if let Some(ranking) = rankings.iter_mut().find(|(r, _)| r.as_slice() == choices) {
+            ranking.1 = overflow.replace(ranking.1, old_weight, new_weight)?;
+        } else {
+            rankings.push((choices.to_vec(), new_weight));
+        }
+        rankings.retain(|(_, weight)| *weight > 0);
+        rankings_store.store(RANKINGS_KEY, &rankings)?;
+    }
+
+    Ok(())
+}
+
+/// What to do when a tally update doesn't add up. A voter's own transaction fails, but the staking
+/// pool's callbacks saturate, so that a poll can never revert a staker's deposit or redeem
+#[derive(Clone, Copy)]
+enum Overflow {
+    Fail,
+    Saturate,
+}
+
+impl Overflow {
+    fn add(self, a: u128, b: u128) -> StdResult<u128> {
+        match self {
+            Overflow::Fail => math::add(a, b),
+            Overflow::Saturate => Ok(a.saturating_add(b)),
+        }
+    }
+
+    fn sub(self, a: u128, b: u128) -> StdResult<u128> {
+        match self {
+            Overflow::Fail => math::sub(a, b),
+            Overflow::Saturate => Ok(a.saturating_sub(b)),
+        }
+    }
+
+    /// Replaces `old` with `new` in `total`
+    fn replace(self, total: u128, old: u128, new: u128) -> StdResult<u128> {
+        if new >= old {
+            self.add(total, new - old)
+        } else {
+            self.sub(total, old - new)
+        }
+    }
+}
+
+/// Returns the choice with the most votes, unless it is tied or below the minimum threshold
+fn plurality_winner(tally: &Tally, total_votes: u128, min_threshold: u8) -> StdResult<Option<u8>> {
+    let max_votes = tally.values().max().cloned().unwrap_or(0);
+    let leaders: Vec<u8> = tally
+        .iter()
+        .filter(|(_, votes)| **votes == max_votes)
+        .map(|(choice, _)| *choice)
+        .collect();
+
+    // A tie means there is no winner
+    if leaders.len() == 1 && reaches_threshold(max_votes, total_votes, min_threshold)? {
+        Ok(Some(leaders[0]))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Eliminates the choices with the fewest votes, moving their ballots to the next preference that
+/// is still in the race, until one choice holds a majority of the remaining ballots
+fn instant_runoff_winner(rankings: &Rankings, min_threshold: u8) -> StdResult<Option<u8>> {
+    let mut eliminated: Vec<u8> = vec![];
+
+    loop {
+        let mut counts: Tally = HashMap::new();
+        for (ranking, weight) in rankings {
+            let mut remaining = ranking.iter().filter(|c| !eliminated.contains(*c));
+            if let Some(preferred) = remaining.next() {
+                let count = counts.entry(*preferred).or_insert(0);
+                *count = math::add(*count, *weight)?;
+            }
+            for other in remaining {
+                counts.entry(*other).or_insert(0);
+            }
+        }
+
+        // Every remaining ballot is exhausted, or the last choices were tied
+        let total_votes = sum(counts.values())?;
+        if total_votes == 0 {
+            return Ok(None);
+        }
+
+        let max_votes = counts.values().max().cloned().unwrap_or(0);
+        if math::mul(max_votes, 2)? > total_votes {
+            if !reaches_threshold(max_votes, total_votes, min_threshold)? {
+                return Ok(None);
+            }
+
+            return Ok(counts
+                .iter()
+                .find(|(_, votes)| **votes == max_votes)
+                .map(|(choice, _)| *choice));
+        }
+
+        let min_votes = counts.values().min().cloned().unwrap_or(0);
+        eliminated.extend(
+            counts
+                .iter()
+                .filter(|(_, votes)| **votes == min_votes)
+                .map(|(choice, _)| *choice),
+        );
+    }
+}
+
+/// Whether `votes` are at least `min_threshold` percent of `total_votes`
+fn reaches_threshold(votes: u128, total_votes: u128, min_threshold: u8) -> StdResult<bool> {
+    Ok(math::mul(votes, 100)? >= math::mul(min_threshold as u128, total_votes)?)
+}
+
+fn sum<'a>(votes: impl Iterator<Item = &'a u128>) -> StdResult<u128> {
+    votes.fold(Ok(0), |total, v| math::add(total?, *v))
+}
+
+fn integer_sqrt(n: u128) -> u128 {
+    if n < 2 {
+        return n;
+    }
+
+    // Newton's method, starting from above the root
+    let mut x = n;
+    let mut y = n / 2 + n % 2;
+    while y < x {
+        x = y;
+        y = (x + n / x) / 2;
+    }
+
+    x
+}
+
+fn is_snapshot<S: Storage, A: Api, Q: Querier>(deps: &Extern<S, A, Q>) -> StdResult<bool> {
+    let snapshot_height: Option<u64> =
+        TypedStore::attach(&deps.storage).load(SNAPSHOT_HEIGHT_KEY)?;
+
+    Ok(snapshot_height.is_some())
+}
+
+fn enforce_valid_choices<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    choices: &[u8],
+) -> StdResult<()> {
+    let config: PollConfig = TypedStore::attach(&deps.storage).load(CONFIG_KEY)?;
+    match config.voting_mode.unwrap_or(VotingMode::SingleChoice) {
+        VotingMode::SingleChoice | VotingMode::Quadratic if choices.len() != 1 => {
+            return Err(StdError::generic_err(
+                "this poll accepts exactly one choice",
+            ));
+        }
+        _ if choices.is_empty() => {
+            return Err(StdError::generic_err("no choices were given"));
+        }
+        _ => {}
+    }
+
+    let tally: Tally = TypedStore::attach(&deps.storage).load(TALLY_KEY)?;
+    for (i, choice) in choices.iter().enumerate() {
+        if !tally.contains_key(choice) {
+            return Err(StdError::generic_err(format!(
+                "choice {} does not exist in this poll",
+                choice
+            )));
+        }
+        if choices[..i].contains(choice) {
+            return Err(StdError::generic_err(format!(
+                "choice {} was given more than once",
+                choice
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+/// Private ballots keep the tally and the list of voters hidden until the poll is finalized,
+/// to avoid bandwagon effects. Voters can still see their own vote with a viewing key
+fn enforce_ballot_revealed<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+) -> StdResult<()> {
+    let config: PollConfig = TypedStore::attach(&deps.storage).load(CONFIG_KEY)?;
+    let result: Option<PollResult> = TypedStore::attach(&deps.storage).load(RESULT_KEY).ok();
+
+    if config.private_ballot.unwrap_or(false) && result.is_none() {
+        return Err(StdError::generic_err(
+            "this is a private ballot, votes are hidden until the poll is finalized",
+        ));
+    }
+
+    Ok(())
+}
+
+fn factory_msg(factory: SecretContract, msg: PollFactoryHandleMsg) -> StdResult<CosmosMsg> {
+    Ok(WasmMsg::Execute {
+        contract_addr: factory.address,
+        callback_code_hash: factory.contract_hash,
+        msg: to_binary(&msg)?,
+        send: vec![],
+    }
+    .into())
+}
+
+fn enforce_poll_open<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    env: &Env,
+) -> StdResult<()> {
+    let result: Option<PollResult> = TypedStore::attach(&deps.storage).load(RESULT_KEY).ok();
+    if result.is_some() {
+        return Err(StdError::generic_err("this poll has been finalized"));
+    }
+
+    let finalize_cursor: Option<u32> = TypedStore::attach(&deps.storage)
+        .load(FINALIZE_CURSOR_KEY)
+        .ok();
+    if finalize_cursor.is_some() {
+        return Err(StdError::generic_err("this poll is being finalized"));
+    }
+
+    let end_height: Option<u64> = TypedStore::attach(&deps.storage).load(END_HEIGHT_KEY)?;
+    if let Some(end_height) = end_height {
+        if env.block.height >= end_height {
+            return Err(StdError::generic_err(format!(
+                "this poll has ended at block {}",
+                end_height
+            )));
+        }
+    }
+
     Ok(())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use cosmwasm_std::testing::{mock_dependencies, mock_env};
-    use cosmwasm_std::{coins, from_binary, StdError};
+    use cosmwasm_std::testing::{mock_env, MockApi, MockStorage, MOCK_CONTRACT_ADDR};
+    use cosmwasm_std::{from_binary, from_slice, Empty, QuerierResult, QueryRequest, WasmQuery};
+    use scrt_finance::lp_staking_msg::{LPStakingQueryAnswer, LPStakingQueryMsg};
+
+    const POLL_KEY: &str = "poll_key";
+    const START: u64 = 12_345; // The height of `mock_env`
+    const END: u64 = START + 100;
+
+    type TestDeps = Extern<MockStorage, MockApi, StakingPoolQuerier>;
+
+    /// Stands in for the staking pool. The poll's queries are answered only while the pool trusts
+    /// it, and a staker's own balance query only with the key "<staker>_key"
+    struct StakingPoolQuerier {
+        trusted: bool,
+        balances: HashMap<HumanAddr, u128>,
+        delegations: HashMap<HumanAddr, (Option<HumanAddr>, u128)>,
+        total_staked: u128,
+    }
+
+    impl Querier for StakingPoolQuerier {
+        fn raw_query(&self, bin_request: &[u8]) -> QuerierResult {
+            let request: QueryRequest<Empty> = from_slice(bin_request).unwrap();
+            let msg = match request {
+                QueryRequest::Wasm(WasmQuery::Smart {
+                    contract_addr, msg, ..
+                }) if contract_addr.0 == "staking_pool" => msg,
+                other => panic!("Unexpected query: {:?}", other),
+            };
+
+            Ok(self
+                .answer(from_binary(&msg).unwrap())
+                .and_then(|answer| to_binary(&answer)))
+        }
+    }
+
+    impl StakingPoolQuerier {
+        fn answer(&self, msg: LPStakingQueryMsg) -> StdResult<LPStakingQueryAnswer> {
+            match msg {
+                LPStakingQueryMsg::StakerBalance {
+                    staker,
+                    contract,
+                    key,
+                    ..
+                } => {
+                    self.enforce_trusted(&contract, &key)?;
+                    Ok(LPStakingQueryAnswer::Balance {
+                        amount: Uint128(self.balance(&staker)),
+                    })
+                }
+                LPStakingQueryMsg::StakerDelegation {
+                    staker,
+                    contract,
+                    key,
+                    ..
+                } => {
+                    self.enforce_trusted(&contract, &key)?;
+                    let (delegate, delegated_power) =
+                        self.delegations.get(&staker).cloned().unwrap_or((None, 0));
+                    Ok(LPStakingQueryAnswer::Delegation {
+                        delegate,
+                        delegated_power: Uint128(delegated_power),
+                    })
+                }
+                LPStakingQueryMsg::Balance { address, key } => {
+                    if key != format!("{}_key", address) {
+                        return Ok(LPStakingQueryAnswer::QueryError {
+                            msg: "Wrong viewing key for this address or viewing key not set"
+                                .to_string(),
+                        });
+                    }
+                    Ok(LPStakingQueryAnswer::Balance {
+                        amount: Uint128(self.balance(&address)),
+                    })
+                }
+                LPStakingQueryMsg::TotalLocked {} | LPStakingQueryMsg::TotalLockedAt { .. } => {
+                    Ok(LPStakingQueryAnswer::TotalLocked {
+                        amount: Uint128(self.total_staked),
+                    })
+                }
+                other => panic!("Unexpected staking pool query: {:?}", other),
+            }
+        }
+
+        fn enforce_trusted(&self, contract: &HumanAddr, key: &str) -> StdResult<()> {
+            if !self.trusted || contract.0 != MOCK_CONTRACT_ADDR || key != POLL_KEY {
+                return Err(StdError::unauthorized());
+            }
+
+            Ok(())
+        }
+
+        fn balance(&self, staker: &HumanAddr) -> u128 {
+            *self.balances.get(staker).unwrap_or(&0)
+        }
+    }
+
+    // Helper functions
+
+    fn init_helper(voting_mode: VotingMode, choices: usize) -> (StdResult<InitResponse>, TestDeps) {
+        let mut deps = Extern {
+            storage: MockStorage::default(),
+            api: MockApi::new(20),
+            querier: StakingPoolQuerier {
+                trusted: true,
+                balances: HashMap::new(),
+                delegations: HashMap::new(),
+                total_staked: 1_000,
+            },
+        };
+
+        let init_msg = PollInitMsg {
+            metadata: PollMetadata {
+                title: "Weights".to_string(),
+                description: "Change the pool weights".to_string(),
+                author: HumanAddr("author".to_string()),
+            },
+            config: PollConfig {
+                duration: Some(END - START),
+                quorum: Some(10),
+                min_threshold: Some(50),
+                private_ballot: None,
+                voting_mode: Some(voting_mode),
+                snapshot: None,
+            },
+            choices: (1..=choices).map(|c| format!("choice {}", c)).collect(),
+            staking_pool: SecretContract {
+                address: HumanAddr("staking_pool".to_string()),
+                contract_hash: "staking_pool_hash".to_string(),
+            },
+            factory: None,
+            execution: None,
+            viewing_key: POLL_KEY.to_string(),
+        };
+
+        (init(&mut deps, mock_env("admin", &[]), init_msg), deps)
+    }
+
+    fn env_at(sender: &str, height: u64) -> Env {
+        let mut env = mock_env(sender, &[]);
+        env.block.height = height;
+        env
+    }
+
+    /// Votes with `voting_power`, which is what the staking pool reports for `voter` from now on
+    fn vote_helper(
+        deps: &mut TestDeps,
+        voter: &str,
+        choices: Vec<u8>,
+        voting_power: u128,
+    ) -> StdResult<HandleResponse> {
+        let voter = HumanAddr(voter.to_string());
+        deps.querier.balances.insert(voter.clone(), voting_power);
+
+        handle(deps, mock_env(voter, &[]), HandleMsg::Vote { choices })
+    }
+
+    fn finalize_helper(deps: &mut TestDeps, batch_size: Option<u32>) -> StdResult<HandleResponse> {
+        handle(
+            deps,
+            env_at("anyone", END),
+            HandleMsg::Finalize { batch_size },
+        )
+    }
+
+    fn callback_helper(
+        deps: &mut TestDeps,
+        height: u64,
+        msg: HandleMsg,
+    ) -> StdResult<HandleResponse> {
+        handle(deps, env_at("staking_pool", height), msg)
+    }
+
+    fn power_update(
+        voter: &str,
+        old_power: u128,
+        new_power: u128,
+        delegate: Option<&str>,
+    ) -> HandleMsg {
+        HandleMsg::UpdateVotingPower {
+            voter: HumanAddr(voter.to_string()),
+            old_power: Uint128(old_power),
+            new_power: Uint128(new_power),
+            delegate: delegate.map(|d| HumanAddr(d.to_string())),
+        }
+    }
+
+    fn load_tally(deps: &TestDeps) -> Vec<u128> {
+        match from_binary(&query(deps, QueryMsg::Tally {}).unwrap()).unwrap() {
+            QueryAnswer::Tally { tally, .. } => tally.iter().map(|t| t.u128()).collect(),
+            other => panic!("Unexpected query answer: {:?}", other),
+        }
+    }
+
+    fn load_result(deps: &TestDeps) -> Option<PollResult> {
+        match from_binary(&query(deps, QueryMsg::Result {}).unwrap()).unwrap() {
+            QueryAnswer::Result { result } => result,
+            other => panic!("Unexpected query answer: {:?}", other),
+        }
+    }
+
+    // Tests
+
+    #[test]
+    fn test_init_sets_viewing_key() {
+        let (init_result, _) = init_helper(VotingMode::SingleChoice, 2);
+
+        let messages = init_result.unwrap().messages;
+        assert_eq!(
+            messages,
+            vec![CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: HumanAddr("staking_pool".to_string()),
+                callback_code_hash: "staking_pool_hash".to_string(),
+                msg: to_binary(&LPStakingHandleMsg::SetViewingKey {
+                    key: POLL_KEY.to_string(),
+                    padding: None,
+                })
+                .unwrap(),
+                send: vec![],
+            })]
+        );
+    }
+
+    #[test]
+    fn test_vote_reads_trusted_balance() {
+        let (init_result, mut deps) = init_helper(VotingMode::SingleChoice, 2);
+        assert!(init_result.is_ok());
+
+        vote_helper(&mut deps, "alice", vec![1], 100).unwrap();
+        assert_eq!(load_tally(&deps), vec![100, 0]);
+
+        // The voter's own key is only checked for their queries, and never kept
+        let vote_query = |key: &str| QueryMsg::Vote {
+            voter: HumanAddr("alice".to_string()),
+            key: key.to_string(),
+        };
+        assert!(query(&deps, vote_query("wrong_key")).is_err());
+        match from_binary(&query(&deps, vote_query("alice_key")).unwrap()).unwrap() {
+            QueryAnswer::Vote { voting_power, .. } => assert_eq!(voting_power, Uint128(100)),
+            other => panic!("Unexpected query answer: {:?}", other),
+        }
+
+        // A poll that the staking pool doesn't trust can't count anyone's vote
+        deps.querier.trusted = false;
+        assert!(vote_helper(&mut deps, "bob", vec![2], 50).is_err());
+        assert_eq!(load_tally(&deps), vec![100, 0]);
+    }
+
+    #[test]
+    fn test_callbacks_never_fail() {
+        let (init_result, mut deps) = init_helper(VotingMode::SingleChoice, 2);
+        assert!(init_result.is_ok());
+
+        deps.querier
+            .delegations
+            .insert(HumanAddr("alice".to_string()), (None, 50));
+        vote_helper(&mut deps, "alice", vec![1], 100).unwrap();
+        assert_eq!(load_tally(&deps), vec![150, 0]);
+
+        let result = handle(
+            &mut deps,
+            env_at("alice", START),
+            power_update("alice", 100, 1_000, None),
+        );
+        assert_eq!(result.unwrap_err(), StdError::unauthorized());
+
+        callback_helper(&mut deps, START + 1, power_update("alice", 100, 40, None)).unwrap();
+        assert_eq!(load_tally(&deps), vec![90, 0]);
+
+        // More than was ever delegated to alice leaves, which saturates instead of reverting the
+        // staker's redeem in the staking pool
+        callback_helper(
+            &mut deps,
+            START + 2,
+            power_update("bob", 80, 0, Some("alice")),
+        )
+        .unwrap();
+        assert_eq!(load_tally(&deps), vec![40, 0]);
+        let undelegate_msg = HandleMsg::UpdateDelegation {
+            delegator: HumanAddr("carol".to_string()),
+            voting_power: Uint128(500),
+            old_delegate: Some(HumanAddr("alice".to_string())),
+            new_delegate: None,
+        };
+        callback_helper(&mut deps, START + 3, undelegate_msg).unwrap();
+        assert_eq!(load_tally(&deps), vec![40, 0]);
+
+        // Nothing changes once the poll has ended
+        callback_helper(&mut deps, END, power_update("alice", 40, 0, None)).unwrap();
+        assert_eq!(load_tally(&deps), vec![40, 0]);
+    }
+
+    #[test]
+    fn test_finalize_fails_without_balances() {
+        let (init_result, mut deps) = init_helper(VotingMode::SingleChoice, 2);
+        assert!(init_result.is_ok());
+        deps.querier.total_staked = 100;
+
+        vote_helper(&mut deps, "alice", vec![1], 100).unwrap();
+        deps.querier
+            .balances
+            .insert(HumanAddr("alice".to_string()), 40);
+
+        // Counting the stale balance instead would let a voter vote and then redeem
+        deps.querier.trusted = false;
+        assert!(finalize_helper(&mut deps, Some(10)).is_err());
+        assert!(load_result(&deps).is_none());
+
+        deps.querier.trusted = true;
+        finalize_helper(&mut deps, Some(10)).unwrap();
+        assert_eq!(load_tally(&deps), vec![40, 0]);
+
+        let result = load_result(&deps).unwrap();
+        assert_eq!(result.total_voting_power, 40);
+        assert!(result.quorum_reached);
+        assert_eq!(result.winning_choice, Some(1));
+
+        // Without a batch size, every balance is re-read at once
+        let (_, mut deps) = init_helper(VotingMode::SingleChoice, 2);
+        deps.querier.total_staked = 100;
+        vote_helper(&mut deps, "alice", vec![1], 100).unwrap();
+        vote_helper(&mut deps, "bob", vec![2], 50).unwrap();
+        deps.querier
+            .balances
+            .insert(HumanAddr("alice".to_string()), 40);
+
+        deps.querier.trusted = false;
+        assert!(finalize_helper(&mut deps, None).is_err());
+
+        deps.querier.trusted = true;
+        finalize_helper(&mut deps, None).unwrap();
+        assert_eq!(load_tally(&deps), vec![40, 50]);
+        assert_eq!(load_result(&deps).unwrap().winning_choice, Some(2));
+    }
+
+    #[test]
+    fn test_finalize_quorum() {
+        let (init_result, mut deps) = init_helper(VotingMode::SingleChoice, 2);
+        assert!(init_result.is_ok());
+
+        vote_helper(&mut deps, "alice", vec![1], 99).unwrap();
+        let result = handle(
+            &mut deps,
+            env_at("anyone", END - 1),
+            HandleMsg::Finalize { batch_size: None },
+        );
+        assert_eq!(
+            result.unwrap_err(),
+            StdError::generic_err(format!("this poll is still open until block {}", END))
+        );
+
+        // 10% of the 1,000 staked is needed
+        finalize_helper(&mut deps, None).unwrap();
+        let result = load_result(&deps).unwrap();
+        assert_eq!(result.total_voting_power, 99);
+        assert_eq!(result.total_staked, 1_000);
+        assert!(!result.quorum_reached);
+        assert_eq!(result.winning_choice, None);
+        assert_eq!(
+            finalize_helper(&mut deps, None).unwrap_err(),
+            StdError::generic_err("this poll has already been finalized")
+        );
+
+        let (_, mut deps) = init_helper(VotingMode::SingleChoice, 2);
+        vote_helper(&mut deps, "alice", vec![1], 100).unwrap();
+        finalize_helper(&mut deps, None).unwrap();
+        let result = load_result(&deps).unwrap();
+        assert!(result.quorum_reached);
+        assert_eq!(result.winning_choice, Some(1));
+
+        // Without any votes there is no quorum, whatever the poll asks for
+        let (_, mut deps) = init_helper(VotingMode::SingleChoice, 2);
+        deps.querier.total_staked = 0;
+        finalize_helper(&mut deps, None).unwrap();
+        assert!(!load_result(&deps).unwrap().quorum_reached);
+    }
+
+    #[test]
+    fn test_plurality_ties() {
+        let (_, mut deps) = init_helper(VotingMode::SingleChoice, 2);
+        vote_helper(&mut deps, "alice", vec![1], 50).unwrap();
+        vote_helper(&mut deps, "bob", vec![2], 50).unwrap();
+
+        finalize_helper(&mut deps, None).unwrap();
+        let result = load_result(&deps).unwrap();
+        assert!(result.quorum_reached);
+        assert_eq!(result.winning_choice, None);
+    }
+
+    #[test]
+    fn test_approval() {
+        let (_, mut deps) = init_helper(VotingMode::Approval, 3);
+        vote_helper(&mut deps, "alice", vec![1, 2], 60).unwrap();
+        vote_helper(&mut deps, "bob", vec![2], 40).unwrap();
+        assert_eq!(load_tally(&deps), vec![60, 100, 0]);
+
+        // Choice 2 was approved by all of the voting power, even though the tally adds up to more
+        finalize_helper(&mut deps, None).unwrap();
+        let result = load_result(&deps).unwrap();
+        assert_eq!(result.total_voting_power, 100);
+        assert_eq!(result.winning_choice, Some(2));
+    }
+
+    #[test]
+    fn test_ranked_choice() {
+        let (init_result, _) = init_helper(VotingMode::RankedChoice, MAX_RANKED_CHOICES + 1);
+        assert_eq!(
+            init_result.unwrap_err(),
+            StdError::generic_err(format!(
+                "the number of choices for a ranked-choice poll cannot exceed {}",
+                MAX_RANKED_CHOICES
+            ))
+        );
+
+        let (_, mut deps) = init_helper(VotingMode::RankedChoice, 3);
+        assert_eq!(
+            vote_helper(&mut deps, "alice", vec![1, 1], 40).unwrap_err(),
+            StdError::generic_err("choice 1 was given more than once")
+        );
+        vote_helper(&mut deps, "alice", vec![1, 2, 3], 40).unwrap();
+        vote_helper(&mut deps, "bob", vec![2, 3], 35).unwrap();
+        vote_helper(&mut deps, "carol", vec![3, 2], 25).unwrap();
+        assert_eq!(load_tally(&deps), vec![40, 35, 25]);
+
+        // Choice 3 is eliminated first, and its ballot moves on to choice 2
+        finalize_helper(&mut deps, None).unwrap();
+        assert_eq!(load_result(&deps).unwrap().winning_choice, Some(2));
+
+        // A changed ballot replaces the old ranking
+        let (_, mut deps) = init_helper(VotingMode::RankedChoice, 3);
+        vote_helper(&mut deps, "alice", vec![1, 2, 3], 40).unwrap();
+        vote_helper(&mut deps, "bob", vec![2, 3], 35).unwrap();
+        vote_helper(&mut deps, "carol", vec![3, 2], 25).unwrap();
+        vote_helper(&mut deps, "carol", vec![3, 1], 25).unwrap();
+        finalize_helper(&mut deps, None).unwrap();
+        assert_eq!(load_result(&deps).unwrap().winning_choice, Some(1));
+    }
+
+    #[test]
+    fn test_quadratic() {
+        let (_, mut deps) = init_helper(VotingMode::Quadratic, 2);
+        assert_eq!(
+            vote_helper(&mut deps, "alice", vec![1, 2], 100).unwrap_err(),
+            StdError::generic_err("this poll accepts exactly one choice")
+        );
+        vote_helper(&mut deps, "alice", vec![1], 100).unwrap();
+        vote_helper(&mut deps, "bob", vec![2], 49).unwrap();
+        vote_helper(&mut deps, "carol", vec![2], 36).unwrap();
+
+        // Two smaller voters outweigh a bigger one
+        assert_eq!(load_tally(&deps), vec![10, 13]);
+        finalize_helper(&mut deps, None).unwrap();
+        let result = load_result(&deps).unwrap();
+        assert_eq!(result.total_voting_power, 185);
+        assert_eq!(result.winning_choice, Some(2));
+    }
+
+    #[test]
+    fn test_integer_sqrt() {
+        for n in 0..1_000u128 {
+            let root = integer_sqrt(n);
+            assert!(root * root <= n && (root + 1) * (root + 1) > n);
+        }
+        assert_eq!(integer_sqrt(u128::MAX), u64::MAX as u128);
+    }
 }
@@ -1,5 +1,6 @@
 pub mod contract;
 pub mod msg;
+pub mod querier;
 pub mod state;
 
 #[cfg(target_arch = "wasm32")]
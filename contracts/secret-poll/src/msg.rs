@@ -1,29 +1,46 @@
-use crate::state::{PollConfig, PollMetadata};
+use crate::state::{ChoiceIdMap, PollResult};
 use cosmwasm_std::{HumanAddr, Uint128};
 use schemars::JsonSchema;
-use scrt_finance::types::SecretContract;
+use scrt_finance::permit::Permit;
+use scrt_finance::secret_poll_msg::{PollConfig, PollMetadata, ProposalExecution};
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize, JsonSchema)]
-pub struct InitMsg {
-    pub metadata: PollMetadata,
-    pub config: PollConfig,
-    pub choices: Vec<String>,
-    pub staking_pool: SecretContract,
-}
-
 #[derive(Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum HandleMsg {
     Vote {
-        choice: u8, // Arbitrary id that is given by the contract
-        staking_pool_viewing_key: String,
+        // Arbitrary ids that are given by the contract. Ranked-choice polls expect them in order
+        // of preference
+        choices: Vec<u8>,
     },
+    Delegate {
+        delegate: Option<HumanAddr>, // None cancels an existing delegation
+    },
+
+    // Staking pool callbacks
     UpdateVotingPower {
         voter: HumanAddr,
+        old_power: Uint128,
         new_power: Uint128,
+        delegate: Option<HumanAddr>,
+    },
+    UpdateDelegation {
+        delegator: HumanAddr,
+        voting_power: Uint128,
+        old_delegate: Option<HumanAddr>,
+        new_delegate: Option<HumanAddr>,
+    },
+
+    Finalize {
+        // Big polls can re-read their voters' balances in batches of this size, across several
+        // transactions. The poll is finalized with the last batch. Without a batch size, a poll
+        // re-reads every balance at once, unless it counts a snapshot
+        batch_size: Option<u32>,
+    },
+    Execute {},
+    RevokePermit {
+        permit_name: String,
     },
-    Finalize {},
 }
 
 #[derive(Serialize, Deserialize, JsonSchema)]
@@ -31,12 +48,79 @@ pub enum HandleMsg {
 pub enum QueryMsg {
     // Public
     Choices {},
-    HasVoted { voter: HumanAddr },
-    Voters {},
-    Tally {}, // TODO: Only when poll is finished?
+    HasVoted {
+        voter: HumanAddr,
+    },
+    Voters {
+        page: Option<u32>,
+        page_size: u32,
+    },
+    Tally {},
+    Metadata {},
+    Result {},
+    Execution {},
 
     // Authenticated
-    Vote { voter: HumanAddr, key: String },
+    Vote {
+        voter: HumanAddr,
+        key: String,
+    },
+    Delegation {
+        voter: HumanAddr,
+        key: String,
+    },
+    WithPermit {
+        permit: Permit,
+        query: QueryWithPermit,
+    },
+}
+
+// The authenticated queries, on behalf of whoever signed the permit
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum QueryWithPermit {
+    Vote {},
+    Delegation {},
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum QueryAnswer {
+    Choices {
+        choices: ChoiceIdMap,
+    },
+    HasVoted {
+        has_voted: bool,
+    },
+    Voters {
+        voters: Vec<HumanAddr>,
+        total: u32,
+    },
+    Tally {
+        choices: Vec<String>,
+        tally: Vec<Uint128>,
+    },
+    Metadata {
+        metadata: PollMetadata,
+        config: PollConfig,
+        end_height: Option<u64>,
+    },
+    Result {
+        result: Option<PollResult>,
+    },
+    Execution {
+        execution: Option<ProposalExecution>,
+        executed: bool,
+    },
+    Vote {
+        choices: Vec<u8>,
+        voting_power: Uint128,
+        delegated_power: Uint128,
+    },
+    Delegation {
+        delegate: HumanAddr,
+        voting_power: Uint128,
+    },
 }
 
 #[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
@@ -0,0 +1,134 @@
+use crate::state::{CONTRACT_ADDRESS_KEY, SNAPSHOT_HEIGHT_KEY, STAKING_POOL_KEY, VIEWING_KEY_KEY};
+use cosmwasm_std::{
+    to_binary, Api, Extern, HumanAddr, Querier, QueryRequest, StdError, StdResult, Storage,
+    WasmQuery,
+};
+use scrt_finance::lp_staking_msg::{LPStakingQueryAnswer, LPStakingQueryMsg};
+use scrt_finance::types::SecretContract;
+use secret_toolkit::snip20;
+use secret_toolkit::storage::TypedStore;
+
+/// Reads a voter's balance, which the staking pool lets the poll do with the poll's own viewing key
+pub fn query_voting_power<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    voter: HumanAddr,
+) -> StdResult<u128> {
+    let staking_pool: SecretContract = TypedStore::attach(&deps.storage).load(STAKING_POOL_KEY)?;
+    let snapshot_height: Option<u64> =
+        TypedStore::attach(&deps.storage).load(SNAPSHOT_HEIGHT_KEY)?;
+    let (contract, key) = load_credentials(deps)?;
+
+    let response = query_staking_pool(
+        deps,
+        staking_pool,
+        LPStakingQueryMsg::StakerBalance {
+            staker: voter,
+            height: snapshot_height,
+            contract,
+            key,
+        },
+    )?;
+
+    match response {
+        LPStakingQueryAnswer::Balance { amount } => Ok(amount.u128()),
+        LPStakingQueryAnswer::QueryError { msg } => Err(StdError::generic_err(msg)),
+        _ => Err(StdError::generic_err(
+            "something is wrong with the staking pool contract..",
+        )),
+    }
+}
+
+/// Returns the voter's global delegate, and the total power that was globally delegated to them
+pub fn query_delegation<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    voter: HumanAddr,
+) -> StdResult<(Option<HumanAddr>, u128)> {
+    let staking_pool: SecretContract = TypedStore::attach(&deps.storage).load(STAKING_POOL_KEY)?;
+    let snapshot_height: Option<u64> =
+        TypedStore::attach(&deps.storage).load(SNAPSHOT_HEIGHT_KEY)?;
+    let (contract, key) = load_credentials(deps)?;
+
+    let response = query_staking_pool(
+        deps,
+        staking_pool,
+        LPStakingQueryMsg::StakerDelegation {
+            staker: voter,
+            height: snapshot_height,
+            contract,
+            key,
+        },
+    )?;
+
+    match response {
+        LPStakingQueryAnswer::Delegation {
+            delegate,
+            delegated_power,
+        } => Ok((delegate, delegated_power.u128())),
+        LPStakingQueryAnswer::QueryError { msg } => Err(StdError::generic_err(msg)),
+        _ => Err(StdError::generic_err(
+            "something is wrong with the staking pool contract..",
+        )),
+    }
+}
+
+/// Fails unless `key` is the voter's viewing key with the staking pool
+pub fn authenticate_staker<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    voter: HumanAddr,
+    key: String,
+) -> StdResult<()> {
+    let staking_pool: SecretContract = TypedStore::attach(&deps.storage).load(STAKING_POOL_KEY)?;
+
+    snip20::balance_query(
+        &deps.querier,
+        voter,
+        key,
+        256,
+        staking_pool.contract_hash,
+        staking_pool.address,
+    )?;
+
+    Ok(())
+}
+
+pub fn query_total_staked<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+) -> StdResult<u128> {
+    let staking_pool: SecretContract = TypedStore::attach(&deps.storage).load(STAKING_POOL_KEY)?;
+    let snapshot_height: Option<u64> =
+        TypedStore::attach(&deps.storage).load(SNAPSHOT_HEIGHT_KEY)?;
+
+    let msg = if let Some(height) = snapshot_height {
+        LPStakingQueryMsg::TotalLockedAt { height }
+    } else {
+        LPStakingQueryMsg::TotalLocked {}
+    };
+
+    match query_staking_pool(deps, staking_pool, msg)? {
+        LPStakingQueryAnswer::TotalLocked { amount } => Ok(amount.u128()),
+        _ => Err(StdError::generic_err(
+            "something is wrong with the staking pool contract..",
+        )),
+    }
+}
+
+fn load_credentials<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+) -> StdResult<(HumanAddr, String)> {
+    let contract: HumanAddr = TypedStore::attach(&deps.storage).load(CONTRACT_ADDRESS_KEY)?;
+    let key: String = TypedStore::attach(&deps.storage).load(VIEWING_KEY_KEY)?;
+
+    Ok((contract, key))
+}
+
+fn query_staking_pool<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    staking_pool: SecretContract,
+    msg: LPStakingQueryMsg,
+) -> StdResult<LPStakingQueryAnswer> {
+    deps.querier.query(&QueryRequest::Wasm(WasmQuery::Smart {
+        callback_code_hash: staking_pool.contract_hash,
+        contract_addr: staking_pool.address,
+        msg: to_binary(&msg)?,
+    }))
+}
@@ -1,35 +1,110 @@
-use cosmwasm_std::HumanAddr;
 use std::collections::HashMap;
 
+use cosmwasm_std::{HumanAddr, ReadonlyStorage, StdResult, Storage};
+use cosmwasm_storage::{PrefixedStorage, ReadonlyPrefixedStorage};
 use schemars::JsonSchema;
+use secret_toolkit::storage::{AppendStore, AppendStoreMut};
 use serde::{Deserialize, Serialize};
 
 pub const OWNER_KEY: &[u8] = b"owner";
+pub const CONTRACT_ADDRESS_KEY: &[u8] = b"contractaddress";
 pub const CHOICE_ID_MAP_KEY: &[u8] = b"choiceidmap";
 pub const TALLY_KEY: &[u8] = b"tally";
 pub const METADATA_KEY: &[u8] = b"metadata";
 pub const CONFIG_KEY: &[u8] = b"config";
 pub const STAKING_POOL_KEY: &[u8] = b"stakingpool";
+pub const END_HEIGHT_KEY: &[u8] = b"endheight";
+pub const RESULT_KEY: &[u8] = b"result";
+pub const FACTORY_KEY: &[u8] = b"factory";
+pub const EXECUTION_KEY: &[u8] = b"execution";
+pub const EXECUTED_KEY: &[u8] = b"executed";
+pub const PREFIX_DELEGATIONS: &[u8] = b"delegations";
+pub const PREFIX_DELEGATED_POWER: &[u8] = b"delegatedpower";
+pub const PREFIX_WITHHELD_FROM: &[u8] = b"withheldfrom";
+pub const PREFIX_VOTERS: &[u8] = b"voters";
+pub const PREFIX_VOTES: &[u8] = b"votes";
+pub const VIEWING_KEY_KEY: &[u8] = b"viewingkey";
+pub const FINALIZE_CURSOR_KEY: &[u8] = b"finalizecursor";
+pub const TOTAL_VOTING_POWER_KEY: &[u8] = b"totalvotingpower";
+pub const RANKINGS_KEY: &[u8] = b"rankings";
+pub const SNAPSHOT_HEIGHT_KEY: &[u8] = b"snapshotheight";
+
+// Every distinct ranking is kept in a single list that is loaded on each vote, so ranked-choice
+// polls are capped at a number of choices that allows no more than 325 distinct rankings
+pub const MAX_RANKED_CHOICES: usize = 5;
 
 pub type ChoiceIdMap = Vec<(u8, String)>;
 pub type Tally = HashMap<u8, u128>;
+pub type Rankings = Vec<(Vec<u8>, u128)>; // The total weight behind every distinct ranked ballot
 
 #[derive(Serialize, Deserialize, JsonSchema)]
-pub struct PollConfig {
-    pub duration: Option<u64>,     // TODO: Might want to change this later
-    pub quorum: Option<u8>,        // X/100% (percentage)
-    pub min_threshold: Option<u8>, // X/100% (percentage)
+pub struct Vote {
+    pub choices: Vec<u8>,
+    pub voting_power: u128,
 }
 
+/// A delegation made for this poll only. It takes precedence over a global delegation
 #[derive(Serialize, Deserialize, JsonSchema)]
-pub struct PollMetadata {
-    pub title: String,
-    pub description: String,
-    pub author: HumanAddr,
+pub struct Delegation {
+    pub delegate: HumanAddr,
+    pub voting_power: u128,
 }
 
-#[derive(Serialize, Deserialize, JsonSchema)]
-pub struct Vote {
-    pub choice: u8,
-    pub voting_power: u128,
+/// Voting power that other addresses delegated to an address. It is counted along with the
+/// address's own vote, so it has no effect until that address votes
+#[derive(Serialize, Deserialize, JsonSchema, Default)]
+pub struct DelegatedPower {
+    pub local: u128,    // Delegated for this poll only
+    pub global: u128,   // Delegated in the staking pool. Only kept up to date for voters
+    pub withheld: u128, // Part of `global` whose delegators voted or delegated in this poll
+}
+
+impl DelegatedPower {
+    pub fn effective(&self) -> u128 {
+        // `global` may lag behind `withheld` for an address that hasn't voted yet
+        self.local
+            .saturating_add(self.global)
+            .saturating_sub(self.withheld)
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct PollResult {
+    pub finalized_at: u64,
+    pub total_voting_power: u128,
+    pub total_staked: u128,
+    pub quorum_reached: bool,
+    pub winning_choice: Option<u8>, // None if quorum or threshold were not reached, or on a tie
+}
+
+/// Appends a first-time voter to the voters index
+pub fn push_voter<S: Storage>(storage: &mut S, voter: &HumanAddr) -> StdResult<()> {
+    let mut voters_storage = PrefixedStorage::new(PREFIX_VOTERS, storage);
+    let mut voters = AppendStoreMut::attach_or_create(&mut voters_storage)?;
+    voters.push(voter)
+}
+
+/// Loads up to `count` voters, in the order they voted, starting from the `start`th voter
+pub fn load_voters<S: ReadonlyStorage>(
+    storage: &S,
+    start: u32,
+    count: u32,
+) -> StdResult<Vec<HumanAddr>> {
+    let voters_storage = ReadonlyPrefixedStorage::new(PREFIX_VOTERS, storage);
+    let voters = if let Some(result) = AppendStore::<HumanAddr, _>::attach(&voters_storage) {
+        result?
+    } else {
+        return Ok(vec![]);
+    };
+
+    voters.iter().skip(start as _).take(count as _).collect()
+}
+
+pub fn voters_count<S: ReadonlyStorage>(storage: &S) -> StdResult<u32> {
+    let voters_storage = ReadonlyPrefixedStorage::new(PREFIX_VOTERS, storage);
+    if let Some(result) = AppendStore::<HumanAddr, _>::attach(&voters_storage) {
+        Ok(result?.len())
+    } else {
+        Ok(0)
+    }
 }
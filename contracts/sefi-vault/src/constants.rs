@@ -0,0 +1,8 @@
+pub const CONFIG_KEY: &[u8] = b"config";
+pub const STAKED_KEY: &[u8] = b"staked";
+pub const TOTAL_SHARES_KEY: &[u8] = b"totalshares";
+
+// Minted to no one on the first deposit, see `shares_for_deposit`
+pub const DEAD_SHARES: u128 = 1_000;
+
+pub const RESPONSE_BLOCK_SIZE: usize = 256;
@@ -0,0 +1,875 @@
+use cosmwasm_std::{
+    from_binary, to_binary, Api, Binary, CosmosMsg, Env, Extern, HandleResponse, HumanAddr,
+    InitResponse, Querier, StdError, StdResult, Storage, Uint128, WasmMsg,
+};
+use secret_toolkit::snip20;
+use secret_toolkit::storage::{TypedStore, TypedStoreMut};
+use secret_toolkit::utils::{pad_handle_result, pad_query_result};
+
+use crate::constants::*;
+use crate::msg::ResponseStatus::Success;
+use crate::msg::{
+    HandleAnswer, HandleMsg, InitMsg, QueryAnswer, QueryMsg, ReceiveAnswer, ReceiveMsg,
+};
+use crate::querier::{query_idle_balance, query_pool_rewards};
+use crate::state::Config;
+use scrt_finance::lp_staking_msg::{LPStakingHandleMsg, LPStakingReceiveMsg};
+use scrt_finance::math::{self, Rounding};
+use scrt_finance::types::SecretContract;
+
+pub fn init<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    msg: InitMsg,
+) -> StdResult<InitResponse> {
+    // Initialize state
+    let mut config_store = TypedStoreMut::attach(&mut deps.storage);
+    config_store.store(
+        CONFIG_KEY,
+        &Config {
+            admin: env.message.sender,
+            sefi: msg.sefi.clone(),
+            staking_pool: msg.staking_pool.clone(),
+            share_token: msg.share_token.clone(),
+            viewing_key: msg.viewing_key.clone(),
+            own_addr: env.contract.address,
+        },
+    )?;
+
+    TypedStoreMut::<u128, S>::attach(&mut deps.storage).store(STAKED_KEY, &0)?;
+    TypedStoreMut::<u128, S>::attach(&mut deps.storage).store(TOTAL_SHARES_KEY, &0)?;
+
+    // Register SEFI and the share token, set vk with SEFI and the staking pool
+    let messages = vec![
+        snip20::register_receive_msg(
+            env.contract_code_hash.clone(),
+            None,
+            1, // This is public data, no need to pad
+            msg.sefi.contract_hash.clone(),
+            msg.sefi.address.clone(),
+        )?,
+        snip20::register_receive_msg(
+            env.contract_code_hash,
+            None,
+            1,
+            msg.share_token.contract_hash,
+            msg.share_token.address,
+        )?,
+        snip20::set_viewing_key_msg(
+            msg.viewing_key.clone(),
+            None,
+            RESPONSE_BLOCK_SIZE, // This is private data, need to pad
+            msg.sefi.contract_hash,
+            msg.sefi.address,
+        )?,
+        WasmMsg::Execute {
+            contract_addr: msg.staking_pool.address,
+            callback_code_hash: msg.staking_pool.contract_hash,
+            msg: to_binary(&LPStakingHandleMsg::SetViewingKey {
+                key: msg.viewing_key,
+                padding: None,
+            })?,
+            send: vec![],
+        }
+        .into(),
+    ];
+
+    Ok(InitResponse {
+        messages,
+        log: vec![],
+    })
+}
+
+pub fn handle<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    msg: HandleMsg,
+) -> StdResult<HandleResponse> {
+    let response = match msg {
+        HandleMsg::Compound {} => compound(deps, env),
+        HandleMsg::Receive {
+            from, amount, msg, ..
+        } => receive(deps, env, from, amount.u128(), msg),
+        HandleMsg::ChangeAdmin { address } => change_admin(deps, env, address),
+        HandleMsg::Sweep { token, recipient } => sweep(deps, env, token, recipient),
+    };
+
+    pad_handle_result(response, RESPONSE_BLOCK_SIZE)
+}
+
+pub fn query<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    msg: QueryMsg,
+) -> StdResult<Binary> {
+    let response = match msg {
+        QueryMsg::Contracts {} => query_contracts(deps),
+        QueryMsg::Admin {} => query_admin(deps),
+        QueryMsg::TotalAssets { height } => query_total_assets(deps, height),
+    };
+
+    pad_query_result(response, RESPONSE_BLOCK_SIZE)
+}
+
+// Handle functions
+
+fn receive<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    from: HumanAddr,
+    amount: u128,
+    msg: Binary,
+) -> StdResult<HandleResponse> {
+    let msg: ReceiveMsg = from_binary(&msg)?;
+
+    match msg {
+        ReceiveMsg::Deposit {} => deposit(deps, env, from, amount),
+        ReceiveMsg::Redeem {} => redeem(deps, env, from, amount),
+    }
+}
+
+fn deposit<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    from: HumanAddr,
+    amount: u128,
+) -> StdResult<HandleResponse> {
+    let config: Config = TypedStore::attach(&deps.storage).load(CONFIG_KEY)?;
+    if env.message.sender != config.sefi.address {
+        return Err(StdError::generic_err(format!(
+            "This token is not supported. Supported: {}, given: {}",
+            config.sefi.address, env.message.sender
+        )));
+    }
+
+    // The deposit itself is already a part of the idle balance
+    let idle = query_idle_balance(deps)?;
+    let (pending, vested) = query_pool_rewards(deps, env.block.height)?;
+    let staked: u128 = TypedStore::attach(&deps.storage).load(STAKED_KEY)?;
+    let total_shares: u128 = TypedStore::attach(&deps.storage).load(TOTAL_SHARES_KEY)?;
+
+    let assets = math::sub(total_assets(staked, idle, pending, vested)?, amount)?;
+    let shares = shares_for_deposit(amount, assets, total_shares)?;
+    if shares == 0 {
+        return Err(StdError::generic_err(
+            "this deposit is too small to be worth a share",
+        ));
+    }
+    // The first deposit also accounts for the dead shares, which are never minted
+    let new_shares = if total_shares == 0 {
+        math::add(shares, DEAD_SHARES)?
+    } else {
+        shares
+    };
+
+    // Depositing to the staking pool also harvests its pending rewards. They are re-deposited on
+    // the next interaction, along with the vested ones
+    let messages = vec![
+        snip20::mint_msg(
+            from,
+            Uint128(shares),
+            None,
+            RESPONSE_BLOCK_SIZE,
+            config.share_token.contract_hash.clone(),
+            config.share_token.address.clone(),
+        )?,
+        stake_msg(&config, idle)?,
+    ];
+
+    TypedStoreMut::attach(&mut deps.storage).store(STAKED_KEY, &math::add(staked, idle)?)?;
+    TypedStoreMut::attach(&mut deps.storage)
+        .store(TOTAL_SHARES_KEY, &math::add(total_shares, new_shares)?)?;
+
+    Ok(HandleResponse {
+        messages,
+        log: vec![],
+        data: Some(to_binary(&ReceiveAnswer::Deposit { status: Success })?),
+    })
+}
+
+fn redeem<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    from: HumanAddr,
+    shares: u128,
+) -> StdResult<HandleResponse> {
+    let config: Config = TypedStore::attach(&deps.storage).load(CONFIG_KEY)?;
+    if env.message.sender != config.share_token.address {
+        return Err(StdError::generic_err(format!(
+            "This token is not supported. Supported: {}, given: {}",
+            config.share_token.address, env.message.sender
+        )));
+    }
+
+    let idle = query_idle_balance(deps)?;
+    let (pending, vested) = query_pool_rewards(deps, env.block.height)?;
+    let mut staked: u128 = TypedStore::attach(&deps.storage).load(STAKED_KEY)?;
+    let total_shares: u128 = TypedStore::attach(&deps.storage).load(TOTAL_SHARES_KEY)?;
+
+    // Every share in circulation was minted by this contract, and the dead shares never are, so
+    // `total_shares > shares > 0`. Rounding down, so that redeeming never takes value from the
+    // remaining shares
+    let amount = math::mul_div(
+        shares,
+        total_assets(staked, idle, pending, vested)?,
+        total_shares,
+        Rounding::Down,
+    )?;
+
+    let mut messages = vec![snip20::burn_msg(
+        Uint128(shares),
+        None,
+        RESPONSE_BLOCK_SIZE,
+        config.share_token.contract_hash.clone(),
+        config.share_token.address.clone(),
+    )?];
+    if vested > 0 {
+        messages.push(claim_vested_msg(&config)?);
+    }
+
+    // Staking or withdrawing also harvests the pending rewards. They're only relied on when the
+    // whole stake isn't enough, and are otherwise left idle until the next interaction
+    let available = math::add(idle, vested)?;
+    if amount > available {
+        let shortfall = amount - available;
+        if staked > 0 {
+            let withdrawn = shortfall.min(staked);
+            messages.push(unstake_msg(&config, withdrawn)?);
+            staked -= withdrawn;
+        } else {
+            messages.push(claim_msg(&config)?);
+        }
+    } else if amount < available {
+        messages.push(stake_msg(&config, available - amount)?);
+        staked = math::add(staked, available - amount)?;
+    }
+
+    if amount > 0 {
+        messages.push(snip20::transfer_msg(
+            from,
+            Uint128(amount),
+            None,
+            RESPONSE_BLOCK_SIZE,
+            config.sefi.contract_hash,
+            config.sefi.address,
+        )?);
+    }
+
+    TypedStoreMut::attach(&mut deps.storage).store(STAKED_KEY, &staked)?;
+    TypedStoreMut::attach(&mut deps.storage)
+        .store(TOTAL_SHARES_KEY, &math::sub(total_shares, shares)?)?;
+
+    Ok(HandleResponse {
+        messages,
+        log: vec![],
+        data: Some(to_binary(&ReceiveAnswer::Redeem { status: Success })?),
+    })
+}
+
+/// Anyone can call this to put the vault's rewards to work between deposits and redemptions
+fn compound<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+) -> StdResult<HandleResponse> {
+    let config: Config = TypedStore::attach(&deps.storage).load(CONFIG_KEY)?;
+    let idle = query_idle_balance(deps)?;
+    let (_, vested) = query_pool_rewards(deps, env.block.height)?;
+
+    let mut messages = vec![];
+    if vested > 0 {
+        messages.push(claim_vested_msg(&config)?);
+    }
+
+    let amount = math::add(idle, vested)?;
+    if amount > 0 {
+        let mut staked_store = TypedStoreMut::<u128, S>::attach(&mut deps.storage);
+        let staked = staked_store.load(STAKED_KEY)?;
+        staked_store.store(STAKED_KEY, &math::add(staked, amount)?)?;
+
+        messages.push(stake_msg(&config, amount)?);
+    } else {
+        // Nothing to re-deposit, only harvest
+        messages.push(claim_msg(&config)?);
+    }
+
+    Ok(HandleResponse {
+        messages,
+        log: vec![],
+        data: Some(to_binary(&HandleAnswer::Compound { status: Success })?),
+    })
+}
+
+fn change_admin<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    address: HumanAddr,
+) -> StdResult<HandleResponse> {
+    let mut config_store = TypedStoreMut::attach(&mut deps.storage);
+    let mut config: Config = config_store.load(CONFIG_KEY)?;
+
+    enforce_admin(&config, &env)?;
+
+    config.admin = address;
+    config_store.store(CONFIG_KEY, &config)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![],
+        data: Some(to_binary(&HandleAnswer::ChangeAdmin { status: Success })?),
+    })
+}
+
+fn sweep<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    token: SecretContract,
+    recipient: Option<HumanAddr>,
+) -> StdResult<HandleResponse> {
+    let config: Config = TypedStore::attach(&deps.storage).load(CONFIG_KEY)?;
+    enforce_admin(&config, &env)?;
+
+    if token.address == config.sefi.address || token.address == config.share_token.address {
+        return Err(StdError::generic_err(format!(
+            "{} belongs to the depositors and cannot be swept",
+            token.address
+        )));
+    }
+
+    let balance = snip20::balance_query(
+        &deps.querier,
+        config.own_addr,
+        config.viewing_key.clone(),
+        RESPONSE_BLOCK_SIZE,
+        token.contract_hash.clone(),
+        token.address.clone(),
+    );
+    let messages = match balance {
+        // No viewing key yet
+        Err(_) => vec![snip20::set_viewing_key_msg(
+            config.viewing_key,
+            None,
+            RESPONSE_BLOCK_SIZE,
+            token.contract_hash,
+            token.address,
+        )?],
+        Ok(balance) if balance.amount.u128() > 0 => vec![snip20::transfer_msg(
+            recipient.unwrap_or(config.admin),
+            balance.amount,
+            None,
+            RESPONSE_BLOCK_SIZE,
+            token.contract_hash,
+            token.address,
+        )?],
+        Ok(_) => vec![],
+    };
+
+    Ok(HandleResponse {
+        messages,
+        log: vec![],
+        data: Some(to_binary(&HandleAnswer::Sweep { status: Success })?),
+    })
+}
+
+// Query functions
+
+fn query_contracts<S: Storage, A: Api, Q: Querier>(deps: &Extern<S, A, Q>) -> StdResult<Binary> {
+    let config: Config = TypedStore::attach(&deps.storage).load(CONFIG_KEY)?;
+
+    to_binary(&QueryAnswer::Contracts {
+        sefi: config.sefi,
+        staking_pool: config.staking_pool,
+        share_token: config.share_token,
+    })
+}
+
+fn query_admin<S: Storage, A: Api, Q: Querier>(deps: &Extern<S, A, Q>) -> StdResult<Binary> {
+    let config: Config = TypedStore::attach(&deps.storage).load(CONFIG_KEY)?;
+
+    to_binary(&QueryAnswer::Admin {
+        address: config.admin,
+    })
+}
+
+fn query_total_assets<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    height: u64,
+) -> StdResult<Binary> {
+    let idle = query_idle_balance(deps)?;
+    let (pending, vested) = query_pool_rewards(deps, height)?;
+    let staked: u128 = TypedStore::attach(&deps.storage).load(STAKED_KEY)?;
+    let total_shares: u128 = TypedStore::attach(&deps.storage).load(TOTAL_SHARES_KEY)?;
+
+    to_binary(&QueryAnswer::TotalAssets {
+        assets: Uint128(total_assets(staked, idle, pending, vested)?),
+        shares: Uint128(total_shares),
+    })
+}
+
+// Helper functions
+
+fn enforce_admin(config: &Config, env: &Env) -> StdResult<()> {
+    if config.admin != env.message.sender {
+        return Err(StdError::generic_err(format!(
+            "not an admin: {}",
+            env.message.sender
+        )));
+    }
+
+    Ok(())
+}
+
+/// What the shares are worth: the SEFI that is staked or idle, and the pool rewards that can be
+/// claimed without a penalty (see `query_pool_rewards`). Rewards that are still vesting only count
+/// once they vest, so whoever redeems before that leaves them to the remaining shares
+fn total_assets(staked: u128, idle: u128, pending: u128, vested: u128) -> StdResult<u128> {
+    math::add(math::add(staked, idle)?, math::add(pending, vested)?)
+}
+
+/// The first deposit gets a share per SEFI, minus `DEAD_SHARES` that are never minted. Otherwise,
+/// sending SEFI straight to a nearly empty vault could inflate the share price enough for the next
+/// deposits to round down to nothing. Any other deposit to a vault that holds nothing also gets a
+/// share per SEFI
+fn shares_for_deposit(amount: u128, total_assets: u128, total_shares: u128) -> StdResult<u128> {
+    if total_shares == 0 {
+        Ok(amount.saturating_sub(DEAD_SHARES))
+    } else if total_assets == 0 {
+        Ok(amount)
+    } else {
+        // Rounding down, so that depositing never takes value from the existing shares
+        math::mul_div(amount, total_shares, total_assets, Rounding::Down)
+    }
+}
+
+fn stake_msg(config: &Config, amount: u128) -> StdResult<CosmosMsg> {
+    snip20::send_msg(
+        config.staking_pool.address.clone(),
+        Uint128(amount),
+        Some(to_binary(&LPStakingReceiveMsg::Deposit {
+            lock_period: None,
+            beneficiary: None,
+            referrer: None,
+        })?),
+        None,
+        RESPONSE_BLOCK_SIZE,
+        config.sefi.contract_hash.clone(),
+        config.sefi.address.clone(),
+    )
+}
+
+fn unstake_msg(config: &Config, amount: u128) -> StdResult<CosmosMsg> {
+    Ok(WasmMsg::Execute {
+        contract_addr: config.staking_pool.address.clone(),
+        callback_code_hash: config.staking_pool.contract_hash.clone(),
+        msg: to_binary(&LPStakingHandleMsg::Redeem {
+            amount: Some(Uint128(amount)),
+            recipient: None,
+        })?,
+        send: vec![],
+    }
+    .into())
+}
+
+fn claim_vested_msg(config: &Config) -> StdResult<CosmosMsg> {
+    Ok(WasmMsg::Execute {
+        contract_addr: config.staking_pool.address.clone(),
+        callback_code_hash: config.staking_pool.contract_hash.clone(),
+        msg: to_binary(&LPStakingHandleMsg::ClaimVested {
+            recipient: None,
+            include_locked: None,
+        })?,
+        send: vec![],
+    }
+    .into())
+}
+
+fn claim_msg(config: &Config) -> StdResult<CosmosMsg> {
+    Ok(WasmMsg::Execute {
+        contract_addr: config.staking_pool.address.clone(),
+        callback_code_hash: config.staking_pool.contract_hash.clone(),
+        msg: to_binary(&LPStakingHandleMsg::Claim { recipient: None })?,
+        send: vec![],
+    }
+    .into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cosmwasm_std::testing::{mock_env, MockApi, MockStorage};
+    use cosmwasm_std::{from_slice, Empty, QuerierResult, QueryRequest, WasmQuery};
+    use scrt_finance::lp_staking_msg::{LPStakingQueryAnswer, LPStakingQueryMsg, VestingConfig};
+    use serde::{Deserialize, Serialize};
+
+    type TestDeps = Extern<MockStorage, MockApi, VaultQuerier>;
+
+    /// Answers the vault's SEFI balance, and what the staking pool owes it. The vault's balance of
+    /// the pool's extra reward token is only answered once it has a viewing key with it
+    #[derive(Default)]
+    struct VaultQuerier {
+        idle: u128,
+        pending: u128,
+        vested: u128,
+        vesting: bool,
+        extra: Option<u128>,
+    }
+
+    impl Querier for VaultQuerier {
+        fn raw_query(&self, bin_request: &[u8]) -> QuerierResult {
+            let request: QueryRequest<Empty> = from_slice(bin_request).unwrap();
+            let (contract_addr, msg) = match request {
+                QueryRequest::Wasm(WasmQuery::Smart {
+                    contract_addr, msg, ..
+                }) => (contract_addr, msg),
+                other => panic!("Unexpected query: {:?}", other),
+            };
+
+            let answer = match contract_addr.0.as_str() {
+                "sefi" => Binary::from(
+                    format!(r#"{{"balance":{{"amount":"{}"}}}}"#, self.idle).as_bytes(),
+                ),
+                "eth" => match self.extra {
+                    Some(extra) => Binary::from(
+                        format!(r#"{{"balance":{{"amount":"{}"}}}}"#, extra).as_bytes(),
+                    ),
+                    None => Binary::from(
+                        r#"{"viewing_key_error":{"msg":"Wrong viewing key"}}"#.as_bytes(),
+                    ),
+                },
+                "pool" => to_binary(&match from_binary(&msg).unwrap() {
+                    LPStakingQueryMsg::VestingConfig {} => LPStakingQueryAnswer::VestingConfig {
+                        vesting: if self.vesting {
+                            Some(VestingConfig {
+                                period: 100,
+                                penalty_bps: 0,
+                            })
+                        } else {
+                            None
+                        },
+                    },
+                    LPStakingQueryMsg::Rewards { .. } => LPStakingQueryAnswer::Rewards {
+                        rewards: Uint128(self.pending),
+                        extra_rewards: vec![],
+                    },
+                    LPStakingQueryMsg::Vesting { .. } => LPStakingQueryAnswer::Vesting {
+                        vested: Uint128(self.vested),
+                        locked: Uint128(0),
+                    },
+                    other => panic!("Unexpected pool query: {:?}", other),
+                })
+                .unwrap(),
+                other => panic!("Unexpected query to {}", other),
+            };
+
+            Ok(Ok(answer))
+        }
+    }
+
+    // Helper functions
+
+    fn init_helper() -> TestDeps {
+        let mut deps = Extern {
+            storage: MockStorage::default(),
+            api: MockApi::new(20),
+            querier: VaultQuerier::default(),
+        };
+
+        let init_msg = InitMsg {
+            sefi: SecretContract {
+                address: HumanAddr("sefi".to_string()),
+                contract_hash: "1".to_string(),
+            },
+            staking_pool: SecretContract {
+                address: HumanAddr("pool".to_string()),
+                contract_hash: "2".to_string(),
+            },
+            share_token: SecretContract {
+                address: HumanAddr("share".to_string()),
+                contract_hash: "3".to_string(),
+            },
+            viewing_key: "123".to_string(),
+        };
+        init(&mut deps, mock_env("admin", &[]), init_msg).unwrap();
+
+        deps
+    }
+
+    /// `amount` of `token` sent to the vault by `from`, which must already be in the idle balance
+    fn receive_helper(
+        deps: &mut TestDeps,
+        token: &str,
+        from: &str,
+        amount: u128,
+        msg: ReceiveMsg,
+    ) -> StdResult<HandleResponse> {
+        let receive_msg = HandleMsg::Receive {
+            sender: HumanAddr(from.to_string()),
+            from: HumanAddr(from.to_string()),
+            amount: Uint128(amount),
+            msg: to_binary(&msg).unwrap(),
+        };
+
+        handle(deps, mock_env(token, &[]), receive_msg)
+    }
+
+    fn query_assets(deps: &TestDeps) -> (u128, u128) {
+        let query_msg = QueryMsg::TotalAssets { height: 12_345 };
+        match from_binary(&query(deps, query_msg).unwrap()).unwrap() {
+            QueryAnswer::TotalAssets { assets, shares } => (assets.u128(), shares.u128()),
+            other => panic!("Unexpected answer: {:?}", other),
+        }
+    }
+
+    fn extract_error_msg<T>(error: StdResult<T>) -> String {
+        match error {
+            Ok(_) => panic!("An error was expected"),
+            Err(StdError::GenericErr { msg, .. }) => msg,
+            Err(err) => panic!("Unexpected error: {:?}", err),
+        }
+    }
+
+    /// A short description of every token transfer and staking pool call in `messages`
+    fn describe(messages: &[CosmosMsg]) -> Vec<String> {
+        messages
+            .iter()
+            .map(|m| match m {
+                CosmosMsg::Wasm(WasmMsg::Execute {
+                    contract_addr, msg, ..
+                }) if contract_addr.0 == "pool" => match from_binary(msg).unwrap() {
+                    LPStakingHandleMsg::Redeem { amount, .. } => {
+                        format!("unstake {}", amount.unwrap())
+                    }
+                    LPStakingHandleMsg::Claim { .. } => "claim".to_string(),
+                    LPStakingHandleMsg::ClaimVested { .. } => "claim vested".to_string(),
+                    other => panic!("Unexpected pool message: {:?}", other),
+                },
+                CosmosMsg::Wasm(WasmMsg::Execute { msg, .. }) => match from_binary(msg).unwrap() {
+                    Snip20HandleMsg::Transfer {
+                        recipient, amount, ..
+                    } => format!("transfer {} {}", recipient, amount),
+                    Snip20HandleMsg::Send {
+                        recipient, amount, ..
+                    } => format!("send {} {}", recipient, amount),
+                    Snip20HandleMsg::Mint {
+                        recipient, amount, ..
+                    } => format!("mint {} {}", recipient, amount),
+                    Snip20HandleMsg::Burn { amount, .. } => format!("burn {}", amount),
+                    Snip20HandleMsg::SetViewingKey { key, .. } => format!("set key {}", key),
+                },
+                other => panic!("Unexpected message: {:?}", other),
+            })
+            .collect()
+    }
+
+    // Tests
+
+    #[test]
+    fn test_shares_for_deposit() {
+        assert_eq!(shares_for_deposit(1_000, 0, 0).unwrap(), 0);
+        assert_eq!(shares_for_deposit(10_000, 0, 0).unwrap(), 9_000);
+        assert_eq!(shares_for_deposit(1_000, 1_000, 1_000).unwrap(), 1_000);
+        assert_eq!(shares_for_deposit(1_000, 0, 1_000).unwrap(), 1_000);
+
+        // Compounded rewards make each share worth more
+        assert_eq!(shares_for_deposit(1_000, 2_000, 1_000).unwrap(), 500);
+        assert_eq!(shares_for_deposit(1, 3, 1).unwrap(), 0);
+        assert!(shares_for_deposit(u128::MAX, 1, 2).is_err());
+    }
+
+    #[test]
+    fn test_deposit() {
+        let mut deps = init_helper();
+
+        // The first deposit must be worth more than the dead shares
+        deps.querier.idle = 1_000;
+        let handle_result =
+            receive_helper(&mut deps, "sefi", "user", 1_000, ReceiveMsg::Deposit {});
+        assert_eq!(
+            extract_error_msg(handle_result),
+            "this deposit is too small to be worth a share".to_string()
+        );
+
+        deps.querier.idle = 10_000;
+        let handle_response =
+            receive_helper(&mut deps, "sefi", "user", 10_000, ReceiveMsg::Deposit {}).unwrap();
+        assert_eq!(
+            describe(&handle_response.messages),
+            vec!["mint user 9000", "send pool 10000"]
+        );
+        deps.querier.idle = 0;
+        assert_eq!(query_assets(&deps), (10_000, 10_000));
+
+        // Pending rewards and SEFI sent straight to the vault both count towards the share price
+        deps.querier.pending = 9_000;
+        deps.querier.idle = 1_000 + 1_000;
+        let handle_response =
+            receive_helper(&mut deps, "sefi", "other", 1_000, ReceiveMsg::Deposit {}).unwrap();
+        assert_eq!(
+            describe(&handle_response.messages),
+            vec!["mint other 500", "send pool 2000"]
+        );
+        deps.querier.idle = 0;
+        assert_eq!(query_assets(&deps), (12_000 + 9_000, 10_500));
+
+        let handle_result =
+            receive_helper(&mut deps, "share", "user", 1_000, ReceiveMsg::Deposit {});
+        assert_eq!(
+            extract_error_msg(handle_result),
+            "This token is not supported. Supported: sefi, given: share".to_string()
+        );
+    }
+
+    #[test]
+    fn test_redeem() {
+        let mut deps = init_helper();
+        deps.querier.idle = 10_000;
+        receive_helper(&mut deps, "sefi", "user", 10_000, ReceiveMsg::Deposit {}).unwrap();
+
+        // Withdrawing from the pool harvests the pending rewards, which are left idle
+        deps.querier.idle = 0;
+        deps.querier.pending = 1_000;
+        let handle_response =
+            receive_helper(&mut deps, "share", "user", 4_500, ReceiveMsg::Redeem {}).unwrap();
+        assert_eq!(
+            describe(&handle_response.messages),
+            vec!["burn 4500", "unstake 4950", "transfer user 4950"]
+        );
+
+        // Whatever is idle beyond the redeemed amount goes back to the pool
+        deps.querier.idle = 1_000;
+        deps.querier.pending = 0;
+        assert_eq!(query_assets(&deps), (5_050 + 1_000, 5_500));
+        let handle_response =
+            receive_helper(&mut deps, "share", "user", 500, ReceiveMsg::Redeem {}).unwrap();
+        assert_eq!(
+            describe(&handle_response.messages),
+            vec!["burn 500", "send pool 450", "transfer user 550"]
+        );
+
+        // The vested rewards are claimed first, and rounding favours the remaining shares
+        deps.querier.idle = 0;
+        deps.querier.vested = 1;
+        let handle_response =
+            receive_helper(&mut deps, "share", "user", 3_000, ReceiveMsg::Redeem {}).unwrap();
+        assert_eq!(
+            describe(&handle_response.messages),
+            vec![
+                "burn 3000",
+                "claim vested",
+                "unstake 3299",
+                "transfer user 3300"
+            ]
+        );
+        deps.querier.vested = 0;
+        assert_eq!(query_assets(&deps), (2_201, 2_000));
+
+        let handle_result = receive_helper(&mut deps, "sefi", "user", 1_000, ReceiveMsg::Redeem {});
+        assert_eq!(
+            extract_error_msg(handle_result),
+            "This token is not supported. Supported: share, given: sefi".to_string()
+        );
+    }
+
+    #[test]
+    fn test_compound() {
+        let mut deps = init_helper();
+        deps.querier.idle = 10_000;
+        receive_helper(&mut deps, "sefi", "user", 10_000, ReceiveMsg::Deposit {}).unwrap();
+
+        // With vesting on, pending rewards only count once they vest
+        deps.querier.vesting = true;
+        deps.querier.idle = 200;
+        deps.querier.pending = 500;
+        deps.querier.vested = 300;
+        assert_eq!(query_assets(&deps), (10_000 + 200 + 300, 10_000));
+        let handle_response = handle(&mut deps, mock_env("anyone", &[]), HandleMsg::Compound {});
+        assert_eq!(
+            describe(&handle_response.unwrap().messages),
+            vec!["claim vested", "send pool 500"]
+        );
+
+        // Nothing to re-deposit, only harvest
+        deps.querier.idle = 0;
+        deps.querier.vested = 0;
+        assert_eq!(query_assets(&deps), (10_500, 10_000));
+        let handle_response = handle(&mut deps, mock_env("anyone", &[]), HandleMsg::Compound {});
+        assert_eq!(describe(&handle_response.unwrap().messages), vec!["claim"]);
+    }
+
+    #[test]
+    fn test_sweep() {
+        let mut deps = init_helper();
+        deps.querier.idle = 10_000;
+        receive_helper(&mut deps, "sefi", "user", 10_000, ReceiveMsg::Deposit {}).unwrap();
+        deps.querier.idle = 0;
+
+        let token = |address: &str| SecretContract {
+            address: HumanAddr(address.to_string()),
+            contract_hash: "4".to_string(),
+        };
+        let sweep_msg = |address: &str| HandleMsg::Sweep {
+            token: token(address),
+            recipient: None,
+        };
+
+        let handle_result = handle(&mut deps, mock_env("user", &[]), sweep_msg("eth"));
+        assert_eq!(
+            extract_error_msg(handle_result),
+            "not an admin: user".to_string()
+        );
+        for address in &["sefi", "share"] {
+            let handle_result = handle(&mut deps, mock_env("admin", &[]), sweep_msg(address));
+            assert_eq!(
+                extract_error_msg(handle_result),
+                format!("{} belongs to the depositors and cannot be swept", address)
+            );
+        }
+
+        // The pool's extra rewards reached the vault along with its SEFI rewards. It needs a
+        // viewing key to see how much of them it has
+        let handle_response = handle(&mut deps, mock_env("admin", &[]), sweep_msg("eth")).unwrap();
+        assert_eq!(describe(&handle_response.messages), vec!["set key 123"]);
+
+        deps.querier.extra = Some(700);
+        let sweep_msg = HandleMsg::Sweep {
+            token: token("eth"),
+            recipient: Some(HumanAddr("treasury".to_string())),
+        };
+        let handle_response = handle(&mut deps, mock_env("admin", &[]), sweep_msg).unwrap();
+        assert_eq!(
+            describe(&handle_response.messages),
+            vec!["transfer treasury 700"]
+        );
+
+        // The share price isn't affected
+        assert_eq!(query_assets(&deps), (10_000, 10_000));
+    }
+
+    /// SNIP20 token handle messages
+    #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+    #[serde(rename_all = "snake_case")]
+    pub enum Snip20HandleMsg {
+        Transfer {
+            recipient: HumanAddr,
+            amount: Uint128,
+            padding: Option<String>,
+        },
+        Send {
+            recipient: HumanAddr,
+            amount: Uint128,
+            msg: Option<Binary>,
+            padding: Option<String>,
+        },
+        Mint {
+            recipient: HumanAddr,
+            amount: Uint128,
+            padding: Option<String>,
+        },
+        Burn {
+            amount: Uint128,
+            padding: Option<String>,
+        },
+        SetViewingKey {
+            key: String,
+            padding: Option<String>,
+        },
+    }
+}
@@ -0,0 +1,99 @@
+use cosmwasm_std::{Binary, HumanAddr, Uint128};
+use schemars::JsonSchema;
+use scrt_finance::types::SecretContract;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct InitMsg {
+    pub sefi: SecretContract,
+    // Must be an lp-staking pool that incentivizes SEFI with SEFI. If the pool has a redeem fee,
+    // its admin must add this contract to the pool's fee exempt list, or every redeem pays it
+    pub staking_pool: SecretContract,
+    pub share_token: SecretContract, // A SNIP-20 that this contract is allowed to mint
+    pub viewing_key: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum HandleMsg {
+    // Re-deposits the rewards harvested so far, and harvests the pending ones
+    Compound {},
+
+    // Registered commands
+    Receive {
+        sender: HumanAddr,
+        from: HumanAddr,
+        amount: Uint128,
+        msg: Binary,
+    },
+
+    // Admin commands
+    ChangeAdmin {
+        address: HumanAddr,
+    },
+    // Transfers the vault's whole balance of a token other than SEFI or the shares, such as the
+    // staking pool's extra rewards, to `recipient`, or to the admin if not set. The vault has no
+    // viewing key with such tokens at first, so the first sweep of a token only sets one, and the
+    // next one transfers the balance
+    Sweep {
+        token: SecretContract,
+        recipient: Option<HumanAddr>,
+    },
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum HandleAnswer {
+    Compound { status: ResponseStatus },
+    ChangeAdmin { status: ResponseStatus },
+    Sweep { status: ResponseStatus },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ReceiveMsg {
+    // Send SEFI to get shares
+    Deposit {},
+    // Send shares to get SEFI
+    Redeem {},
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ReceiveAnswer {
+    Deposit { status: ResponseStatus },
+    Redeem { status: ResponseStatus },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum QueryMsg {
+    Contracts {},
+    Admin {},
+    TotalAssets { height: u64 },
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum QueryAnswer {
+    Contracts {
+        sefi: SecretContract,
+        staking_pool: SecretContract,
+        share_token: SecretContract,
+    },
+    Admin {
+        address: HumanAddr,
+    },
+    // A share is worth `assets / shares` SEFI. Rewards that are still vesting are not included
+    TotalAssets {
+        assets: Uint128,
+        shares: Uint128,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ResponseStatus {
+    Success,
+    Failure,
+}
@@ -0,0 +1,87 @@
+use crate::constants::CONFIG_KEY;
+use crate::state::Config;
+use cosmwasm_std::{
+    to_binary, Api, Extern, Querier, QueryRequest, StdError, StdResult, Storage, WasmQuery,
+};
+use scrt_finance::lp_staking_msg::{LPStakingQueryAnswer, LPStakingQueryMsg};
+use secret_toolkit::snip20;
+use secret_toolkit::storage::TypedStore;
+
+/// Returns the SEFI that this contract holds but didn't deposit to the staking pool yet. These are
+/// mostly rewards that were harvested since the last interaction
+pub fn query_idle_balance<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+) -> StdResult<u128> {
+    let config: Config = TypedStore::attach(&deps.storage).load(CONFIG_KEY)?;
+
+    let balance = snip20::balance_query(
+        &deps.querier,
+        config.own_addr,
+        config.viewing_key,
+        256,
+        config.sefi.contract_hash,
+        config.sefi.address,
+    )?;
+
+    Ok(balance.amount.u128())
+}
+
+/// The rewards that the staking pool owes this contract as of `height`, and that can be had
+/// without a penalty: the pending ones, and those that finished vesting. Pending rewards only
+/// count when the pool pays them out right away, since otherwise harvesting them starts vesting
+/// them instead. Returns `(pending, vested)`
+pub fn query_pool_rewards<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    height: u64,
+) -> StdResult<(u128, u128)> {
+    let config: Config = TypedStore::attach(&deps.storage).load(CONFIG_KEY)?;
+
+    let vesting_on = match query_staking_pool(deps, &config, LPStakingQueryMsg::VestingConfig {})? {
+        LPStakingQueryAnswer::VestingConfig { vesting } => vesting.is_some(),
+        _ => return Err(unexpected_answer()),
+    };
+
+    let pending = if vesting_on {
+        0
+    } else {
+        let msg = LPStakingQueryMsg::Rewards {
+            address: config.own_addr.clone(),
+            key: config.viewing_key.clone(),
+            height,
+        };
+        match query_staking_pool(deps, &config, msg)? {
+            LPStakingQueryAnswer::Rewards { rewards, .. } => rewards.u128(),
+            LPStakingQueryAnswer::QueryError { msg } => return Err(StdError::generic_err(msg)),
+            _ => return Err(unexpected_answer()),
+        }
+    };
+
+    let msg = LPStakingQueryMsg::Vesting {
+        address: config.own_addr.clone(),
+        key: config.viewing_key.clone(),
+        height,
+    };
+    let vested = match query_staking_pool(deps, &config, msg)? {
+        LPStakingQueryAnswer::Vesting { vested, .. } => vested.u128(),
+        LPStakingQueryAnswer::QueryError { msg } => return Err(StdError::generic_err(msg)),
+        _ => return Err(unexpected_answer()),
+    };
+
+    Ok((pending, vested))
+}
+
+fn query_staking_pool<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    config: &Config,
+    msg: LPStakingQueryMsg,
+) -> StdResult<LPStakingQueryAnswer> {
+    deps.querier.query(&QueryRequest::Wasm(WasmQuery::Smart {
+        callback_code_hash: config.staking_pool.contract_hash.clone(),
+        contract_addr: config.staking_pool.address.clone(),
+        msg: to_binary(&msg)?,
+    }))
+}
+
+fn unexpected_answer() -> StdError {
+    StdError::generic_err("something is wrong with the staking pool contract..")
+}
@@ -1,5 +1,8 @@
 pub mod lp_staking_msg;
 pub mod master_msg;
+pub mod math;
+pub mod permit;
+pub mod secret_poll_msg;
 pub mod types;
 pub mod utils;
 pub mod viewing_key;
@@ -1,3 +1,4 @@
+use crate::permit::Permit;
 use crate::types::{SecretContract, TokenInfo};
 use crate::viewing_key::ViewingKey;
 use cosmwasm_std::{Binary, HumanAddr, Uint128};
@@ -9,6 +10,20 @@ use serde::{Deserialize, Serialize};
 pub enum LPStakingHandleMsg {
     Redeem {
         amount: Option<Uint128>,
+        recipient: Option<HumanAddr>, // The sender if not set
+    },
+    Claim {
+        recipient: Option<HumanAddr>, // The sender if not set
+    },
+    TransferStake {
+        recipient: HumanAddr,
+        amount: Uint128,
+    },
+    // SNIP-20 compatible alias of `TransferStake`
+    Transfer {
+        recipient: HumanAddr,
+        amount: Uint128,
+        padding: Option<String>,
     },
     CreateViewingKey {
         entropy: String,
@@ -18,7 +33,29 @@ pub enum LPStakingHandleMsg {
         key: String,
         padding: Option<String>,
     },
+    RevokePermit {
+        permit_name: String,
+        padding: Option<String>,
+    },
     EmergencyRedeem {},
+    Delegate {
+        delegate: Option<HumanAddr>,
+    },
+    ExpireLock {
+        address: HumanAddr,
+    },
+    // Moves the sender's whole balance to the pool that the admin set as the migration target
+    MigrateTo {
+        new_pool: HumanAddr,
+    },
+    ClaimReferralRewards {
+        recipient: Option<HumanAddr>, // The sender if not set
+    },
+    ClaimVested {
+        recipient: Option<HumanAddr>, // The sender if not set
+        // Also takes whatever is still locked, minus the early claim penalty
+        include_locked: Option<bool>,
+    },
 
     // Registered commands
     Receive {
@@ -34,6 +71,56 @@ pub enum LPStakingHandleMsg {
     ChangeAdmin {
         address: HumanAddr,
     },
+    SetPollFactory {
+        address: Option<HumanAddr>,
+    },
+    AddRewardToken {
+        token: SecretContract,
+    },
+    // Ends the pool at `block`. Stakers stop earning the master's rewards after it
+    SetDeadline {
+        block: u64,
+    },
+    // Sweeps the rewards that weren't handed out to stakers, once the pool has ended
+    ClaimRewardPool {
+        recipient: Option<HumanAddr>, // The sender if not set
+    },
+    SetLockTiers {
+        tiers: Vec<LockTier>,
+    },
+    SetMigrationTarget {
+        new_pool: Option<SecretContract>, // None disables migrating
+    },
+    SetRedeemFee {
+        fee: Option<RedeemFee>, // None disables the fee
+    },
+    AddFeeExempt {
+        contracts: Vec<HumanAddr>,
+    },
+    RemoveFeeExempt {
+        contracts: Vec<HumanAddr>,
+    },
+    // The share of a referred staker's rewards that goes to their referrer. 0 disables referrals
+    SetReferralRate {
+        rate_bps: u64,
+    },
+    SetVesting {
+        vesting: Option<VestingConfig>, // None pays harvested rewards out right away
+    },
+
+    // Admin or poll factory commands
+    AddTrustedContracts {
+        contracts: Vec<HumanAddr>,
+    },
+    RemoveTrustedContracts {
+        contracts: Vec<HumanAddr>,
+    },
+    AddSubscribers {
+        contracts: Vec<SecretContract>,
+    },
+    RemoveSubscribers {
+        contracts: Vec<HumanAddr>,
+    },
 
     // Master callbacks
     NotifyAllocation {
@@ -50,26 +137,61 @@ pub struct LPStakingInitMsg {
     pub viewing_key: String,
     pub token_info: TokenInfo,
     pub prng_seed: Binary,
+    // A power of 10, at least 10^decimals of the incentivized token. Defaults to 10^12
+    pub reward_scale: Option<Uint128>,
 }
 
 #[derive(Serialize, Deserialize, JsonSchema, Debug)]
 #[serde(rename_all = "snake_case")]
 pub enum LPStakingHandleAnswer {
     Redeem { status: LPStakingResponseStatus },
+    Claim { status: LPStakingResponseStatus },
+    TransferStake { status: LPStakingResponseStatus },
+    MigrateTo { status: LPStakingResponseStatus },
+    ClaimReferralRewards { status: LPStakingResponseStatus },
+    ClaimVested { status: LPStakingResponseStatus },
     CreateViewingKey { key: ViewingKey },
     SetViewingKey { status: LPStakingResponseStatus },
+    RevokePermit { status: LPStakingResponseStatus },
     StopContract { status: LPStakingResponseStatus },
     ResumeContract { status: LPStakingResponseStatus },
     ChangeAdmin { status: LPStakingResponseStatus },
+    SetPollFactory { status: LPStakingResponseStatus },
+    AddRewardToken { status: LPStakingResponseStatus },
+    SetLockTiers { status: LPStakingResponseStatus },
+    SetMigrationTarget { status: LPStakingResponseStatus },
+    SetRedeemFee { status: LPStakingResponseStatus },
+    AddFeeExempt { status: LPStakingResponseStatus },
+    RemoveFeeExempt { status: LPStakingResponseStatus },
+    SetReferralRate { status: LPStakingResponseStatus },
+    SetVesting { status: LPStakingResponseStatus },
+    AddTrustedContracts { status: LPStakingResponseStatus },
+    RemoveTrustedContracts { status: LPStakingResponseStatus },
+    AddSubscribers { status: LPStakingResponseStatus },
+    RemoveSubscribers { status: LPStakingResponseStatus },
     SetDeadline { status: LPStakingResponseStatus },
     ClaimRewardPool { status: LPStakingResponseStatus },
     EmergencyRedeem { status: LPStakingResponseStatus },
+    Delegate { status: LPStakingResponseStatus },
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum LPStakingReceiveMsg {
-    Deposit {},
+    Deposit {
+        // Locks the whole balance for this many blocks in exchange for a reward boost. Must match
+        // one of the pool's lock tiers
+        lock_period: Option<u64>,
+        // Credits the deposit to someone else. Only the beneficiary themselves can lock it
+        beneficiary: Option<HumanAddr>,
+        // Only recorded on the depositor's first deposit, and can't be changed later
+        referrer: Option<HumanAddr>,
+    },
+    // Funds an extra reward token, paid out evenly per block until `end_block`. Admin only, since
+    // it also spreads whatever is left of the current campaign until the new `end_block`
+    DepositRewards {
+        end_block: u64,
+    },
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -77,11 +199,33 @@ pub enum LPStakingReceiveMsg {
 pub enum LPStakingHookMsg {
     Deposit {
         from: HumanAddr,
+        beneficiary: HumanAddr,
         amount: Uint128,
+        lock_period: Option<u64>,
     },
     Redeem {
         to: HumanAddr,
         amount: Option<Uint128>,
+        recipient: HumanAddr,
+    },
+    Claim {
+        staker: HumanAddr,
+        recipient: HumanAddr,
+    },
+    TransferStake {
+        from: HumanAddr,
+        recipient: HumanAddr,
+        amount: Uint128,
+    },
+    ExpireLock {
+        address: HumanAddr,
+    },
+    ClaimRewardPool {
+        recipient: HumanAddr,
+    },
+    MigrateTo {
+        staker: HumanAddr,
+        new_pool: SecretContract,
     },
 }
 
@@ -98,7 +242,24 @@ pub enum LPStakingQueryMsg {
     TokenInfo {},
     ContractStatus {},
     RewardToken {},
+    RewardTokens {},
     IncentivizedToken {},
+    TotalLocked {},
+    TotalLockedAt {
+        height: u64,
+    },
+    Subscribers {},
+    LockTiers {},
+    PoolStats {
+        height: u64,
+    },
+    TrustedContracts {},
+    Deadline {},
+    MigrationTarget {},
+    RedeemFee {},
+    FeeExempt {},
+    ReferralRate {},
+    VestingConfig {},
 
     // Authenticated
     Rewards {
@@ -110,6 +271,47 @@ pub enum LPStakingQueryMsg {
         address: HumanAddr,
         key: String,
     },
+    BalanceAt {
+        address: HumanAddr,
+        key: String,
+        height: u64,
+    },
+    Delegation {
+        address: HumanAddr,
+        key: String,
+        height: Option<u64>, // Current delegation if not set
+    },
+    Lock {
+        address: HumanAddr,
+        key: String,
+    },
+    ReferralRewards {
+        address: HumanAddr,
+        key: String,
+    },
+    Vesting {
+        address: HumanAddr,
+        key: String,
+        height: u64,
+    },
+    // Lets a trusted contract read any staker's balance. The key is the contract's own viewing key
+    StakerBalance {
+        staker: HumanAddr,
+        height: Option<u64>, // Current balance if not set
+        contract: HumanAddr,
+        key: String,
+    },
+    // Same as `StakerBalance`, for the staker's delegation
+    StakerDelegation {
+        staker: HumanAddr,
+        height: Option<u64>, // Current delegation if not set
+        contract: HumanAddr,
+        key: String,
+    },
+    WithPermit {
+        permit: Permit,
+        query: LPStakingQueryWithPermit,
+    },
 }
 
 impl LPStakingQueryMsg {
@@ -117,11 +319,39 @@ impl LPStakingQueryMsg {
         match self {
             LPStakingQueryMsg::Rewards { address, key, .. } => (address, ViewingKey(key.clone())),
             LPStakingQueryMsg::Balance { address, key } => (address, ViewingKey(key.clone())),
+            LPStakingQueryMsg::BalanceAt { address, key, .. } => (address, ViewingKey(key.clone())),
+            LPStakingQueryMsg::Delegation { address, key, .. } => {
+                (address, ViewingKey(key.clone()))
+            }
+            LPStakingQueryMsg::Lock { address, key } => (address, ViewingKey(key.clone())),
+            LPStakingQueryMsg::ReferralRewards { address, key } => {
+                (address, ViewingKey(key.clone()))
+            }
+            LPStakingQueryMsg::Vesting { address, key, .. } => (address, ViewingKey(key.clone())),
+            LPStakingQueryMsg::StakerBalance { contract, key, .. } => {
+                (contract, ViewingKey(key.clone()))
+            }
+            LPStakingQueryMsg::StakerDelegation { contract, key, .. } => {
+                (contract, ViewingKey(key.clone()))
+            }
             _ => panic!("This should never happen"),
         }
     }
 }
 
+// The authenticated queries, on behalf of whoever signed the permit
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum LPStakingQueryWithPermit {
+    Rewards { height: u64 },
+    Balance {},
+    BalanceAt { height: u64 },
+    Delegation { height: Option<u64> },
+    Lock {},
+    ReferralRewards {},
+    Vesting { height: u64 },
+}
+
 #[derive(Serialize, Deserialize, JsonSchema, Debug)]
 #[serde(rename_all = "snake_case")]
 pub enum LPStakingQueryAnswer {
@@ -133,6 +363,7 @@ pub enum LPStakingQueryAnswer {
     },
     Rewards {
         rewards: Uint128,
+        extra_rewards: Vec<RewardAmount>, // Tokens added with `AddRewardToken`
     },
     Balance {
         amount: Uint128,
@@ -143,15 +374,110 @@ pub enum LPStakingQueryAnswer {
     RewardToken {
         token: SecretContract,
     },
+    RewardTokens {
+        tokens: Vec<SecretContract>,
+    },
     IncentivizedToken {
         token: SecretContract,
     },
+    TotalLocked {
+        amount: Uint128,
+    },
+    Subscribers {
+        contracts: Vec<SecretContract>,
+    },
+    Delegation {
+        delegate: Option<HumanAddr>,
+        delegated_power: Uint128, // Total stake of everyone who delegated to this address
+    },
+    LockTiers {
+        tiers: Vec<LockTier>,
+    },
+    PoolStats {
+        total_locked: Uint128,
+        boosted_supply: Uint128, // Rewards are shared by it, see `LockTier`
+        acc_reward_per_share: Uint128,
+        residue: Uint128, // Rewards that were allocated while nothing was staked
+        stakers: u32,
+        // Estimated from the master's schedule and this pool's weight. The APR is in reward tokens
+        // per incentivized token, so it's only a percentage as is when both are the same token
+        yearly_rewards: Uint128,
+        apr_bps: Uint128,
+    },
+    Lock {
+        unlock_block: u64, // 0 if the balance was never locked
+        boost: u64,        // In percent, 100 means no boost
+    },
+    TrustedContracts {
+        contracts: Vec<HumanAddr>,
+    },
+    Deadline {
+        deadline: Option<u64>,
+        claimable_from: Option<u64>, // When the admin can sweep what's left of the rewards
+    },
+    MigrationTarget {
+        new_pool: Option<SecretContract>,
+    },
+    RedeemFee {
+        fee: Option<RedeemFee>,
+    },
+    FeeExempt {
+        contracts: Vec<HumanAddr>,
+    },
+    ReferralRate {
+        rate_bps: u64,
+    },
+    ReferralRewards {
+        amount: Uint128, // Not claimed yet
+    },
+    VestingConfig {
+        vesting: Option<VestingConfig>,
+    },
+    Vesting {
+        vested: Uint128, // Can be claimed without a penalty
+        locked: Uint128,
+    },
 
     QueryError {
         msg: String,
     },
 }
 
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+pub struct RewardAmount {
+    pub token: SecretContract,
+    pub amount: Uint128,
+}
+
+/// Locking a balance for `period` blocks weighs it by `boost` percent when sharing rewards
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+pub struct LockTier {
+    pub period: u64,
+    pub boost: u64,
+}
+
+/// Redeeming right after a deposit costs `rate_bps` of the amount, decreasing linearly to nothing
+/// over `decay_period` blocks. The fee is sent to `treasury`. Deposits on behalf of a staker and
+/// stake transferred to them move their clock forward in proportion to the amount. Contracts that
+/// the admin exempts are never charged. The SEFI vault must be one of them, since it redeems on
+/// behalf of its own users, who would otherwise pay the fee on every withdrawal
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+pub struct RedeemFee {
+    pub rate_bps: u64,
+    pub decay_period: u64,
+    pub treasury: SecretContract,
+}
+
+/// Harvested rewards, referral rewards included, unlock linearly over `period` blocks. They
+/// always vest for the staker, even when claimed to another recipient. A new harvest pushes the
+/// end of what's still locked back in proportion to its size, rather than restarting it. Claiming
+/// before the end forfeits `penalty_bps` of what's still locked, which is shared by the stakers
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+pub struct VestingConfig {
+    pub period: u64,
+    pub penalty_bps: u64,
+}
+
 #[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
 #[serde(rename_all = "snake_case")]
 pub enum LPStakingResponseStatus {
@@ -0,0 +1,183 @@
+use cosmwasm_std::{StdError, StdResult};
+
+/// Which way a division that doesn't come out even is rounded
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Rounding {
+    Down,
+    Up,
+}
+
+pub fn add(a: u128, b: u128) -> StdResult<u128> {
+    a.checked_add(b)
+        .ok_or_else(|| StdError::generic_err(format!("overflow: {} + {}", a, b)))
+}
+
+pub fn sub(a: u128, b: u128) -> StdResult<u128> {
+    a.checked_sub(b)
+        .ok_or_else(|| StdError::generic_err(format!("underflow: {} - {}", a, b)))
+}
+
+pub fn mul(a: u128, b: u128) -> StdResult<u128> {
+    a.checked_mul(b)
+        .ok_or_else(|| StdError::generic_err(format!("overflow: {} * {}", a, b)))
+}
+
+pub fn div(a: u128, b: u128, rounding: Rounding) -> StdResult<u128> {
+    if b == 0 {
+        return Err(StdError::generic_err(format!(
+            "division by zero: {} / 0",
+            a
+        )));
+    }
+
+    let quotient = a / b;
+    match rounding {
+        Rounding::Up if a % b != 0 => Ok(quotient + 1),
+        _ => Ok(quotient),
+    }
+}
+
+/// `a * b / c`, which is how every fixed-point amount in the project is scaled. `a * b` is kept
+/// in 256 bits, so only a result that doesn't fit in `u128` overflows
+pub fn mul_div(a: u128, b: u128, c: u128, rounding: Rounding) -> StdResult<u128> {
+    let (high, low) = wide_mul(a, b);
+    if high == 0 {
+        return div(low, c, rounding);
+    }
+    if c == 0 {
+        return Err(StdError::generic_err(format!(
+            "division by zero: {} * {} / 0",
+            a, b
+        )));
+    }
+    // The quotient only fits in 128 bits if the high half is smaller than the divisor
+    if high >= c {
+        return Err(StdError::generic_err(format!(
+            "overflow: {} * {} / {}",
+            a, b, c
+        )));
+    }
+
+    // Long division, one bit of the low half at a time. The remainder always stays below `c`,
+    // but shifting it can carry out of 128 bits, in which case it's certainly bigger than `c`
+    let mut quotient: u128 = 0;
+    let mut remainder = high;
+    for i in (0..128).rev() {
+        let carry = remainder >> 127;
+        remainder = (remainder << 1) | ((low >> i) & 1);
+        quotient <<= 1;
+        if carry == 1 || remainder >= c {
+            remainder = remainder.wrapping_sub(c);
+            quotient |= 1;
+        }
+    }
+
+    match rounding {
+        Rounding::Up if remainder != 0 => add(quotient, 1),
+        _ => Ok(quotient),
+    }
+}
+
+/// The full 256 bit product of `a` and `b`, as its high and low halves
+fn wide_mul(a: u128, b: u128) -> (u128, u128) {
+    const LOW_MASK: u128 = u64::MAX as u128;
+
+    let (a_high, a_low) = (a >> 64, a & LOW_MASK);
+    let (b_high, b_low) = (b >> 64, b & LOW_MASK);
+
+    let low_low = a_low * b_low;
+    let low_high = a_low * b_high;
+    let high_low = a_high * b_low;
+    let high_high = a_high * b_high;
+
+    // At most three 64 bit values, so this can't overflow
+    let middle = (low_low >> 64) + (low_high & LOW_MASK) + (high_low & LOW_MASK);
+    let low = (low_low & LOW_MASK) | (middle << 64);
+    let high = high_high + (low_high >> 64) + (high_low >> 64) + (middle >> 64);
+
+    (high, low)
+}
+
+/// Narrows a result back to the `u64` that block counts and weights are stored as
+pub fn to_u64(a: u128) -> StdResult<u64> {
+    if a > u64::MAX as u128 {
+        return Err(StdError::generic_err(format!(
+            "overflow: {} exceeds u64",
+            a
+        )));
+    }
+
+    Ok(a as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checked_ops() {
+        assert_eq!(add(1, 2).unwrap(), 3);
+        assert!(add(u128::MAX, 1).is_err());
+        assert_eq!(sub(3, 2).unwrap(), 1);
+        assert!(sub(2, 3).is_err());
+        assert_eq!(mul(3, 4).unwrap(), 12);
+        assert!(mul(u128::MAX, 2).is_err());
+        assert!(div(1, 0, Rounding::Down).is_err());
+        assert_eq!(to_u64(u64::MAX as u128).unwrap(), u64::MAX);
+        assert!(to_u64(u64::MAX as u128 + 1).is_err());
+    }
+
+    #[test]
+    fn test_rounding() {
+        assert_eq!(div(7, 2, Rounding::Down).unwrap(), 3);
+        assert_eq!(div(7, 2, Rounding::Up).unwrap(), 4);
+        assert_eq!(div(8, 2, Rounding::Up).unwrap(), 4);
+        assert_eq!(mul_div(10, 3, 4, Rounding::Down).unwrap(), 7);
+        assert_eq!(mul_div(10, 3, 4, Rounding::Up).unwrap(), 8);
+        assert!(mul_div(1, 1, 0, Rounding::Up).is_err());
+    }
+
+    #[test]
+    fn test_wide_mul_div() {
+        // The product overflows 128 bits, but the result doesn't
+        assert_eq!(
+            mul_div(u128::MAX, u128::MAX, u128::MAX, Rounding::Down).unwrap(),
+            u128::MAX
+        );
+        assert_eq!(
+            mul_div(u128::MAX, 4, 8, Rounding::Down).unwrap(),
+            u128::MAX / 2
+        );
+        assert_eq!(
+            mul_div(u128::MAX, 4, 8, Rounding::Up).unwrap(),
+            u128::MAX / 2 + 1
+        );
+
+        // A balance scaled by the biggest reward scale, then unscaled
+        let scale = 10u128.pow(24);
+        let amount = 10u128.pow(30) + 7;
+        assert_eq!(
+            mul_div(amount, scale, scale, Rounding::Down).unwrap(),
+            amount
+        );
+        assert_eq!(
+            mul_div(amount, 3 * scale, 2 * scale, Rounding::Down).unwrap(),
+            amount * 3 / 2
+        );
+        assert_eq!(
+            mul_div(amount, 3 * scale, 2 * scale, Rounding::Up).unwrap(),
+            amount * 3 / 2 + 1
+        );
+
+        // Divisors above 2 ^ 127 carry out of the remainder
+        let big = u128::MAX - 1;
+        assert_eq!(mul_div(big, 3, big, Rounding::Down).unwrap(), 3);
+        assert_eq!(mul_div(u128::MAX, 3, big, Rounding::Down).unwrap(), 3);
+        assert_eq!(mul_div(u128::MAX, 3, big, Rounding::Up).unwrap(), 4);
+
+        assert!(mul_div(u128::MAX, 2, 1, Rounding::Down).is_err());
+        assert!(mul_div(u128::MAX, u128::MAX, u128::MAX - 1, Rounding::Down).is_err());
+        assert!(mul_div(u128::MAX, u128::MAX, u128::MAX, Rounding::Up).is_ok());
+        assert!(mul_div(u128::MAX, 2, 0, Rounding::Down).is_err());
+    }
+}
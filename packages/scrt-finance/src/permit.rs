@@ -0,0 +1,257 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use cosmwasm_std::{
+    to_binary, Api, Binary, CanonicalAddr, HumanAddr, ReadonlyStorage, StdError, StdResult,
+    Storage, Uint128,
+};
+use cosmwasm_storage::{PrefixedStorage, ReadonlyPrefixedStorage};
+use ripemd160::{Digest, Ripemd160};
+use secret_toolkit::crypto::sha_256;
+
+pub const PREFIX_REVOKED_PERMITS: &[u8] = b"revokedpermits";
+pub const PERMIT_MSG_TYPE: &str = "query_permit";
+
+/// A query permit is signed off-chain by the querier's account key, and is accepted in place of
+/// a viewing key by every contract it names
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub struct Permit {
+    pub params: PermitParams,
+    pub signature: PermitSignature,
+}
+
+impl Permit {
+    pub fn check_permission(&self, permission: &Permission) -> bool {
+        self.params.permissions.contains(permission)
+            || self.params.permissions.contains(&Permission::Owner)
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub struct PermitParams {
+    pub allowed_tokens: Vec<HumanAddr>,
+    pub permit_name: String,
+    pub chain_id: String,
+    pub permissions: Vec<Permission>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub struct PermitSignature {
+    pub pub_key: PubKey,
+    pub signature: Binary,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub struct PubKey {
+    /// Ignored, but must be "tendermint/PubKeySecp256k1" to be accepted by wallets
+    #[serde(rename = "type")]
+    pub r#type: String,
+    /// Compressed secp256k1 public key
+    pub value: Binary,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Permission {
+    Allowance,
+    Balance,
+    History,
+    /// Grants every other permission
+    Owner,
+}
+
+/// Verifies that `permit` was signed for `contract` and wasn't revoked, and returns the address
+/// of the account that signed it
+pub fn validate<S: ReadonlyStorage, A: Api>(
+    storage: &S,
+    api: &A,
+    permit: &Permit,
+    contract: &HumanAddr,
+) -> StdResult<HumanAddr> {
+    if !permit.params.allowed_tokens.contains(contract) {
+        return Err(StdError::generic_err(format!(
+            "permit doesn't apply to contract {}, allowed contracts: {:?}",
+            contract, permit.params.allowed_tokens
+        )));
+    }
+
+    let pubkey = permit.signature.pub_key.value.as_slice();
+    let account = api.human_address(&pubkey_to_account(pubkey))?;
+
+    if is_permit_revoked(storage, &account, &permit.params.permit_name) {
+        return Err(StdError::generic_err(format!(
+            "permit {:?} was revoked by account {}",
+            permit.params.permit_name, account
+        )));
+    }
+
+    verify_signature(&permit.params, &permit.signature)?;
+
+    Ok(account)
+}
+
+pub fn verify_signature(params: &PermitParams, signature: &PermitSignature) -> StdResult<()> {
+    let sign_bytes = to_binary(&SignDoc::from_params(params))?;
+    let message = secp256k1::Message::parse(&sha_256(sign_bytes.as_slice()));
+    let public_key = secp256k1::PublicKey::parse_slice(
+        signature.pub_key.value.as_slice(),
+        Some(secp256k1::PublicKeyFormat::Compressed),
+    )
+    .map_err(|_| StdError::generic_err("malformed permit public key"))?;
+    let signature = secp256k1::Signature::parse_slice(signature.signature.as_slice())
+        .map_err(|_| StdError::generic_err("malformed permit signature"))?;
+
+    if !secp256k1::verify(&message, &signature, &public_key) {
+        return Err(StdError::unauthorized());
+    }
+
+    Ok(())
+}
+
+pub fn revoke_permit<S: Storage>(storage: &mut S, account: &HumanAddr, permit_name: &str) {
+    let mut revoked_store =
+        PrefixedStorage::multilevel(&[PREFIX_REVOKED_PERMITS, account.0.as_bytes()], storage);
+    revoked_store.set(permit_name.as_bytes(), &[1]);
+}
+
+pub fn is_permit_revoked<S: ReadonlyStorage>(
+    storage: &S,
+    account: &HumanAddr,
+    permit_name: &str,
+) -> bool {
+    let revoked_store = ReadonlyPrefixedStorage::multilevel(
+        &[PREFIX_REVOKED_PERMITS, account.0.as_bytes()],
+        storage,
+    );
+    revoked_store.get(permit_name.as_bytes()).is_some()
+}
+
+/// Cosmos account addresses are RIPEMD160(SHA256(compressed public key))
+pub fn pubkey_to_account(pubkey: &[u8]) -> CanonicalAddr {
+    let mut hasher = Ripemd160::new();
+    hasher.update(sha_256(pubkey));
+    CanonicalAddr(Binary(hasher.finalize().to_vec()))
+}
+
+// Wallets sign permits as an amino-JSON transaction that can never be broadcast. Fields are
+// declared in alphabetical order, because that's how the signed document is canonicalized
+
+#[derive(Serialize)]
+struct SignDoc {
+    account_number: Uint128,
+    chain_id: String,
+    fee: Fee,
+    memo: String,
+    msgs: Vec<PermitMsg>,
+    sequence: Uint128,
+}
+
+impl SignDoc {
+    fn from_params(params: &PermitParams) -> Self {
+        Self {
+            account_number: Uint128::zero(),
+            chain_id: params.chain_id.clone(),
+            fee: Fee {
+                amount: vec![Coin {
+                    amount: Uint128::zero(),
+                    denom: "uscrt".to_string(),
+                }],
+                gas: Uint128(1),
+            },
+            memo: String::new(),
+            msgs: vec![PermitMsg {
+                r#type: PERMIT_MSG_TYPE.to_string(),
+                value: PermitContent {
+                    allowed_tokens: params.allowed_tokens.clone(),
+                    permissions: params.permissions.clone(),
+                    permit_name: params.permit_name.clone(),
+                },
+            }],
+            sequence: Uint128::zero(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Fee {
+    amount: Vec<Coin>,
+    gas: Uint128,
+}
+
+#[derive(Serialize)]
+struct Coin {
+    amount: Uint128,
+    denom: String,
+}
+
+#[derive(Serialize)]
+struct PermitMsg {
+    #[serde(rename = "type")]
+    r#type: String,
+    value: PermitContent,
+}
+
+#[derive(Serialize)]
+struct PermitContent {
+    allowed_tokens: Vec<HumanAddr>,
+    permissions: Vec<Permission>,
+    permit_name: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cosmwasm_std::testing::MockStorage;
+
+    fn signed_params(params: &PermitParams, secret: &secp256k1::SecretKey) -> PermitSignature {
+        let sign_bytes = to_binary(&SignDoc::from_params(params)).unwrap();
+        let message = secp256k1::Message::parse(&sha_256(sign_bytes.as_slice()));
+        let (signature, _) = secp256k1::sign(&message, secret);
+        let public_key = secp256k1::PublicKey::from_secret_key(secret);
+
+        PermitSignature {
+            pub_key: PubKey {
+                r#type: "tendermint/PubKeySecp256k1".to_string(),
+                value: Binary(public_key.serialize_compressed().to_vec()),
+            },
+            signature: Binary(signature.serialize().to_vec()),
+        }
+    }
+
+    #[test]
+    fn test_verify_signature() {
+        let secret = secp256k1::SecretKey::parse(&[7u8; 32]).unwrap();
+        let mut params = PermitParams {
+            allowed_tokens: vec![HumanAddr("staking-pool".to_string())],
+            permit_name: "wallet".to_string(),
+            chain_id: "secret-2".to_string(),
+            permissions: vec![Permission::Balance],
+        };
+        let signature = signed_params(&params, &secret);
+        assert!(verify_signature(&params, &signature).is_ok());
+
+        // Granting more than what was signed must fail
+        params.permissions.push(Permission::Owner);
+        assert!(verify_signature(&params, &signature).is_err());
+    }
+
+    #[test]
+    fn test_revoke_permit() {
+        let mut storage = MockStorage::new();
+        let account = HumanAddr("alice".to_string());
+
+        assert!(!is_permit_revoked(&storage, &account, "wallet"));
+        revoke_permit(&mut storage, &account, "wallet");
+        assert!(is_permit_revoked(&storage, &account, "wallet"));
+        assert!(!is_permit_revoked(&storage, &account, "other"));
+        assert!(!is_permit_revoked(
+            &storage,
+            &HumanAddr("bob".to_string()),
+            "wallet"
+        ));
+    }
+}
@@ -0,0 +1,87 @@
+use crate::types::SecretContract;
+use cosmwasm_std::{Binary, HumanAddr, Uint128};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PollInitMsg {
+    pub metadata: PollMetadata,
+    pub config: PollConfig,
+    pub choices: Vec<String>,
+    pub staking_pool: SecretContract,
+    pub factory: Option<SecretContract>, // If set, the poll registers itself in the factory on init
+    pub execution: Option<ProposalExecution>,
+    // The poll's own viewing key with the staking pool, which must also trust the poll. It lets
+    // the poll read its voters' balances without holding on to their keys
+    pub viewing_key: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PollConfig {
+    pub duration: Option<u64>, // In blocks. TODO: Might want to change this later
+    pub quorum: Option<u8>,    // X/100% (percentage)
+    pub min_threshold: Option<u8>, // X/100% (percentage)
+    pub private_ballot: Option<bool>, // If true, the tally and voters are hidden until finalization
+    pub voting_mode: Option<VotingMode>, // Defaults to a single choice
+    pub snapshot: Option<bool>, // If true, voting power is measured at the block the poll was created
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum VotingMode {
+    SingleChoice,
+    Approval,     // Any number of choices, each one gets the voter's full weight
+    RankedChoice, // Choices in order of preference, tallied with instant-runoff
+    Quadratic,    // A single choice, weighted by the square root of the voter's power
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PollMetadata {
+    pub title: String,
+    pub description: String,
+    pub author: HumanAddr,
+}
+
+/// A message that is sent once the poll has finalized with `winning_choice`, and `timelock` blocks
+/// have passed since. Polls created by a factory send it through the factory, so the factory can
+/// be the admin of the target contract
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ProposalExecution {
+    pub contract: SecretContract,
+    pub msg: Binary,
+    pub winning_choice: u8,
+    pub timelock: u64, // In blocks
+}
+
+// Duplicating the relevant part of the poll's HandleMsg, because staking pools need to notify
+// polls without depending on the secret-poll contract
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PollHandleMsg {
+    UpdateVotingPower {
+        voter: HumanAddr,
+        old_power: Uint128,
+        new_power: Uint128,
+        delegate: Option<HumanAddr>, // The voter's global delegate, if any
+    },
+    UpdateDelegation {
+        delegator: HumanAddr,
+        voting_power: Uint128,
+        old_delegate: Option<HumanAddr>,
+        new_delegate: Option<HumanAddr>,
+    },
+}
+
+// Duplicating the poll callbacks of the factory's HandleMsg, for the same reason
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PollFactoryHandleMsg {
+    RegisterPoll {},
+    PollFinalized {
+        quorum_reached: bool,
+    },
+    ExecuteProposal {
+        contract: SecretContract,
+        msg: Binary,
+    },
+}
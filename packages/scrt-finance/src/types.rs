@@ -7,6 +7,7 @@ use serde::{Deserialize, Serialize};
 pub struct RewardPool {
     pub residue: u128,
     pub inc_token_supply: u128,
+    pub boosted_supply: u128, // Total stake with lock boosts applied. Rewards are shared by it
     pub acc_reward_per_share: u128,
 }
 